@@ -1,5 +1,9 @@
+use std::path::PathBuf;
+
 use clap::Parser;
 
+use crate::tools::precision::{Decimals, MAX_DECIMALS};
+
 #[derive(Parser, Debug)]
 #[command(name = env!("CARGO_PKG_NAME"))]
 #[command(version = env!("CARGO_PKG_VERSION"))]
@@ -12,4 +16,43 @@ pub struct CommandArguments {
         default_value = "Calculator MCP Server is running"
     )]
     pub startup_message: String,
-}
\ No newline at end of file
+
+    /// batch_validate 和 validate_csv 允许读取输入文件的目录，可以指定多个
+    #[arg(
+        long = "allow-dir",
+        value_name = "DIR",
+        help = "Directory that batch_validate and validate_csv may read input files from (repeatable)"
+    )]
+    pub allow_dir: Vec<PathBuf>,
+
+    /// 不启动服务器，验证 CSV 报表后退出；有失败的行时退出码为 1。文件须在 `--allow-dir` 指定的目录中，未指定时为当前目录
+    #[arg(
+        long = "validate-csv",
+        value_name = "FILE",
+        help = "Validate a CSV statement file and exit instead of starting the server; the file must be inside --allow-dir (default: current directory)"
+    )]
+    pub validate_csv: Option<PathBuf>,
+
+    /// `--validate-csv` 的小数位数：0-15 的整数或 auto，默认为 auto
+    #[arg(
+        long,
+        value_name = "N|auto",
+        value_parser = parse_decimals,
+        requires = "validate_csv",
+        help = "Decimal places for --validate-csv: 0-15 or auto (default: auto)"
+    )]
+    pub decimals: Option<Decimals>,
+
+    /// `--validate-csv` 的数字格式：en-US、de-DE、fr-FR、de-CH、zh-CN、en-IN 或 auto，默认为 auto
+    #[arg(
+        long = "number-format",
+        value_name = "FORMAT",
+        requires = "validate_csv",
+        help = "Number format for --validate-csv: en-US, de-DE, fr-FR, de-CH, zh-CN, en-IN or auto (default: auto)"
+    )]
+    pub number_format: Option<String>,
+}
+
+fn parse_decimals(value: &str) -> Result<Decimals, String> {
+    Decimals::parse(value).ok_or_else(|| format!("无效的小数位数 '{}'，支持 0-{} 的整数或 auto", value, MAX_DECIMALS))
+}
@@ -1,5 +1,7 @@
 use thiserror::Error;
 
+use crate::tools::calculator::CalcError;
+
 pub type ServiceResult<T> = Result<T, ServiceError>;
 
 #[derive(Error, Debug)]
@@ -10,18 +12,6 @@ pub enum ServiceError {
     #[error("无效表达式: {0}")]
     InvalidExpression(String),
     
-    #[error("除零错误")]
-    DivisionByZero,
-    
-    #[error("无效字符: {0}")]
-    InvalidCharacter(char),
-    
-    #[error("括号不匹配")]
-    MismatchedParens,
-    
-    #[error("表达式意外结束")]
-    UnexpectedEndOfExpression,
-    
     #[error("IO 错误: {0}")]
     Io(#[from] std::io::Error),
     
@@ -35,14 +25,15 @@ pub enum ServiceError {
     Generic(String),
 }
 
-impl From<crate::tools::calculator::CalcError> for ServiceError {
-    fn from(err: crate::tools::calculator::CalcError) -> Self {
-        match err {
-            crate::tools::calculator::CalcError::InvalidCharacter(c) => ServiceError::InvalidCharacter(c),
-            crate::tools::calculator::CalcError::MismatchedParens => ServiceError::MismatchedParens,
-            crate::tools::calculator::CalcError::InvalidExpression => ServiceError::InvalidExpression("无效表达式".to_string()),
-            crate::tools::calculator::CalcError::DivisionByZero => ServiceError::DivisionByZero,
-            crate::tools::calculator::CalcError::UnexpectedEndOfExpression => ServiceError::UnexpectedEndOfExpression,
-        }
+impl ServiceError {
+    /// 把计算错误转换为服务错误，错误信息中附带标注出错位置的表达式片段
+    pub fn from_calc(expr: &str, err: CalcError) -> Self {
+        ServiceError::CalculationError(err.render(expr))
     }
-}
\ No newline at end of file
+}
+
+impl From<CalcError> for ServiceError {
+    fn from(err: CalcError) -> Self {
+        ServiceError::CalculationError(err.to_string())
+    }
+}
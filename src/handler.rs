@@ -1,4 +1,5 @@
 use std::cmp::Ordering;
+use std::sync::{Mutex, PoisonError};
 
 use crate::cli::CommandArguments;
 use crate::error::ServiceResult;
@@ -7,20 +8,59 @@ use async_trait::async_trait;
 use rust_mcp_sdk::mcp_server::ServerHandler;
 use rust_mcp_sdk::schema::{
     schema_utils::CallToolError, CallToolRequest, CallToolResult, InitializeRequest,
-    InitializeResult, ListToolsRequest, ListToolsResult, RpcError,
+    InitializeResult, ListToolsRequest, ListToolsResult, RootsListChangedNotification, RpcError,
 };
 use rust_mcp_sdk::McpServer;
 
 pub struct CalculatorHandler {
     startup_message: String,
+    file_access: FileAccess,
+    roots: Mutex<RootsCache>,
+}
+
+/// 客户端 roots 的缓存，收到 roots/list_changed 通知后失效
+#[derive(Default)]
+struct RootsCache {
+    /// 每次失效时加一，失效前发起的查询不再写回
+    generation: u64,
+    file_access: Option<FileAccess>,
 }
 
 impl CalculatorHandler {
     pub fn new(args: &CommandArguments) -> ServiceResult<Self> {
         Ok(Self {
             startup_message: args.startup_message.clone(),
+            file_access: FileAccess::new(&args.allow_dir)?,
+            roots: Mutex::default(),
         })
     }
+
+    /// 允许读取的目录：启动参数指定的目录加上客户端提供的 roots
+    ///
+    /// roots 只在第一次需要时和列表变化后向客户端查询，查询失败时不缓存。
+    async fn file_access(&self, runtime: &dyn McpServer) -> FileAccess {
+        if runtime.client_supports_root_list() != Some(true) {
+            return self.file_access.clone();
+        }
+        let generation = {
+            let cache = self.roots.lock().unwrap_or_else(PoisonError::into_inner);
+            if let Some(file_access) = &cache.file_access {
+                return file_access.clone();
+            }
+            cache.generation
+        };
+        match runtime.list_roots(None).await {
+            Ok(result) => {
+                let file_access = self.file_access.with_roots(result.roots.iter().map(|root| root.uri.as_str()));
+                let mut cache = self.roots.lock().unwrap_or_else(PoisonError::into_inner);
+                if cache.generation == generation {
+                    cache.file_access = Some(file_access.clone());
+                }
+                file_access
+            }
+            Err(_) => self.file_access.clone(),
+        }
+    }
 }
 
 #[async_trait]
@@ -31,13 +71,30 @@ impl ServerHandler for CalculatorHandler {
 
     async fn on_initialized(&self, _: &dyn McpServer) {}
 
+    async fn handle_roots_list_changed_notification(
+        &self,
+        _: RootsListChangedNotification,
+        _: &dyn McpServer,
+    ) -> std::result::Result<(), RpcError> {
+        let mut cache = self.roots.lock().unwrap_or_else(PoisonError::into_inner);
+        cache.generation += 1;
+        cache.file_access = None;
+        Ok(())
+    }
+
     async fn handle_list_tools_request(
         &self,
         _: ListToolsRequest,
         _: &dyn McpServer,
     ) -> std::result::Result<ListToolsResult, RpcError> {
         Ok(ListToolsResult {
-            tools: CalculatorTools::tools(),
+            tools: CalculatorTools::tools()
+                .into_iter()
+                .map(|mut tool| {
+                    tool.output_schema = output_schema(&tool.name);
+                    tool
+                })
+                .collect(),
             meta: None,
             next_cursor: None,
         })
@@ -66,7 +123,7 @@ impl ServerHandler for CalculatorHandler {
     async fn handle_call_tool_request(
         &self,
         request: CallToolRequest,
-        _: &dyn McpServer,
+        runtime: &dyn McpServer,
     ) -> std::result::Result<CallToolResult, CallToolError> {
         let tool_params: CalculatorTools =
             CalculatorTools::try_from(request.params).map_err(CallToolError::new)?;
@@ -79,7 +136,31 @@ impl ServerHandler for CalculatorHandler {
                 ValidateTool::run_tool(params, &()).await
             }
             CalculatorTools::BatchValidateTool(params) => {
-                BatchValidateTool::run_tool(params, &()).await
+                BatchValidateTool::run_tool(params, &self.file_access(runtime).await).await
+            }
+            CalculatorTools::ParseTool(params) => {
+                ParseTool::run_tool(params, &()).await
+            }
+            CalculatorTools::CalculateScriptTool(params) => {
+                CalculateScriptTool::run_tool(params, &()).await
+            }
+            CalculatorTools::FindRoundingConventionTool(params) => {
+                FindRoundingConventionTool::run_tool(params, &()).await
+            }
+            CalculatorTools::ValidateStatementTool(params) => {
+                ValidateStatementTool::run_tool(params, &()).await
+            }
+            CalculatorTools::CrossfootTableTool(params) => {
+                CrossfootTableTool::run_tool(params, &()).await
+            }
+            CalculatorTools::ValidateCsvTool(params) => {
+                ValidateCsvTool::run_tool(params, &self.file_access(runtime).await).await
+            }
+            CalculatorTools::CalculateGridTool(params) => {
+                CalculateGridTool::run_tool(params, &()).await
+            }
+            CalculatorTools::RecalculateXlsxTool(params) => {
+                RecalculateXlsxTool::run_tool(params, &self.file_access(runtime).await).await
             }
         }
     }
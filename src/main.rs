@@ -4,10 +4,44 @@ mod handler;
 mod server;
 mod tools;
 
+use std::path::Path;
+
 use clap::Parser;
-use error::ServiceResult;
+use cli::CommandArguments;
+use error::{ServiceError, ServiceResult};
 
 #[tokio::main]
 async fn main() -> ServiceResult<()> {
-    server::start_server(cli::CommandArguments::parse()).await
+    let args = CommandArguments::parse();
+    match &args.validate_csv {
+        Some(path) => validate_csv(path, &args),
+        None => server::start_server(args).await,
+    }
+}
+
+/// 命令行模式：按 validate_csv 工具的设置验证 CSV 报表，输出结果；有失败的行时退出码为 1
+///
+/// 与服务器相同，只读取 `--allow-dir` 指定的目录中的文件；未指定时为当前目录。
+/// 相对路径按当前目录解析，与一般命令行工具一致。
+fn validate_csv(path: &Path, args: &CommandArguments) -> ServiceResult<()> {
+    let current_dir = std::env::current_dir()?;
+    let dirs = match args.allow_dir.is_empty() {
+        true => vec![current_dir.clone()],
+        false => args.allow_dir.clone(),
+    };
+    let path = current_dir.join(path);
+    let params = tools::ValidateCsvTool {
+        path: path.display().to_string(),
+        decimals: args.decimals,
+        number_format: args.number_format.clone(),
+        ..Default::default()
+    };
+    let config = params.batch_config().map_err(|e| ServiceError::Generic(e.to_string()))?;
+    let file = tools::FileAccess::new(&dirs)?.open(&params.path).map_err(ServiceError::InvalidExpression)?;
+    let (text, output) = tools::validate_csv(&config, file).map_err(ServiceError::InvalidExpression)?;
+    println!("{}", text);
+    if !output.all_passed {
+        std::process::exit(1);
+    }
+    Ok(())
 }
@@ -0,0 +1,270 @@
+//! # 断言
+//!
+//! 并非所有检查都是等式：毛利率应介于 20% 和 40% 之间，余额应 `>= 0`，差异率应 `< 5%`。
+//! 预期值可以写成以下断言之一：
+//!
+//! | 写法 | 含义 |
+//! |---|---|
+//! | `x`、`= x`、`== x` | 等于 x（按容差比较） |
+//! | `!= x`、`≠ x` | 不等于 x（超出容差） |
+//! | `> x`、`>= x`、`≥ x` | 大于、大于等于 x |
+//! | `< x`、`<= x`、`≤ x` | 小于、小于等于 x |
+//! | `between a and b` | 介于 a 和 b 之间（含两端） |
+//!
+//! `x`、`a`、`b` 可以是数字、百分数或表达式。等于和不等于的比较值按 `evaluate_expected` 的规则计算；
+//! 不等式的边界不做舍入，`20%` 就是 0.2。
+
+use std::fmt;
+
+use crate::tools::calculator::{evaluate_expected_in, evaluate_traced, CalcError, CalcOptions, Variables};
+use crate::tools::parser::parse;
+
+/// 比较浮点数时始终容忍的误差
+const EPSILON: f64 = 1e-9;
+
+/// 比较运算符，较长的写法在前
+const OPERATORS: [(&str, Op); 10] = [
+    (">=", Op::GreaterOrEqual),
+    ("<=", Op::LessOrEqual),
+    ("!=", Op::NotEqual),
+    ("==", Op::Equal),
+    ("≥", Op::GreaterOrEqual),
+    ("≤", Op::LessOrEqual),
+    ("≠", Op::NotEqual),
+    (">", Op::Greater),
+    ("<", Op::Less),
+    ("=", Op::Equal),
+];
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+enum Op {
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+}
+
+/// 对计算结果的断言
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Assertion {
+    Equal(f64),
+    NotEqual(f64),
+    Greater(f64),
+    GreaterOrEqual(f64),
+    Less(f64),
+    LessOrEqual(f64),
+    /// 闭区间 `[下限, 上限]`
+    Between(f64, f64),
+}
+
+impl Assertion {
+    /// 解析断言，错误位置相对于 `text`
+    pub fn parse(text: &str, options: &CalcOptions) -> Result<Self, CalcError> {
+        Self::parse_in(text, options, &Variables::new())
+    }
+
+    /// 解析可以引用变量的断言
+    pub fn parse_in(text: &str, options: &CalcOptions, variables: &Variables) -> Result<Self, CalcError> {
+        let (op, operands) = split_operator(text);
+        let evaluate_at = |(start, operand): (usize, &str), bound: bool| {
+            let offset = text[..start].chars().count();
+            let value = if bound { evaluate_bound(operand, options, variables) } else { evaluate_expected_in(operand, options, variables) };
+            value.map_err(|e| e.shifted(offset))
+        };
+
+        match (op, operands.as_slice()) {
+            (None, &[lower, upper]) => {
+                let (lower, upper) = (evaluate_at(lower, true)?, evaluate_at(upper, true)?);
+                Ok(Assertion::Between(lower.min(upper), lower.max(upper)))
+            }
+            (Some(op), &[operand]) => Ok(match op {
+                Op::Equal => Assertion::Equal(evaluate_at(operand, false)?),
+                Op::NotEqual => Assertion::NotEqual(evaluate_at(operand, false)?),
+                Op::Greater => Assertion::Greater(evaluate_at(operand, true)?),
+                Op::GreaterOrEqual => Assertion::GreaterOrEqual(evaluate_at(operand, true)?),
+                Op::Less => Assertion::Less(evaluate_at(operand, true)?),
+                Op::LessOrEqual => Assertion::LessOrEqual(evaluate_at(operand, true)?),
+            }),
+            _ => unreachable!("split_operator 对 between 返回两个操作数，其余返回一个"),
+        }
+    }
+
+    /// 检查计算结果是否满足断言，等于和不等于按 `options.tolerance` 比较
+    pub fn check(&self, actual: f64, options: &CalcOptions) -> bool {
+        let equal = |expected: f64| options.tolerance.check(actual, expected, options.decimals).passed;
+        match *self {
+            Assertion::Equal(expected) => equal(expected),
+            Assertion::NotEqual(expected) => !equal(expected),
+            Assertion::Greater(bound) => actual > bound + EPSILON,
+            Assertion::GreaterOrEqual(bound) => actual >= bound - EPSILON,
+            Assertion::Less(bound) => actual < bound - EPSILON,
+            Assertion::LessOrEqual(bound) => actual <= bound + EPSILON,
+            Assertion::Between(lower, upper) => actual >= lower - EPSILON && actual <= upper + EPSILON,
+        }
+    }
+}
+
+/// 断言中的操作数文本（去掉运算符和 `between … and`），用于推断小数位数
+pub fn operands(text: &str) -> Vec<&str> {
+    split_operator(text).1.into_iter().map(|(_, operand)| operand).collect()
+}
+
+/// 把等式或断言形式的算式拆分为算式和预期值；不含比较运算符时返回 `None`
+///
+/// 单独的 `=` 被去掉，其余运算符保留在预期值中，例如 `a >= 0` 拆分为 `("a", ">= 0")`。
+/// `[标记]` 和 `{引用}` 中的文本不参与查找。
+pub fn split_equation(equation: &str) -> Option<(&str, &str)> {
+    let visible = mask_brackets(equation);
+    let keyword = find_keyword(&visible.to_ascii_lowercase(), "between");
+    let operator = visible.find(['=', '<', '>', '!', '≥', '≤', '≠']);
+    let at = match (keyword, operator) {
+        (Some(k), Some(o)) => k.min(o),
+        (k, o) => k.or(o)?,
+    };
+
+    let (lhs, rhs) = equation.split_at(at);
+    let rhs = match rhs.strip_prefix('=') {
+        Some(rest) if !rest.starts_with('=') => rest,
+        _ => rhs,
+    };
+    Some((lhs.trim(), rhs.trim()))
+}
+
+/// 把 `[标记]` 和 `{引用}`（含括号）替换为等长的空格，字节位置不变
+fn mask_brackets(text: &str) -> String {
+    let mut masked = String::with_capacity(text.len());
+    let mut close = None;
+    for c in text.chars() {
+        match close {
+            None if c == '[' || c == '{' => close = Some(if c == '[' { ']' } else { '}' }),
+            None => {
+                masked.push(c);
+                continue;
+            }
+            Some(end) if c == end => close = None,
+            Some(_) => {}
+        }
+        masked.extend(std::iter::repeat_n(' ', c.len_utf8()));
+    }
+    masked
+}
+
+/// 拆分出运算符和操作数（字节位置, 文本）；`between a and b` 返回 `None` 和两个操作数，没有运算符时视为等于
+fn split_operator(text: &str) -> (Option<Op>, Vec<(usize, &str)>) {
+    let start = text.len() - text.trim_start().len();
+    let rest = &text[start..];
+
+    let lower = rest.to_ascii_lowercase();
+    if lower.starts_with("between") {
+        let body = start + "between".len();
+        if let Some(and) = find_keyword(&text[body..].to_ascii_lowercase(), "and") {
+            let upper = body + and + "and".len();
+            return (None, vec![(body, &text[body..body + and]), (upper, &text[upper..])]);
+        }
+    }
+
+    for (symbol, op) in OPERATORS {
+        if let Some(operand) = rest.strip_prefix(symbol) {
+            return (Some(op), vec![(start + symbol.len(), operand)]);
+        }
+    }
+    (Some(Op::Equal), vec![(0, text)])
+}
+
+/// 查找独立的单词（两侧不是字母、数字或 `_`），返回字节位置
+fn find_keyword(text: &str, keyword: &str) -> Option<usize> {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    text.match_indices(keyword).map(|(at, _)| at).find(|&at| {
+        !text[..at].chars().next_back().is_some_and(is_word) && !text[at + keyword.len()..].chars().next().is_some_and(is_word)
+    })
+}
+
+/// 不等式的边界：按原值计算，不预先舍入也不舍入结果
+fn evaluate_bound(text: &str, options: &CalcOptions, variables: &Variables) -> Result<f64, CalcError> {
+    let ast = parse(text, options)?;
+    evaluate_traced(&ast, &options.with_pre_round(false), variables, None)
+}
+
+impl fmt::Display for Assertion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Assertion::Equal(value) => write!(f, "= {}", value),
+            Assertion::NotEqual(value) => write!(f, "!= {}", value),
+            Assertion::Greater(value) => write!(f, "> {}", value),
+            Assertion::GreaterOrEqual(value) => write!(f, ">= {}", value),
+            Assertion::Less(value) => write!(f, "< {}", value),
+            Assertion::LessOrEqual(value) => write!(f, "<= {}", value),
+            Assertion::Between(lower, upper) => write!(f, "介于 {} 和 {} 之间", lower, upper),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::calculator::PercentRounding;
+    use crate::tools::diagnostic::{Expected, Span};
+    use crate::tools::number_format::NumberFormat;
+    use crate::tools::tolerance::Tolerance;
+
+    fn options() -> CalcOptions {
+        CalcOptions::new(0, PercentRounding::DivideBy100ThenRound).with_number_format(NumberFormat::EnUs)
+    }
+
+    #[test]
+    fn test_parse_assertions() {
+        let parse = |text: &str| Assertion::parse(text, &options()).unwrap();
+        assert_eq!(parse("1,000"), Assertion::Equal(1000.0));
+        assert_eq!(parse("== 500 + 500"), Assertion::Equal(1000.0));
+        assert_eq!(parse(">= 0"), Assertion::GreaterOrEqual(0.0));
+        assert_eq!(parse("≤ -1"), Assertion::LessOrEqual(-1.0));
+        assert_eq!(parse("!=0"), Assertion::NotEqual(0.0));
+        // 边界不舍入：decimals = 0 时 5% 仍是 0.05
+        assert_eq!(parse("< 5%"), Assertion::Less(0.05));
+        assert_eq!(parse("between 20% and 40%"), Assertion::Between(0.2, 0.4));
+        assert_eq!(parse("BETWEEN 40% AND 20%"), Assertion::Between(0.2, 0.4));
+    }
+
+    #[test]
+    fn test_parse_errors_point_into_assertion() {
+        assert_eq!(
+            Assertion::parse(">= 1 +", &options()),
+            Err(CalcError::UnexpectedEndOfExpression { pos: 6, expected: Expected::Operand })
+        );
+        assert_eq!(
+            Assertion::parse("between 1 and 2x", &options()),
+            Err(CalcError::InvalidExpression { span: Span::new(15, 16), expected: Expected::Operator })
+        );
+        assert!(Assertion::parse("between 1", &options()).is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        let options = options();
+        assert!(Assertion::GreaterOrEqual(0.0).check(0.0, &options));
+        assert!(!Assertion::Greater(0.0).check(0.0, &options));
+        assert!(Assertion::Between(0.2, 0.4).check(0.4, &options));
+        assert!(!Assertion::Between(0.2, 0.4).check(0.41, &options));
+        assert!(Assertion::NotEqual(100.0).check(101.0, &options));
+        assert!(!Assertion::NotEqual(100.0).check(101.0, &options.with_tolerance(Tolerance::Absolute(1.0))));
+    }
+
+    #[test]
+    fn test_split_equation() {
+        assert_eq!(split_equation("1,000 + 2,000 = 500 + 2,500"), Some(("1,000 + 2,000", "500 + 2,500")));
+        assert_eq!(split_equation("a - b >= 0"), Some(("a - b", ">= 0")));
+        assert_eq!(split_equation("1 == 1"), Some(("1", "== 1")));
+        assert_eq!(split_equation("(1 - 2) / 3 Between 1% and 5%"), Some(("(1 - 2) / 3", "Between 1% and 5%")));
+        assert_eq!(split_equation("1 + 2"), None);
+        // 多个等号时右边无法解析，由调用方报告错误
+        assert_eq!(split_equation("1 = 1 = 1"), Some(("1", "1 = 1")));
+        // 标记和引用中的比较符号、between 不是运算符
+        assert_eq!(split_equation("1,000[A<=B] + 2,000"), None);
+        assert_eq!(split_equation("1,000[A<=B] + 2,000 = 3,000"), Some(("1,000[A<=B] + 2,000", "3,000")));
+        assert_eq!(split_equation("{利润 ≠ 现金} >= {between 项}"), Some(("{利润 ≠ 现金}", ">= {between 项}")));
+        assert_eq!(operands("between 1 and 2.5"), vec![" 1 ", " 2.5"]);
+        assert_eq!(operands(">= 0.25"), vec![" 0.25"]);
+    }
+}
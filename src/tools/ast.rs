@@ -0,0 +1,316 @@
+//! # 语法树
+//!
+//! `parser::parse` 把表达式解析为 `Expr`。语法树只描述表达式的结构，不包含任何舍入：
+//! 数字字面量保留原始文本、所用的数字格式和位置，舍入由求值器按 `CalcOptions` 对每个节点施加。
+//!
+//! `Expr` 的 `Display` 实现输出规范形式：数字去掉千分位、运算符两侧各一个空格、只保留必要的括号，
+//! 操作数标记统一写成 `[标记]`。
+//!
+//! 脚本由若干 `Statement` 组成，语句中的表达式可以通过 `Expr::Variable` 引用此前赋值的变量。
+//! 单元格地址（如 `B2`）也是变量，区域（如 `B2:B10`）只作为函数的参数出现。
+
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::tools::cell::{CellRange, CellRef};
+use crate::tools::diagnostic::Span;
+use crate::tools::number_format::NumberFormat;
+
+/// 表达式节点
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Expr {
+    /// 数字字面量（可带百分号）
+    Number(NumberLiteral),
+    /// 一元运算，例如 `-5`、`-(1 + 2)`
+    Unary {
+        op: UnaryOp,
+        operand: Box<Expr>,
+        span: Span,
+    },
+    /// 二元运算
+    Binary {
+        op: BinaryOp,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+        span: Span,
+    },
+    /// 括号，`span` 包含两侧的括号
+    Group { inner: Box<Expr>, span: Span },
+    /// 变量引用，值来自脚本中此前的赋值，或批量验证中此前的行（`{标记}`）
+    Variable { name: String, span: Span },
+    /// 函数调用，例如 `SUM(B2:B10, C5)`
+    Call { function: Function, args: Vec<Expr>, span: Span },
+    /// 单元格区域，例如 `B2:B10`，值为区域中各单元格之和
+    Range { range: CellRange, span: Span },
+}
+
+/// 支持的函数
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Function {
+    /// 各参数之和，参数可以是区域
+    Sum,
+    /// `ROUND(数值, 位数)`，与电子表格相同：中间值远离零舍入，位数可以为负，小数部分截去
+    Round,
+}
+
+impl Function {
+    /// 支持的函数名，用于错误提示
+    pub const NAMES: &'static str = "SUM、ROUND";
+
+    /// 函数名不区分大小写
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "SUM" => Some(Function::Sum),
+            "ROUND" => Some(Function::Round),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Function::Sum => "SUM",
+            Function::Round => "ROUND",
+        }
+    }
+
+    /// 参数个数，不限时为 `None`
+    pub fn arity(&self) -> Option<usize> {
+        match self {
+            Function::Sum => None,
+            Function::Round => Some(2),
+        }
+    }
+}
+
+/// 表达式中的单元格引用
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CellReference<'a> {
+    /// 单个单元格，`name` 是表达式中的写法，例如 `$B$2`
+    Cell { name: &'a str, cell: CellRef },
+    /// 函数参数中的区域
+    Range(CellRange),
+}
+
+/// 脚本中的一条语句
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Statement {
+    /// 赋值 `name = expr`，`span` 覆盖整条语句
+    Assign { name: String, expr: Expr, span: Span },
+    /// 单独的表达式，通常是脚本的最后一行
+    Expr { expr: Expr },
+}
+
+/// 数字字面量
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NumberLiteral {
+    /// 原始文本，不含百分号，例如 `1,234.56`
+    pub text: String,
+    /// 按数字格式解析出的值，未经舍入，百分数也未除以 100
+    pub value: f64,
+    /// 是否带百分号
+    pub percent: bool,
+    /// 操作数标记，例如 `1,000[货币资金]` 或 `1,000 #cash` 中的 `货币资金`、`cash`；不影响计算结果
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// 解析所用的数字格式；自动识别时为推断出的格式，无法区分时为 `auto`
+    pub format: NumberFormat,
+    /// 字面量的位置，包含百分号，不含标记
+    pub span: Span,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnaryOp {
+    Negate,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+impl BinaryOp {
+    /// 运算符的优先级，数值越大越先结合
+    pub fn precedence(&self) -> u8 {
+        match self {
+            BinaryOp::Add | BinaryOp::Subtract => 1,
+            BinaryOp::Multiply | BinaryOp::Divide => 2,
+        }
+    }
+
+    pub fn symbol(&self) -> char {
+        match self {
+            BinaryOp::Add => '+',
+            BinaryOp::Subtract => '-',
+            BinaryOp::Multiply => '*',
+            BinaryOp::Divide => '/',
+        }
+    }
+}
+
+/// 一元负号的优先级，高于所有二元运算符
+const UNARY_PRECEDENCE: u8 = 3;
+
+impl Expr {
+    /// 节点在表达式中的位置
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Number(literal) => literal.span,
+            Expr::Unary { span, .. }
+            | Expr::Binary { span, .. }
+            | Expr::Group { span, .. }
+            | Expr::Variable { span, .. }
+            | Expr::Call { span, .. }
+            | Expr::Range { span, .. } => *span,
+        }
+    }
+
+    /// 按出现顺序列出所有数字字面量
+    pub fn literals(&self) -> Vec<&NumberLiteral> {
+        let mut literals = Vec::new();
+        self.collect_literals(&mut literals);
+        literals
+    }
+
+    fn collect_literals<'a>(&'a self, literals: &mut Vec<&'a NumberLiteral>) {
+        match self {
+            Expr::Number(literal) => literals.push(literal),
+            Expr::Unary { operand, .. } => operand.collect_literals(literals),
+            Expr::Binary { lhs, rhs, .. } => {
+                lhs.collect_literals(literals);
+                rhs.collect_literals(literals);
+            }
+            Expr::Group { inner, .. } => inner.collect_literals(literals),
+            Expr::Call { args, .. } => args.iter().for_each(|arg| arg.collect_literals(literals)),
+            Expr::Variable { .. } | Expr::Range { .. } => {}
+        }
+    }
+
+    /// 按出现顺序列出单元格引用，不是单元格地址的变量不包括在内
+    pub fn cell_references(&self) -> Vec<CellReference<'_>> {
+        let mut references = Vec::new();
+        self.collect_cell_references(&mut references);
+        references
+    }
+
+    fn collect_cell_references<'a>(&'a self, references: &mut Vec<CellReference<'a>>) {
+        match self {
+            Expr::Variable { name, .. } => references.extend(CellRef::parse(name).map(|cell| CellReference::Cell { name, cell })),
+            Expr::Range { range, .. } => references.push(CellReference::Range(*range)),
+            Expr::Unary { operand, .. } => operand.collect_cell_references(references),
+            Expr::Binary { lhs, rhs, .. } => {
+                lhs.collect_cell_references(references);
+                rhs.collect_cell_references(references);
+            }
+            Expr::Group { inner, .. } => inner.collect_cell_references(references),
+            Expr::Call { args, .. } => args.iter().for_each(|arg| arg.collect_cell_references(references)),
+            Expr::Number(_) => {}
+        }
+    }
+
+    /// 按出现顺序列出所有数字字面量的可变引用
+    pub fn literals_mut(&mut self) -> Vec<&mut NumberLiteral> {
+        match self {
+            Expr::Number(literal) => vec![literal],
+            Expr::Unary { operand, .. } => operand.literals_mut(),
+            Expr::Binary { lhs, rhs, .. } => {
+                let mut literals = lhs.literals_mut();
+                literals.extend(rhs.literals_mut());
+                literals
+            }
+            Expr::Group { inner, .. } => inner.literals_mut(),
+            Expr::Call { args, .. } => args.iter_mut().flat_map(Expr::literals_mut).collect(),
+            Expr::Variable { .. } | Expr::Range { .. } => Vec::new(),
+        }
+    }
+
+    /// 去掉括号节点后的实际节点
+    fn unwrap_groups(&self) -> &Expr {
+        match self {
+            Expr::Group { inner, .. } => inner.unwrap_groups(),
+            other => other,
+        }
+    }
+
+    /// 节点作为子表达式时的优先级
+    fn precedence(&self) -> u8 {
+        match self.unwrap_groups() {
+            Expr::Binary { op, .. } => op.precedence(),
+            Expr::Unary { .. } => UNARY_PRECEDENCE,
+            _ => u8::MAX,
+        }
+    }
+
+    /// 以规范形式输出子表达式，优先级低于 `min_precedence` 时加括号
+    fn fmt_operand(&self, f: &mut fmt::Formatter<'_>, min_precedence: u8) -> fmt::Result {
+        if self.precedence() < min_precedence {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Number(literal) => write!(f, "{}", literal),
+            Expr::Unary { operand, .. } => {
+                write!(f, "-")?;
+                operand.fmt_operand(f, UNARY_PRECEDENCE)
+            }
+            Expr::Binary { op, lhs, rhs, .. } => {
+                lhs.fmt_operand(f, op.precedence())?;
+                write!(f, " {} ", op.symbol())?;
+                // 减法和除法不满足结合律，右侧同级运算需要括号
+                rhs.fmt_operand(f, op.precedence() + 1)
+            }
+            Expr::Group { inner, .. } => write!(f, "{}", inner),
+            // 不是合法标识符的名称（来自标记引用）保留花括号
+            Expr::Variable { name, .. } if is_identifier(name) => write!(f, "{}", name),
+            Expr::Variable { name, .. } => write!(f, "{{{}}}", name),
+            Expr::Call { function, args, .. } => {
+                let args: Vec<String> = args.iter().map(Expr::to_string).collect();
+                write!(f, "{}({})", function.name(), args.join(", "))
+            }
+            Expr::Range { range, .. } => write!(f, "{}", range),
+        }
+    }
+}
+
+/// 名称能否不加花括号直接作为变量名；单元格地址可以带 `$`，例如 `$B$2`
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    let plain = chars.next().is_some_and(|c| c.is_alphabetic() || c == '_') && chars.all(|c| c.is_alphanumeric() || c == '_');
+    plain || CellRef::parse(name).is_some()
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Statement::Assign { name, expr, .. } => write!(f, "{} = {}", name, expr),
+            Statement::Expr { expr } => write!(f, "{}", expr),
+        }
+    }
+}
+
+impl fmt::Display for NumberLiteral {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.value)?;
+        if self.percent {
+            write!(f, "%")?;
+        }
+        if let Some(label) = &self.label {
+            write!(f, "[{}]", label)?;
+        }
+        Ok(())
+    }
+}
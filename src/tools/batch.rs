@@ -0,0 +1,332 @@
+//! # 批量验证的输入
+//!
+//! `batch_validate` 接受三种输入，统一转换为 `BatchLine` 后再逐行验证：
+//!
+//! - `expressions`：以 `|` 分隔的字符串，`expression|expected|label|key=value;...`，保留用于兼容；
+//! - `items`：JSON 对象数组，字段各自独立，标记和表达式中可以包含 `|`；
+//! - `file`：本地文件，每行是上述两种写法之一，逐块读取。
+//!
+//! 表达式和预期值可以用 `{标记}` 引用前面某一行的值，例如 `{流动资产合计} + {非流动资产合计}|10,000|资产总计`。
+//! 只能引用前面的行；引用自身或后面的行会被报告为循环引用或向前引用。
+
+use rust_mcp_sdk::macros::JsonSchema;
+
+use std::collections::{HashMap, HashSet};
+
+use crate::tools::assertion::split_equation;
+use crate::tools::overrides::LineOverrides;
+use crate::tools::precision::Decimals;
+
+/// `batch_validate` 的一项
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, JsonSchema)]
+pub struct BatchItem {
+    /// 调用方指定的标识，原样出现在该项的结果中
+    pub id: Option<String>,
+    /// 要验证的算术表达式，也可以是等式或断言形式（此时省略 expected）
+    pub expression: String,
+    /// 预期值或断言，例如 1,234.56、>= 0、between 20% and 40%
+    pub expected: Option<String>,
+    /// 标记，例如 流动资产合计
+    pub label: Option<String>,
+    /// 该项的小数位数，非负整数或 "auto"，默认沿用工具参数
+    pub decimals: Option<Decimals>,
+    /// 该项的百分数处理策略：divide_by_100_then_round 或 round_then_divide_by_100，默认沿用工具参数
+    pub percent_rounding: Option<String>,
+    /// 该项的容差，写法同工具参数 tolerance，默认沿用工具参数
+    pub tolerance: Option<String>,
+}
+
+/// 待验证的一行
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct BatchLine {
+    pub id: Option<String>,
+    pub expression: String,
+    pub expected: String,
+    /// 标记，没有时为空字符串
+    pub label: String,
+    pub overrides: LineOverrides,
+}
+
+impl BatchLine {
+    /// 解析 `|` 分隔的一行；出错时的信息已带上标记
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let parts: Vec<&str> = line.split('|').collect();
+
+        // 等式形式 "lhs = rhs|label"，或 "expression|expected|label"；其余字段为标记或单行设置
+        let (expression, expected, fields) = match split_equation(parts[0]) {
+            Some((lhs, rhs)) => (lhs, rhs, &parts[1..]),
+            None if parts.len() >= 2 => (parts[0].trim(), parts[1].trim(), &parts[2..]),
+            None => return Err("格式错误 - 需要 'expression|expected' 或 'lhs = rhs' 格式".to_string()),
+        };
+        let mut label = String::new();
+        let mut overrides = Ok(LineOverrides::default());
+        for field in fields {
+            match LineOverrides::parse(field) {
+                Ok(Some(line)) => overrides = overrides.map(|current| current.merge(line)),
+                Ok(None) if label.is_empty() => label = field.trim().to_string(),
+                Ok(None) => {}
+                Err(message) => overrides = Err(message),
+            }
+        }
+
+        let overrides = overrides.map_err(|message| format!("{}{}", prefix(None, &label), message))?;
+        Ok(BatchLine { id: None, expression: expression.to_string(), expected: expected.to_string(), label, overrides })
+    }
+
+    /// 解析文件中的一行：能解析为 JSON 对象时按 `items` 的一项处理，否则按 `|` 分隔的格式
+    pub fn parse_record(record: &str) -> Result<Self, String> {
+        let record = record.trim();
+        // `{标记} + 1|2` 也以 `{` 开头，但不是合法的 JSON
+        match serde_json::from_str::<BatchItem>(record) {
+            Ok(item) if record.starts_with('{') => BatchLine::from_item(&item),
+            _ => BatchLine::parse(record),
+        }
+    }
+
+    /// 转换 JSON 形式的一项；出错时的信息已带上标记
+    pub fn from_item(item: &BatchItem) -> Result<Self, String> {
+        let label = item.label.as_deref().unwrap_or("").trim().to_string();
+        let error = |message: String| format!("{}{}", prefix(item.id.as_deref(), &label), message);
+
+        let (expression, expected) = match (split_equation(&item.expression), item.expected.as_deref()) {
+            (Some(_), Some(_)) => return Err(error("等式形式的表达式不能同时指定预期值".to_string())),
+            (Some(equation), None) => equation,
+            (None, Some(expected)) => (item.expression.trim(), expected.trim()),
+            (None, None) => return Err(error("缺少预期值：请指定 expected，或使用等式形式 `左边 = 右边`".to_string())),
+        };
+
+        let mut overrides = LineOverrides { decimals: item.decimals, ..Default::default() };
+        if let Some(strategy) = &item.percent_rounding {
+            overrides.set("percent", strategy).map_err(error)?;
+        }
+        if let Some(tolerance) = &item.tolerance {
+            overrides.set("tol", tolerance).map_err(error)?;
+        }
+        Ok(BatchLine {
+            id: item.id.clone(),
+            expression: expression.to_string(),
+            expected: expected.to_string(),
+            label,
+            overrides,
+        })
+    }
+
+    /// 表达式和预期值中引用的标记，按出现顺序
+    pub fn references(&self) -> Vec<String> {
+        let mut names = references(&self.expression);
+        names.extend(references(&self.expected));
+        names
+    }
+
+    /// 结果行中标识和标记的前缀，例如 `(id: r1) [流动资产合计] `
+    pub fn prefix(&self) -> String {
+        prefix(self.id.as_deref(), &self.label)
+    }
+}
+
+/// 被引用的行提供的值
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum ReferenceValue {
+    /// 预期值（已经核对过的数字）；断言不是等式时取计算结果
+    #[default]
+    Expected,
+    /// 计算结果
+    Computed,
+}
+
+impl ReferenceValue {
+    pub const NAMES: &'static str = "expected, computed";
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim() {
+            "expected" => Some(ReferenceValue::Expected),
+            "computed" => Some(ReferenceValue::Computed),
+            _ => None,
+        }
+    }
+}
+
+/// 文本中 `{标记}` 形式的引用，按出现顺序
+pub fn references(text: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}') else { break };
+        let name = rest[start + 1..start + end].trim();
+        if !name.is_empty() {
+            names.push(name.to_string());
+        }
+        rest = &rest[start + end + 1..];
+    }
+    names
+}
+
+/// 一块中每个标记第一次出现的行（从 0 开始），每块建立一次供 `check_references` 查找
+pub fn label_positions(lines: &[Result<BatchLine, String>]) -> HashMap<&str, usize> {
+    let mut positions = HashMap::new();
+    for (index, line) in lines.iter().enumerate() {
+        if let Ok(line) = line {
+            if !line.label.is_empty() {
+                positions.entry(line.label.as_str()).or_insert(index);
+            }
+        }
+    }
+    positions
+}
+
+/// 检查第 `index` 行（从 0 开始）的引用：只能引用前面的行，不能引用自身或形成循环
+///
+/// `lines` 可以是分块读取的一块，`positions` 是 `label_positions(lines)`，`defined_earlier` 判断标记是否已在之前的块中定义。
+/// 找不到对应标记的引用不在这里报告，求值时会作为未定义的变量标出位置。
+pub fn check_references(
+    lines: &[Result<BatchLine, String>],
+    positions: &HashMap<&str, usize>,
+    index: usize,
+    defined_earlier: impl Fn(&str) -> bool,
+) -> Result<(), String> {
+    let Ok(line) = &lines[index] else { return Ok(()) };
+    for name in line.references() {
+        let definition = positions.get(name.as_str()).copied();
+        if defined_earlier(&name) || definition.is_some_and(|at| at < index) {
+            continue;
+        }
+        if name == line.label {
+            return Err(format!("循环引用: {{{}}} 引用了本行", name));
+        }
+        let Some(later) = definition else { continue };
+        if let Some(path) = cycle(lines, positions, later, &line.label, &mut HashSet::new()) {
+            return Err(format!("循环引用: {} → {}", line.label, path.join(" → ")));
+        }
+        return Err(format!("向前引用: {{{}}} 在第 {} 行才定义，只能引用前面的行", name, later + 1));
+    }
+    Ok(())
+}
+
+/// 从第 `from` 行出发沿引用查找回到 `target` 的路径，返回经过的标记
+fn cycle(lines: &[Result<BatchLine, String>], positions: &HashMap<&str, usize>, from: usize, target: &str, visited: &mut HashSet<usize>) -> Option<Vec<String>> {
+    let Ok(line) = &lines[from] else { return None };
+    if target.is_empty() || !visited.insert(from) {
+        return None;
+    }
+    for name in line.references() {
+        if name == target {
+            return Some(vec![line.label.clone(), name]);
+        }
+        let next = positions.get(name.as_str()).copied();
+        if let Some(mut path) = next.and_then(|next| cycle(lines, positions, next, target, visited)) {
+            path.insert(0, line.label.clone());
+            return Some(path);
+        }
+    }
+    None
+}
+
+fn prefix(id: Option<&str>, label: &str) -> String {
+    let id = id.map(|id| format!("(id: {}) ", id)).unwrap_or_default();
+    if label.is_empty() {
+        id
+    } else {
+        format!("{}[{}] ", id, label)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::tolerance::Tolerance;
+
+    fn item(expression: &str, expected: Option<&str>) -> BatchItem {
+        BatchItem {
+            id: None,
+            expression: expression.to_string(),
+            expected: expected.map(str::to_string),
+            label: None,
+            decimals: None,
+            percent_rounding: None,
+            tolerance: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_line() {
+        let line = BatchLine::parse("1 + 2|3|合计|dp=0").unwrap();
+        assert_eq!((line.expression.as_str(), line.expected.as_str(), line.label.as_str()), ("1 + 2", "3", "合计"));
+        assert_eq!(line.overrides.decimals, Some(Decimals::Fixed(0)));
+
+        let line = BatchLine::parse("1 + 2 = 3|tol=0.01|合计").unwrap();
+        assert_eq!((line.expected.as_str(), line.label.as_str()), ("3", "合计"));
+        assert_eq!(line.overrides.tolerance, Some(Tolerance::Absolute(0.01)));
+
+        assert!(BatchLine::parse("1 + 2").is_err());
+        // 标记中的比较符号不拆分算式
+        let line = BatchLine::parse("1,000[A<=B] + 2,000|3,000").unwrap();
+        assert_eq!((line.expression.as_str(), line.expected.as_str()), ("1,000[A<=B] + 2,000", "3,000"));
+        assert_eq!(BatchLine::parse("1|1|合计|dp=x").unwrap_err(), "[合计] 无效的小数位数 'x'，支持 0-15 的整数或 auto");
+    }
+
+    #[test]
+    fn test_from_item() {
+        // 标记中的 `|` 不再被拆分
+        let mut labelled = item("1 + 2", Some("3"));
+        labelled.label = Some("A|B".to_string());
+        labelled.id = Some("r1".to_string());
+        labelled.tolerance = Some("1%".to_string());
+        let line = BatchLine::from_item(&labelled).unwrap();
+        assert_eq!(line.prefix(), "(id: r1) [A|B] ");
+        assert_eq!(line.overrides.tolerance, Some(Tolerance::Relative(1.0)));
+
+        let line = BatchLine::from_item(&item("a - b >= 0", None)).unwrap();
+        assert_eq!((line.expression.as_str(), line.expected.as_str()), ("a - b", ">= 0"));
+
+        assert!(BatchLine::from_item(&item("1 = 1", Some("1"))).is_err());
+        assert!(BatchLine::from_item(&item("1 + 1", None)).is_err());
+        let mut invalid = item("1", Some("1"));
+        invalid.percent_rounding = Some("up".to_string());
+        invalid.id = Some("r2".to_string());
+        assert!(BatchLine::from_item(&invalid).unwrap_err().starts_with("(id: r2) 无效的百分数处理策略"));
+    }
+
+    #[test]
+    fn test_references() {
+        assert_eq!(references("{流动资产合计} + { 非流动资产合计 } + {}"), vec!["流动资产合计", "非流动资产合计"]);
+        assert!(references("1 + 2").is_empty());
+
+        let lines: Vec<Result<BatchLine, String>> = [
+            "{B} + 1|2|A",
+            "1|1|B",
+            "{B} + {C}|3|C",
+            "{D}|1|E",
+            "{E}|1|D",
+            "{A} + {B}|3|总计",
+        ]
+        .iter()
+        .map(|line| BatchLine::parse(line))
+        .collect();
+        let positions = label_positions(&lines);
+        let check = |index: usize| check_references(&lines, &positions, index, |_: &str| false);
+        assert_eq!(check(0), Err("向前引用: {B} 在第 2 行才定义，只能引用前面的行".to_string()));
+        assert_eq!(check(1), Ok(()));
+        assert_eq!(check(2), Err("循环引用: {C} 引用了本行".to_string()));
+        assert_eq!(check(3), Err("循环引用: E → D → E".to_string()));
+        assert_eq!(check(5), Ok(()));
+        // 之前的块中已经定义的标记
+        assert_eq!(check_references(&lines, &positions, 0, |name| name == "B"), Ok(()));
+    }
+
+    #[test]
+    fn test_parse_record() {
+        let line = BatchLine::parse_record(r#"{"id": "r1", "expression": "1 + 2", "expected": "3", "label": "A|B"}"#).unwrap();
+        assert_eq!((line.id.as_deref(), line.label.as_str()), (Some("r1"), "A|B"));
+        let line = BatchLine::parse_record("{合计} + 1|2|总计").unwrap();
+        assert_eq!((line.expression.as_str(), line.label.as_str()), ("{合计} + 1", "总计"));
+        assert!(BatchLine::parse_record(r#"{"expression": "1 + 1"}"#).is_err());
+    }
+
+    #[test]
+    fn test_item_schema() {
+        let schema = BatchItem::json_schema();
+        assert_eq!(schema["type"], "object");
+        assert_eq!(schema["required"], serde_json::json!(["expression"]));
+        assert!(schema["properties"]["decimals"]["anyOf"].is_array());
+    }
+}
@@ -7,29 +7,23 @@
 //!    - 规则3：计算结果也需要进行最终的四舍五入。
 //! 2. `validate`: 验证一个算式的计算结果是否与预期值相符。
 //!
-//! 核心算法采用"调度场算法"(Shunting-yard Algorithm)，分为三步：
-//! 1. **词法分析 (Tokenization)**: 将字符串分解为词元（Token），并在此阶段完成数字的预先舍入和百分比处理。
-//! 2. **语法分析 (Parsing)**: 使用调度场算法将中缀表达式词元序列转换为后缀表达式（逆波兰表示法, RPN）。
-//! 3. **求值 (Evaluation)**: 计算后缀表达式得出结果。
+//! 数字的小数点与千分位分隔符默认自动识别，也可以通过 `CalcOptions` 指定 `NumberFormat` 确定性地解析。
+//!
+//! 计算分为三步：
+//! 1. **解析 (Parsing)**: `parser::parse` 把字符串解析为语法树（`ast::Expr`），数字保留原始文本，不进行舍入。
+//! 2. **求值 (Evaluation)**: `evaluate` 遍历语法树，对每个数字字面量完成预先舍入和百分比处理后再参与运算。
+//! 3. **结果舍入**: 对求值结果进行最终的四舍五入。
 
-use std::iter::Peekable;
-use std::str::Chars;
+use std::collections::HashMap;
+use std::fmt;
 
-// --- 公开的枚举和结构体 ---
+use crate::tools::ast::{BinaryOp, Expr, Function, NumberLiteral, UnaryOp};
+use crate::tools::diagnostic::{render_snippet, Expected, Span};
+use crate::tools::number_format::NumberFormat;
+use crate::tools::parser::{parse, MAX_DEPTH, MAX_NESTING};
+use crate::tools::tolerance::{Tolerance, ToleranceCheck};
 
-/// 定义词元（Token）类型
-/// `Clone` is needed for the shunting-yard algorithm.
-/// `PartialEq` and `Debug` are for testing and debugging.
-#[derive(Debug, PartialEq, Clone)]
-enum Token {
-    Number(f64),
-    Add,
-    Subtract,
-    Multiply,
-    Divide,
-    LeftParen,
-    RightParen,
-}
+// --- 公开的枚举和结构体 ---
 
 /// 定义百分数的处理策略
 /// 用户可以通过这个参数决定如何处理百分数（%符号）
@@ -43,205 +37,642 @@ pub enum PercentRounding {
     RoundThenDivideBy100,
 }
 
+impl PercentRounding {
+    /// 支持的策略名称
+    pub const NAMES: &'static str = "divide_by_100_then_round, round_then_divide_by_100";
+
+    /// 按名称解析策略
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim() {
+            "divide_by_100_then_round" => Some(PercentRounding::DivideBy100ThenRound),
+            "round_then_divide_by_100" => Some(PercentRounding::RoundThenDivideBy100),
+            _ => None,
+        }
+    }
+
+    /// 策略名称，与 `parse` 接受的写法一致
+    pub fn name(&self) -> &'static str {
+        match self {
+            PercentRounding::DivideBy100ThenRound => "divide_by_100_then_round",
+            PercentRounding::RoundThenDivideBy100 => "round_then_divide_by_100",
+        }
+    }
+
+    /// 另一种策略
+    pub fn other(&self) -> Self {
+        match self {
+            PercentRounding::DivideBy100ThenRound => PercentRounding::RoundThenDivideBy100,
+            PercentRounding::RoundThenDivideBy100 => PercentRounding::DivideBy100ThenRound,
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            PercentRounding::DivideBy100ThenRound => "先除以100后舍入",
+            PercentRounding::RoundThenDivideBy100 => "先舍入后除以100",
+        }
+    }
+}
+
+/// 舍入方式，三种方式都关于零对称
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum RoundingMode {
+    /// 四舍五入（中间值远离零舍入）
+    #[default]
+    HalfUp,
+    /// 银行家舍入（中间值舍入到偶数）
+    HalfEven,
+    /// 截断（向零舍入）
+    Down,
+}
+
+impl RoundingMode {
+    pub const ALL: [RoundingMode; 3] = [RoundingMode::HalfUp, RoundingMode::HalfEven, RoundingMode::Down];
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            RoundingMode::HalfUp => "四舍五入",
+            RoundingMode::HalfEven => "银行家舍入",
+            RoundingMode::Down => "截断",
+        }
+    }
+
+    /// 舍入方式的名称
+    pub fn name(&self) -> &'static str {
+        match self {
+            RoundingMode::HalfUp => "half_up",
+            RoundingMode::HalfEven => "half_even",
+            RoundingMode::Down => "down",
+        }
+    }
+}
+
+/// 计算选项：汇总影响词法分析与舍入的全部设置
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct CalcOptions {
+    /// 要保留的小数位数
+    pub decimals: u32,
+    /// 处理百分比的舍入策略
+    pub percent_rounding: PercentRounding,
+    /// 数字字面量的格式
+    pub number_format: NumberFormat,
+    /// 严格分组模式：拒绝不构成合法分组的分隔符
+    pub strict_grouping: bool,
+    /// 验证时允许的差异
+    pub tolerance: Tolerance,
+    /// 是否按规则1对数字字面量预先舍入，关闭后只对最终结果舍入
+    pub pre_round: bool,
+    /// 舍入方式
+    pub rounding_mode: RoundingMode,
+    /// 逐步舍入：每一步运算的结果都舍入，而不只是最终结果
+    pub step_rounding: bool,
+}
+
+impl CalcOptions {
+    pub fn new(decimals: u32, percent_rounding: PercentRounding) -> Self {
+        Self {
+            decimals,
+            percent_rounding,
+            number_format: NumberFormat::Auto,
+            strict_grouping: false,
+            tolerance: Tolerance::Exact,
+            pre_round: true,
+            rounding_mode: RoundingMode::HalfUp,
+            step_rounding: false,
+        }
+    }
+
+    pub fn with_number_format(mut self, number_format: NumberFormat) -> Self {
+        self.number_format = number_format;
+        self
+    }
+
+    pub fn with_strict_grouping(mut self, strict_grouping: bool) -> Self {
+        self.strict_grouping = strict_grouping;
+        self
+    }
+
+    pub fn with_tolerance(mut self, tolerance: Tolerance) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    pub fn with_pre_round(mut self, pre_round: bool) -> Self {
+        self.pre_round = pre_round;
+        self
+    }
+
+    pub fn with_rounding_mode(mut self, rounding_mode: RoundingMode) -> Self {
+        self.rounding_mode = rounding_mode;
+        self
+    }
+
+    pub fn with_step_rounding(mut self, step_rounding: bool) -> Self {
+        self.step_rounding = step_rounding;
+        self
+    }
+
+    /// 按选项的小数位数和舍入方式舍入
+    pub fn round(&self, value: f64) -> f64 {
+        round_with(value, self.decimals, self.rounding_mode)
+    }
+}
+
+/// 求值时可以引用的变量及其（已舍入的）值
+pub type Variables = HashMap<String, f64>;
+
+/// 求值过程中的一步，用于逐步展示计算过程
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    /// 数字字面量预先舍入后的值，`text` 是原始文本（含百分号）
+    Literal { text: String, label: Option<String>, value: f64 },
+    /// 引用变量
+    Variable { name: String, value: f64 },
+    /// 取负
+    Negate { operand: f64, result: f64 },
+    /// 一次二元运算，结果不舍入
+    Operation { op: BinaryOp, lhs: f64, rhs: f64, result: f64 },
+    /// 函数调用，`args` 是各参数的值
+    Call { function: Function, args: Vec<f64>, result: f64 },
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Step::Literal { text, label: Some(label), value } => write!(f, "{}[{}] → {}", text, label, value),
+            Step::Literal { text, label: None, value } => write!(f, "{} → {}", text, value),
+            Step::Variable { name, value } => write!(f, "{} → {}", name, value),
+            Step::Negate { operand, result } => write!(f, "-({}) = {}", operand, result),
+            Step::Operation { op, lhs, rhs, result } => write!(f, "{} {} {} = {}", lhs, op.symbol(), rhs, result),
+            Step::Call { function, args, result } => {
+                let args: Vec<String> = args.iter().map(f64::to_string).collect();
+                write!(f, "{}({}) = {}", function.name(), args.join(", "), result)
+            }
+        }
+    }
+}
+
 /// 定义可能出现的错误类型
+///
+/// 所有位置都是表达式中的字符偏移（从 0 开始）。
 #[derive(Debug, PartialEq)]
 pub enum CalcError {
-    InvalidCharacter(char),
-    MismatchedParens,
-    InvalidExpression,
-    DivisionByZero,
+    InvalidCharacter { pos: usize, ch: char },
+    /// 严格分组模式下不合法的分隔符
+    InvalidGrouping { pos: usize, ch: char },
+    /// 数字字面量无法解析（例如 de-DE 格式下的 `1,2,3`）
+    InvalidNumber { span: Span },
+    /// 括号不匹配，`pos` 指向未闭合的 `(` 或多余的 `)`
+    MismatchedParens { pos: usize, ch: char },
+    /// 在 `span` 处出现了不期望的内容
+    InvalidExpression { span: Span, expected: Expected },
+    /// 除数为零，`span` 是除数所在的区间
+    DivisionByZero { span: Span },
     /// 当表达式不完整时（例如 "5 * "）
-    UnexpectedEndOfExpression,
+    UnexpectedEndOfExpression { pos: usize, expected: Expected },
+    /// 引用了未定义（或尚未赋值）的变量
+    UndefinedVariable { name: String, span: Span },
+    /// 脚本中对同一变量重复赋值，`span` 是重复的赋值语句
+    DuplicateVariable { name: String, span: Span },
+    /// 不支持的函数
+    UnknownFunction { name: String, span: Span },
+    /// 函数的参数个数不符，`span` 是整个函数调用
+    ArgumentCount { function: Function, found: usize, span: Span },
+    /// 括号、取负或函数调用嵌套过深，`pos` 指向超出上限的那一层
+    TooDeep { pos: usize },
+    /// 语法树过深（通常是连续的运算过多），`pos` 指向超出上限的运算符
+    TooLong { pos: usize },
+}
+
+impl CalcError {
+    /// 错误所在的区间
+    pub fn span(&self) -> Span {
+        match *self {
+            CalcError::InvalidCharacter { pos, .. }
+            | CalcError::InvalidGrouping { pos, .. }
+            | CalcError::MismatchedParens { pos, .. }
+            | CalcError::UnexpectedEndOfExpression { pos, .. }
+            | CalcError::TooDeep { pos }
+            | CalcError::TooLong { pos } => Span::at(pos),
+            CalcError::InvalidNumber { span }
+            | CalcError::InvalidExpression { span, .. }
+            | CalcError::DivisionByZero { span }
+            | CalcError::UndefinedVariable { span, .. }
+            | CalcError::DuplicateVariable { span, .. }
+            | CalcError::UnknownFunction { span, .. }
+            | CalcError::ArgumentCount { span, .. } => span,
+        }
+    }
+
+    /// 把错误位置整体后移 `offset` 个字符，用于在更长的文本中解析一段子串时
+    pub fn shifted(self, offset: usize) -> Self {
+        let span = |span: Span| Span::new(span.start + offset, span.end + offset);
+        match self {
+            CalcError::InvalidCharacter { pos, ch } => CalcError::InvalidCharacter { pos: pos + offset, ch },
+            CalcError::InvalidGrouping { pos, ch } => CalcError::InvalidGrouping { pos: pos + offset, ch },
+            CalcError::InvalidNumber { span: s } => CalcError::InvalidNumber { span: span(s) },
+            CalcError::MismatchedParens { pos, ch } => CalcError::MismatchedParens { pos: pos + offset, ch },
+            CalcError::InvalidExpression { span: s, expected } => CalcError::InvalidExpression { span: span(s), expected },
+            CalcError::DivisionByZero { span: s } => CalcError::DivisionByZero { span: span(s) },
+            CalcError::UnexpectedEndOfExpression { pos, expected } => CalcError::UnexpectedEndOfExpression { pos: pos + offset, expected },
+            CalcError::UndefinedVariable { name, span: s } => CalcError::UndefinedVariable { name, span: span(s) },
+            CalcError::DuplicateVariable { name, span: s } => CalcError::DuplicateVariable { name, span: span(s) },
+            CalcError::UnknownFunction { name, span: s } => CalcError::UnknownFunction { name, span: span(s) },
+            CalcError::ArgumentCount { function, found, span: s } => CalcError::ArgumentCount { function, found, span: span(s) },
+            CalcError::TooDeep { pos } => CalcError::TooDeep { pos: pos + offset },
+            CalcError::TooLong { pos } => CalcError::TooLong { pos: pos + offset },
+        }
+    }
+
+    /// 渲染错误信息，并在表达式片段中用 `^` 标出出错位置
+    pub fn render(&self, expr: &str) -> String {
+        format!("{}\n{}", self, render_snippet(expr, self.span()))
+    }
+}
+
+impl fmt::Display for CalcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CalcError::InvalidCharacter { pos, ch } => write!(f, "无效字符: 第 {} 个字符 '{}'", pos + 1, ch),
+            CalcError::InvalidGrouping { pos, ch } => write!(f, "无效的数字分组: 第 {} 个字符 '{}'", pos + 1, ch),
+            CalcError::InvalidNumber { span } => write!(f, "无法解析的数字: 第 {}-{} 个字符", span.start + 1, span.end),
+            CalcError::MismatchedParens { pos, ch: '(' } => write!(f, "括号不匹配: 第 {} 个字符处的 `(` 缺少对应的 `)`", pos + 1),
+            CalcError::MismatchedParens { pos, ch } => write!(f, "括号不匹配: 第 {} 个字符处多余的 `{}`", pos + 1, ch),
+            CalcError::InvalidExpression { span, expected } => write!(f, "无效表达式: 第 {} 个字符处期望{}", span.start + 1, expected),
+            CalcError::DivisionByZero { span } => write!(f, "除零错误: 第 {}-{} 个字符的除数为零", span.start + 1, span.end),
+            CalcError::UnexpectedEndOfExpression { expected, .. } => write!(f, "表达式意外结束: 期望{}", expected),
+            CalcError::UndefinedVariable { name, span } => write!(f, "未定义的变量: 第 {} 个字符处的 `{}`", span.start + 1, name),
+            CalcError::DuplicateVariable { name, span } => write!(f, "变量重复赋值: 第 {} 个字符处的 `{}`", span.start + 1, name),
+            CalcError::UnknownFunction { name, span } => {
+                write!(f, "未知的函数: 第 {} 个字符处的 `{}`，支持的函数：{}", span.start + 1, name, Function::NAMES)
+            }
+            CalcError::ArgumentCount { function, found, span } => {
+                let expected = function.arity().unwrap_or_default();
+                write!(f, "参数个数不符: 第 {} 个字符处的 {} 需要 {} 个参数，实际为 {} 个", span.start + 1, function.name(), expected, found)
+            }
+            CalcError::TooDeep { pos } => write!(f, "嵌套过深: 第 {} 个字符处的括号、取负或函数调用超过 {} 层", pos + 1, MAX_NESTING),
+            CalcError::TooLong { pos } => write!(f, "表达式过长: 第 {} 个字符处的运算使嵌套超过 {} 层，请拆分后分别计算", pos + 1, MAX_DEPTH),
+        }
+    }
 }
 
 // --- 核心功能函数 ---
 
-/// 函数1：计算
-///
-/// # 参数
-/// * `expr` - 要计算的算式字符串
-/// * `decimals` - 要保留的小数位数
-/// * `rounding_strategy` - 处理百分比的舍入策略
-///
-/// # 返回
-/// * `Result<f64, CalcError>` - 计算结果或错误
-pub fn calculate(
-    expr: &str,
-    decimals: u32,
-    rounding_strategy: PercentRounding,
-) -> Result<f64, CalcError> {
-    // 步骤 1: 词法分析与预先舍入
-    let tokens = tokenize_and_round(expr, decimals, rounding_strategy)?;
+/// 计算算式并返回逐步的计算过程，最后一步之后的最终舍入由返回值体现
+pub fn calculate_steps(expr: &str, options: &CalcOptions) -> Result<(f64, Vec<Step>), CalcError> {
+    let ast = parse(expr, options)?;
+    let mut steps = Vec::new();
+    let result = evaluate_traced(&ast, options, &Variables::new(), Some(&mut steps))?;
+    Ok((options.round(result), steps))
+}
+
+/// 按完整的计算选项计算算式
+pub fn calculate_with(expr: &str, options: &CalcOptions) -> Result<f64, CalcError> {
+    calculate_in(expr, options, &Variables::new())
+}
 
-    // 步骤 2: 转换为后缀表达式 (Shunting-yard)
-    let rpn_queue = shunt_to_rpn(&tokens)?;
+/// 计算可以引用变量的算式，变量的值不再舍入
+pub fn calculate_in(expr: &str, options: &CalcOptions, variables: &Variables) -> Result<f64, CalcError> {
+    // 步骤 1: 解析为语法树
+    let ast = parse(expr, options)?;
 
-    // 步骤 3: 求值
-    let result = evaluate_rpn(&rpn_queue)?;
+    // 步骤 2: 求值（数字字面量在此预先舍入）
+    let result = evaluate_traced(&ast, options, variables, None)?;
 
-    // 步骤 4: 最终结果舍入
-    Ok(round_value(result, decimals))
+    // 步骤 3: 最终结果舍入
+    Ok(options.round(result))
 }
 
-/// 函数2：验证
+/// 计算预期值
 ///
-/// # 参数
-/// * `expr` - 要计算的算式字符串
-/// * `expected` - 预期的结果
-/// * `decimals` - 要保留的小数位数
-/// * `rounding_strategy` - 处理百分比的舍入策略
-///
-/// # 返回
-/// * `bool` - 算式计算结果是否与预期一致
-pub fn validate(
-    expr: &str,
-    expected: f64,
-    decimals: u32,
-    rounding_strategy: PercentRounding,
-) -> bool {
-    // 使用一个小的容差来比较浮点数，避免精度问题
-    const EPSILON: f64 = 1e-9;
+/// 预期值必须完整地解析为一个表达式，不允许多余的字符。单个不带百分号的数字按原值返回，不做舍入；
+/// 百分数和表达式按与算式相同的规则计算。
+pub fn evaluate_expected(expected: &str, options: &CalcOptions) -> Result<f64, CalcError> {
+    evaluate_expected_in(expected, options, &Variables::new())
+}
 
-    match calculate(expr, decimals, rounding_strategy) {
-        Ok(actual) => (actual - expected).abs() < EPSILON,
-        Err(_) => false, // 如果计算出错，则验证失败
+/// 计算可以引用变量的预期值
+pub fn evaluate_expected_in(expected: &str, options: &CalcOptions, variables: &Variables) -> Result<f64, CalcError> {
+    let ast = parse(expected, options)?;
+    match plain_number(&ast) {
+        Some(value) => Ok(value),
+        None => Ok(options.round(evaluate_traced(&ast, options, variables, None)?)),
     }
 }
 
+/// 不带百分号的数字（可以带负号）的原值
+fn plain_number(expr: &Expr) -> Option<f64> {
+    match expr {
+        Expr::Number(literal) if !literal.percent => Some(literal.value),
+        Expr::Unary { op: UnaryOp::Negate, operand, .. } => plain_number(operand).map(|value| -value),
+        _ => None,
+    }
+}
+
+/// 计算算式并按 `options.tolerance` 与预期值比较，返回差异和允许的差异
+pub fn check_with(expr: &str, expected: f64, options: &CalcOptions) -> Result<ToleranceCheck, CalcError> {
+    check_in(expr, expected, options, &Variables::new())
+}
+
+/// 计算可以引用变量的算式并与预期值比较
+pub fn check_in(expr: &str, expected: f64, options: &CalcOptions, variables: &Variables) -> Result<ToleranceCheck, CalcError> {
+    let actual = calculate_in(expr, options, variables)?;
+    Ok(options.tolerance.check(actual, expected, options.decimals))
+}
+
 // --- 辅助函数 ---
 
 /// 辅助函数：对一个 f64 值进行四舍五入
-fn round_value(value: f64, decimals: u32) -> f64 {
-    let factor = 10f64.powi(decimals as i32);
-    (value * factor).round() / factor
+pub fn round_value(value: f64, decimals: u32) -> f64 {
+    round_with(value, decimals, RoundingMode::HalfUp)
 }
 
-/// 辅助函数：获取操作符的优先级
-fn precedence(token: &Token) -> u8 {
-    match token {
-        Token::Add | Token::Subtract => 1,
-        Token::Multiply | Token::Divide => 2,
-        _ => 0,
+/// 辅助函数：按指定的舍入方式舍入
+pub fn round_with(value: f64, decimals: u32, mode: RoundingMode) -> f64 {
+    let factor = 10f64.powi(i32::try_from(decimals).unwrap_or(i32::MAX));
+    let scaled = value * factor;
+    // 位数多到放大后溢出时，值本身已没有更多的小数位可舍入
+    if !scaled.is_finite() {
+        return value;
+    }
+    // 乘法产生的尾差（例如 0.29 * 100 = 28.999999999999996）不应影响截断和中间值的判断，
+    // 三种舍入方式使用同一个吸附后的值，比较舍入口径时才不会因尾差的处理不同而出现假的差异
+    let snapped = if (scaled - scaled.round()).abs() < 1e-9 { scaled.round() } else { scaled };
+    let rounded = match mode {
+        RoundingMode::HalfUp => snapped.round(),
+        RoundingMode::HalfEven => snapped.round_ties_even(),
+        RoundingMode::Down => snapped.trunc(),
+    };
+    rounded / factor
+}
+
+/// 电子表格的 `ROUND`：位数的小数部分截去，负的位数舍入到十位、百位等
+fn round_digits(value: f64, digits: f64) -> f64 {
+    let digits = digits.trunc() as i32;
+    if digits >= 0 {
+        round_with(value, digits as u32, RoundingMode::HalfUp)
+    } else {
+        let factor = 10f64.powi(-digits);
+        round_with(value / factor, 0, RoundingMode::HalfUp) * factor
     }
 }
 
 // --- 算法核心实现 ---
 
-/// 步骤 1: 词法分析与预先舍入
-fn tokenize_and_round(
-    expr: &str,
-    decimals: u32,
-    rounding_strategy: PercentRounding,
-) -> Result<Vec<Token>, CalcError> {
-    let mut tokens = Vec::new();
-    let mut chars = expr.chars().peekable();
-
-    while let Some(&c) = chars.peek() {
-        match c {
-            '0'..='9' => {
-                let num_str = consume_number(&mut chars);
-                let mut num = num_str.parse::<f64>().map_err(|_| CalcError::InvalidExpression)?;
-
-                // 检查百分号
-                if let Some('%') = chars.peek() {
-                    chars.next(); // consume '%'
-                    num = match rounding_strategy {
-                        PercentRounding::DivideBy100ThenRound => {
-                            let converted = num / 100.0;
-                            round_value(converted, decimals)
-                        }
-                        PercentRounding::RoundThenDivideBy100 => {
-                            let rounded = round_value(num, decimals);
-                            rounded / 100.0
-                        }
-                    };
-                } else {
-                    // 普通数字的舍入
-                    num = round_value(num, decimals);
-                }
-                tokens.push(Token::Number(num));
-            }
-            '+' => {
-                tokens.push(Token::Add);
-                chars.next();
-            }
-            // 处理负号和减号的区别
-            '-' => {
-                let is_unary = tokens.is_empty() || matches!(tokens.last(), Some(Token::LeftParen) | Some(Token::Add) | Some(Token::Subtract) | Some(Token::Multiply) | Some(Token::Divide));
-                chars.next(); // consume '-'
-                if is_unary {
-                    // This is a negative number
-                    let num_str = consume_number(&mut chars);
-                    if num_str.is_empty() {
-                        return Err(CalcError::InvalidExpression);
-                    }
-                    let mut num = -num_str.parse::<f64>().map_err(|_| CalcError::InvalidExpression)?;
-                     // Check for percentage on negative number
-                    if let Some('%') = chars.peek() {
-                        chars.next(); // consume '%'
-                         num = match rounding_strategy {
-                            PercentRounding::DivideBy100ThenRound => round_value(num / 100.0, decimals),
-                            PercentRounding::RoundThenDivideBy100 => round_value(num, decimals) / 100.0,
-                        };
-                    } else {
-                        num = round_value(num, decimals);
-                    }
-                    tokens.push(Token::Number(num));
-                } else {
-                    // This is a subtraction operator
-                    tokens.push(Token::Subtract);
-                }
+/// 求值语法树，并按求值顺序把每一步记录到 `trace` 中
+///
+/// 数字字面量按规则1预先舍入（百分数按 `percent_rounding` 处理），除非开启 `step_rounding`，运算过程不舍入。
+/// 变量的值在赋值时已经舍入，引用时不再舍入。
+pub fn evaluate_traced(expr: &Expr, options: &CalcOptions, variables: &Variables, mut trace: Option<&mut Vec<Step>>) -> Result<f64, CalcError> {
+    match expr {
+        Expr::Variable { name, span } => {
+            let value = *variables
+                .get(name)
+                .ok_or_else(|| CalcError::UndefinedVariable { name: name.clone(), span: *span })?;
+            if let Some(trace) = trace {
+                trace.push(Step::Variable { name: name.clone(), value });
             }
-            '*' => {
-                tokens.push(Token::Multiply);
-                chars.next();
+            Ok(value)
+        }
+        Expr::Number(literal) => {
+            let value = literal_value(literal, options);
+            if let Some(trace) = trace {
+                trace.push(Step::Literal {
+                    text: if literal.percent { format!("{}%", literal.text) } else { literal.text.clone() },
+                    label: literal.label.clone(),
+                    value,
+                });
             }
-            '/' => {
-                tokens.push(Token::Divide);
-                chars.next();
+            Ok(value)
+        }
+        Expr::Unary { op: UnaryOp::Negate, operand, .. } => {
+            // 舍入关于零对称，先舍入再取负与对负数舍入结果相同
+            let operand = evaluate_traced(operand, options, variables, trace.as_deref_mut())?;
+            if let Some(trace) = trace {
+                trace.push(Step::Negate { operand, result: -operand });
             }
-            '(' => {
-                tokens.push(Token::LeftParen);
-                chars.next();
+            Ok(-operand)
+        }
+        Expr::Group { inner, .. } => evaluate_traced(inner, options, variables, trace),
+        Expr::Range { .. } => {
+            // 区域只出现在函数的参数中，单独求值时按 SUM 处理
+            let mut values = Vec::new();
+            push_argument(expr, options, variables, trace, &mut values)?;
+            Ok(sum(&values, options))
+        }
+        Expr::Call { function, args, span } => {
+            let mut values = Vec::with_capacity(args.len());
+            for arg in args {
+                push_argument(arg, options, variables, trace.as_deref_mut(), &mut values)?;
             }
-            ')' => {
-                tokens.push(Token::RightParen);
-                chars.next();
+            let result = match function {
+                Function::Sum => sum(&values, options),
+                Function::Round => match values[..] {
+                    [value, digits] => round_digits(value, digits),
+                    // 区域展开后参数个数可能不符
+                    _ => return Err(CalcError::ArgumentCount { function: *function, found: values.len(), span: *span }),
+                },
+            };
+            if let Some(trace) = trace {
+                trace.push(Step::Call { function: *function, args: values, result });
             }
-            ' ' | '\t' | '\n' => {
-                // Skip whitespace
-                chars.next();
+            Ok(result)
+        }
+        Expr::Binary { op, lhs, rhs, .. } => {
+            let lhs_value = evaluate_traced(lhs, options, variables, trace.as_deref_mut())?;
+            let rhs_value = evaluate_traced(rhs, options, variables, trace.as_deref_mut())?;
+            let result = match op {
+                BinaryOp::Add => lhs_value + rhs_value,
+                BinaryOp::Subtract => lhs_value - rhs_value,
+                BinaryOp::Multiply => lhs_value * rhs_value,
+                BinaryOp::Divide => {
+                    if rhs_value.abs() < 1e-9 {
+                        return Err(CalcError::DivisionByZero { span: rhs.span() });
+                    }
+                    lhs_value / rhs_value
+                }
+            };
+            let result = if options.step_rounding { options.round(result) } else { result };
+            if let Some(trace) = trace {
+                trace.push(Step::Operation { op: *op, lhs: lhs_value, rhs: rhs_value, result });
             }
-            _ => return Err(CalcError::InvalidCharacter(c)),
+            Ok(result)
+        }
+    }
+}
+
+/// 求值函数的一个参数并加入参数列表；区域展开为其中各单元格的值，与逐个列出单元格相同
+fn push_argument(arg: &Expr, options: &CalcOptions, variables: &Variables, mut trace: Option<&mut Vec<Step>>, values: &mut Vec<f64>) -> Result<(), CalcError> {
+    let Expr::Range { range, span } = arg else {
+        values.push(evaluate_traced(arg, options, variables, trace)?);
+        return Ok(());
+    };
+    // 调用方可以按区域的写法（如 `B2:B10`）直接给出合计，大区域不必逐格提供变量
+    let name = range.to_string();
+    if let Some(&value) = variables.get(&name) {
+        if let Some(trace) = trace {
+            trace.push(Step::Variable { name, value });
         }
+        values.push(value);
+        return Ok(());
     }
+    for cell in range.cells() {
+        let name = cell.to_string();
+        let value = *variables.get(&name).ok_or_else(|| CalcError::UndefinedVariable { name: name.clone(), span: *span })?;
+        if let Some(trace) = trace.as_deref_mut() {
+            trace.push(Step::Variable { name, value });
+        }
+        values.push(value);
+    }
+    Ok(())
+}
 
-    Ok(tokens)
+/// `SUM` 的结果，逐步舍入时舍入一次
+fn sum(values: &[f64], options: &CalcOptions) -> f64 {
+    let sum: f64 = values.iter().sum();
+    if options.step_rounding { options.round(sum) } else { sum }
 }
 
-/// 辅助函数：从字符流中消费一个完整的数字字符串（支持千分位分隔符）
-fn consume_number(chars: &mut Peekable<Chars>) -> String {
-    let mut num_str = String::new();
-    let mut has_digit = false;
-    
-    while let Some(&c) = chars.peek() {
+/// 数字字面量参与运算的值：完成预先舍入和百分比处理
+pub fn literal_value(literal: &NumberLiteral, options: &CalcOptions) -> f64 {
+    if !options.pre_round {
+        return if literal.percent { literal.value / 100.0 } else { literal.value };
+    }
+    if !literal.percent {
+        // 普通数字的舍入
+        return options.round(literal.value);
+    }
+    match options.percent_rounding {
+        PercentRounding::DivideBy100ThenRound => {
+            let converted = literal.value / 100.0;
+            options.round(converted)
+        }
+        PercentRounding::RoundThenDivideBy100 => {
+            let rounded = options.round(literal.value);
+            rounded / 100.0
+        }
+    }
+}
+
+/// 表示零的金额单元格
+const ZERO_CELLS: [&str; 3] = ["-", "—", "–"];
+
+/// 解析表格中的金额单元格：只接受一个数字字面量，可以带负号或百分号，`(1,234.00)` 这样的会计写法表示负数，
+/// `-`、`—` 表示零；数字按字面量的规则预先舍入
+///
+/// 空单元格为 `None`。不是单个数字（例如 `1,000 + 5`、`(1+2)`）时返回原因，由调用方附上单元格的位置。
+pub fn parse_amount_cell(text: &str, options: &CalcOptions) -> Result<Option<f64>, String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(None);
+    }
+    if ZERO_CELLS.contains(&text) {
+        return Ok(Some(0.0));
+    }
+    let (number, sign) = match text.strip_prefix('(').and_then(|rest| rest.strip_suffix(')')) {
+        Some(inner) => (inner.trim(), -1.0),
+        None => (text, 1.0),
+    };
+    let not_number = || "只能是一个数字，不能是表达式".to_string();
+    match parse(number, options).map_err(|e| e.render(number))? {
+        Expr::Number(literal) => Ok(Some(sign * literal_value(&literal, options))),
+        Expr::Unary { op: UnaryOp::Negate, operand, .. } if sign > 0.0 => match *operand {
+            Expr::Number(literal) => Ok(Some(-literal_value(&literal, options))),
+            _ => Err(not_number()),
+        },
+        _ => Err(not_number()),
+    }
+}
+
+/// 辅助函数：严格分组模式下校验并标准化原始数字字符串
+///
+/// 整数部分的分组必须是合法的三位分组（`en-IN` 为 lakh/crore 的 2-2-3 分组），且只能使用同一种分组字符；
+/// 小数部分不允许出现分隔符。`start` 是数字在表达式中的字符位置，用于报告出错字符的位置。
+pub fn normalize_strict(raw: &str, start: usize, number_format: NumberFormat) -> Result<String, CalcError> {
+    // 末尾的空白属于数字与下一个词元之间的间隔
+    let chars: Vec<char> = raw.trim_end().chars().collect();
+    let separators: Vec<(usize, char)> = chars
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| !c.is_ascii_digit())
+        .map(|(i, &c)| (i, c))
+        .collect();
+    let invalid = |i: usize| CalcError::InvalidGrouping { pos: start + i, ch: chars[i] };
+
+    let decimal = match number_format.decimal_separator() {
+        Some(d) => separators.iter().find(|(_, c)| *c == d).map(|&(i, _)| i),
+        None => auto_decimal_position(&chars, &separators),
+    };
+
+    // 整数部分：检查分组
+    let int_end = decimal.unwrap_or(chars.len());
+    let groups: Vec<(usize, char)> = separators.iter().copied().filter(|&(i, _)| i < int_end).collect();
+    if let Some(&(_, group_char)) = groups.first() {
+        if let Some(&(i, _)) = groups.iter().find(|&&(_, c)| c != group_char) {
+            return Err(invalid(i));
+        }
+
+        let mut bounds = vec![0];
+        bounds.extend(groups.iter().map(|&(i, _)| i + 1));
+        let mut ends: Vec<usize> = groups.iter().map(|&(i, _)| i).collect();
+        ends.push(int_end);
+
+        let last = ends.len() - 1;
+        for (k, (&from, &to)) in bounds.iter().zip(ends.iter()).enumerate() {
+            let len = to - from;
+            let valid = match (number_format, k) {
+                (NumberFormat::EnIn, 0) => (1..=2).contains(&len),
+                (NumberFormat::EnIn, k) if k < last => len == 2,
+                (_, 0) => (1..=3).contains(&len),
+                _ => len == 3,
+            };
+            if !valid {
+                // 报告与该分组相邻的分隔符：首组报告其后的分隔符，其余报告其前的分隔符
+                return Err(invalid(if k == 0 { groups[0].0 } else { groups[k - 1].0 }));
+            }
+        }
+    }
+
+    // 小数部分：不允许出现任何分隔符，且至少有一位数字
+    if let Some(d) = decimal {
+        if let Some(&(i, _)) = separators.iter().find(|&&(i, _)| i > d) {
+            return Err(invalid(i));
+        }
+        if d + 1 == chars.len() {
+            return Err(invalid(d));
+        }
+    }
+
+    let mut normalized = String::with_capacity(chars.len());
+    for (i, &c) in chars.iter().enumerate() {
         if c.is_ascii_digit() {
-            num_str.push(c);
-            has_digit = true;
-            chars.next();
-        } else if (c == '.' || c == ',' || c == ' ' || c == '\'') && has_digit {
-            // 只有在已经有数字的情况下才消费分隔符
-            num_str.push(c);
-            chars.next();
-        } else {
-            break;
+            normalized.push(c);
+        } else if Some(i) == decimal {
+            normalized.push('.');
         }
     }
-    
-    if has_digit {
-        normalize_number(&num_str)
+    Ok(normalized)
+}
+
+/// 辅助函数：`auto` 格式下判断哪个分隔符是小数点，规则与 `normalize_number` 保持一致
+fn auto_decimal_position(chars: &[char], separators: &[(usize, char)]) -> Option<usize> {
+    let last_mark = separators.iter().rev().find(|(_, c)| *c == ',' || *c == '.')?;
+    let has_comma = separators.iter().any(|(_, c)| *c == ',');
+    let has_dot = separators.iter().any(|(_, c)| *c == '.');
+    let occurrences = separators.iter().filter(|(_, c)| *c == last_mark.1).count();
+
+    if has_comma && has_dot {
+        // 两者都有时，最后出现的是小数点
+        Some(last_mark.0)
+    } else if occurrences > 1 {
+        // 同一字符出现多次，必然是千分位
+        None
+    } else if last_mark.1 == ',' {
+        // 单个逗号后面不超过三位数字时视为欧式小数点
+        let digits_after = chars.len() - last_mark.0 - 1;
+        (digits_after <= 3).then_some(last_mark.0)
     } else {
-        num_str
+        Some(last_mark.0)
     }
 }
 
@@ -253,6 +684,10 @@ pub fn normalize_number(input: &str) -> String {
     
     // 简化的格式检测逻辑
     let cleaned = input.trim();
+    // 最常见的情况：没有任何分隔符
+    if cleaned.bytes().all(|b| b.is_ascii_digit()) {
+        return cleaned.to_string();
+    }
     
     // 如果包含逗号和点号，判断哪个是小数点
     if cleaned.contains(',') && cleaned.contains('.') {
@@ -315,100 +750,26 @@ pub fn normalize_number(input: &str) -> String {
 
 /// 移除千分位分隔符
 fn remove_thousand_separators(input: &str, separators: &[char]) -> String {
-    let mut result = input.to_string();
-    for &sep in separators {
-        result = result.replace(sep, "");
-    }
-    result
+    input.chars().filter(|c| !separators.contains(c)).collect()
 }
 
 
-/// 步骤 2: 将词元序列转换为后缀表达式 (Shunting-yard)
-fn shunt_to_rpn(tokens: &[Token]) -> Result<Vec<Token>, CalcError> {
-    let mut output_queue: Vec<Token> = Vec::new();
-    let mut operator_stack: Vec<Token> = Vec::new();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    for token in tokens.iter().cloned() {
-        match token {
-            Token::Number(_) => output_queue.push(token),
-            Token::LeftParen => operator_stack.push(token),
-            Token::RightParen => {
-                while let Some(top_op) = operator_stack.last() {
-                    if matches!(top_op, Token::LeftParen) {
-                        break;
-                    }
-                    output_queue.push(operator_stack.pop().unwrap());
-                }
-                if operator_stack.pop().is_none() {
-                    // Mismatched parentheses
-                    return Err(CalcError::MismatchedParens);
-                }
-            }
-            // Operator case
-            _ => {
-                while let Some(top_op) = operator_stack.last() {
-                    if matches!(top_op, Token::LeftParen) {
-                        break;
-                    }
-                    if precedence(top_op) >= precedence(&token) {
-                        output_queue.push(operator_stack.pop().unwrap());
-                    } else {
-                        break;
-                    }
-                }
-                operator_stack.push(token);
-            }
-        }
+    fn calculate(expr: &str, decimals: u32, rounding_strategy: PercentRounding) -> Result<f64, CalcError> {
+        calculate_with(expr, &CalcOptions::new(decimals, rounding_strategy))
     }
 
-    // Pop remaining operators from the stack to the queue
-    while let Some(op) = operator_stack.pop() {
-        if matches!(op, Token::LeftParen) {
-            return Err(CalcError::MismatchedParens);
-        }
-        output_queue.push(op);
-    }
-
-    Ok(output_queue)
-}
-
-/// 步骤 3: 求值后缀表达式
-fn evaluate_rpn(rpn_queue: &[Token]) -> Result<f64, CalcError> {
-    let mut operand_stack: Vec<f64> = Vec::new();
-
-    for token in rpn_queue.iter().cloned() {
-        match token {
-            Token::Number(n) => operand_stack.push(n),
-            _ => {
-                let rhs = operand_stack.pop().ok_or(CalcError::InvalidExpression)?;
-                let lhs = operand_stack.pop().ok_or(CalcError::InvalidExpression)?;
-                let result = match token {
-                    Token::Add => lhs + rhs,
-                    Token::Subtract => lhs - rhs,
-                    Token::Multiply => lhs * rhs,
-                    Token::Divide => {
-                        if rhs.abs() < 1e-9 {
-                            return Err(CalcError::DivisionByZero);
-                        }
-                        lhs / rhs
-                    }
-                    _ => unreachable!(), // Should not happen with a valid RPN queue
-                };
-                operand_stack.push(result);
-            }
-        }
+    fn validate(expr: &str, expected: f64, decimals: u32, rounding_strategy: PercentRounding) -> bool {
+        validate_with(expr, expected, &CalcOptions::new(decimals, rounding_strategy))
     }
 
-    if operand_stack.len() == 1 {
-        Ok(operand_stack.pop().unwrap())
-    } else {
-        Err(CalcError::InvalidExpression)
+    /// 计算出错时验证失败
+    fn validate_with(expr: &str, expected: f64, options: &CalcOptions) -> bool {
+        check_with(expr, expected, options).is_ok_and(|check| check.passed)
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
 
     #[test]
     fn test_basic_arithmetic() {
@@ -437,6 +798,10 @@ mod tests {
         assert_eq!(calculate("1.234 + 2.567", 2, PercentRounding::DivideBy100ThenRound), Ok(3.80));
         assert_eq!(calculate("1.235 + 2.564", 2, PercentRounding::DivideBy100ThenRound), Ok(3.80));
         assert_eq!(calculate("1.999 + 0.001", 2, PercentRounding::DivideBy100ThenRound), Ok(2.00));
+        // 位数过多时放大会溢出，值保持不变
+        assert_eq!(round_value(1.25, 400), 1.25);
+        assert_eq!(round_value(1.25, u32::MAX), 1.25);
+        assert_eq!(round_value(0.0, u32::MAX), 0.0);
     }
 
     #[test]
@@ -467,6 +832,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::approx_constant)]
     fn test_decimal_numbers() {
         assert_eq!(calculate("1.5 + 2.5", 1, PercentRounding::DivideBy100ThenRound), Ok(4.0));
         assert_eq!(calculate("3.14 * 2", 2, PercentRounding::DivideBy100ThenRound), Ok(6.28));
@@ -482,28 +848,31 @@ mod tests {
 
     #[test]
     fn test_division_by_zero() {
-        assert_eq!(calculate("5 / 0", 0, PercentRounding::DivideBy100ThenRound), Err(CalcError::DivisionByZero));
-        assert_eq!(calculate("1 / (2 - 2)", 0, PercentRounding::DivideBy100ThenRound), Err(CalcError::DivisionByZero));
+        assert_eq!(calculate("5 / 0", 0, PercentRounding::DivideBy100ThenRound), Err(CalcError::DivisionByZero { span: Span::new(4, 5) }));
+        assert_eq!(calculate("1 / (2 - 2)", 0, PercentRounding::DivideBy100ThenRound), Err(CalcError::DivisionByZero { span: Span::new(4, 11) }));
     }
 
     #[test]
     fn test_invalid_expressions() {
-        assert_eq!(calculate("1 +", 0, PercentRounding::DivideBy100ThenRound), Err(CalcError::InvalidExpression));
-        assert_eq!(calculate("* 2", 0, PercentRounding::DivideBy100ThenRound), Err(CalcError::InvalidExpression));
-        assert_eq!(calculate("1 + + 2", 0, PercentRounding::DivideBy100ThenRound), Err(CalcError::InvalidExpression));
+        assert_eq!(calculate("1 +", 0, PercentRounding::DivideBy100ThenRound), Err(CalcError::UnexpectedEndOfExpression { pos: 3, expected: Expected::Operand }));
+        assert_eq!(calculate("* 2", 0, PercentRounding::DivideBy100ThenRound), Err(CalcError::InvalidExpression { span: Span::at(0), expected: Expected::Operand }));
+        assert_eq!(calculate("1 + + 2", 0, PercentRounding::DivideBy100ThenRound), Err(CalcError::InvalidExpression { span: Span::at(4), expected: Expected::Operand }));
+        assert_eq!(calculate("(1 + 2) 3", 0, PercentRounding::DivideBy100ThenRound), Err(CalcError::InvalidExpression { span: Span::at(8), expected: Expected::Operator }));
+        assert_eq!(calculate("(1 + 2 (3)", 0, PercentRounding::DivideBy100ThenRound), Err(CalcError::InvalidExpression { span: Span::at(7), expected: Expected::OperatorOrRightParen }));
+        assert_eq!(calculate("1 * ()", 0, PercentRounding::DivideBy100ThenRound), Err(CalcError::InvalidExpression { span: Span::at(5), expected: Expected::Operand }));
     }
 
     #[test]
     fn test_mismatched_parentheses() {
-        assert_eq!(calculate("(1 + 2", 0, PercentRounding::DivideBy100ThenRound), Err(CalcError::MismatchedParens));
-        assert_eq!(calculate("1 + 2)", 0, PercentRounding::DivideBy100ThenRound), Err(CalcError::MismatchedParens));
-        assert_eq!(calculate("((1 + 2)", 0, PercentRounding::DivideBy100ThenRound), Err(CalcError::MismatchedParens));
+        assert_eq!(calculate("(1 + 2", 0, PercentRounding::DivideBy100ThenRound), Err(CalcError::MismatchedParens { pos: 0, ch: '(' }));
+        assert_eq!(calculate("1 + 2)", 0, PercentRounding::DivideBy100ThenRound), Err(CalcError::MismatchedParens { pos: 5, ch: ')' }));
+        assert_eq!(calculate("((1 + 2)", 0, PercentRounding::DivideBy100ThenRound), Err(CalcError::MismatchedParens { pos: 0, ch: '(' }));
     }
 
     #[test]
     fn test_invalid_characters() {
-        assert_eq!(calculate("1 + 2 @", 0, PercentRounding::DivideBy100ThenRound), Err(CalcError::InvalidCharacter('@')));
-        assert_eq!(calculate("1 & 2", 0, PercentRounding::DivideBy100ThenRound), Err(CalcError::InvalidCharacter('&')));
+        assert_eq!(calculate("1 + 2 @", 0, PercentRounding::DivideBy100ThenRound), Err(CalcError::InvalidCharacter { pos: 6, ch: '@' }));
+        assert_eq!(calculate("1 & 2", 0, PercentRounding::DivideBy100ThenRound), Err(CalcError::InvalidCharacter { pos: 2, ch: '&' }));
     }
 
     #[test]
@@ -663,4 +1032,216 @@ mod tests {
         // 测试整数情况
         assert!(validate("50 * 2", 100.0, 0, PercentRounding::DivideBy100ThenRound));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_explicit_number_format() {
+        let us = CalcOptions::new(2, PercentRounding::DivideBy100ThenRound).with_number_format(NumberFormat::EnUs);
+        let de = CalcOptions::new(2, PercentRounding::DivideBy100ThenRound).with_number_format(NumberFormat::DeDe);
+
+        // 自动识别会把 1,234 当作欧式小数，指定 en-US 后按千分位解析
+        assert_eq!(calculate("1,234 + 1", 2, PercentRounding::DivideBy100ThenRound), Ok(2.23));
+        assert_eq!(calculate_with("1,234 + 1", &us), Ok(1235.0));
+        assert_eq!(calculate_with("1.234 + 1", &de), Ok(1235.0));
+        assert_eq!(calculate_with("1.234,5 + 0,5", &de), Ok(1235.0));
+        assert_eq!(calculate_with("1.000,5 * 2", &de), Ok(2001.0));
+    }
+
+    #[test]
+    fn test_locale_group_separators() {
+        let fr = CalcOptions::new(2, PercentRounding::DivideBy100ThenRound).with_number_format(NumberFormat::FrFr);
+        let ch = CalcOptions::new(2, PercentRounding::DivideBy100ThenRound).with_number_format(NumberFormat::DeCh);
+        let zh = CalcOptions::new(2, PercentRounding::DivideBy100ThenRound).with_number_format(NumberFormat::ZhCn);
+        let en_in = CalcOptions::new(2, PercentRounding::DivideBy100ThenRound).with_number_format(NumberFormat::EnIn);
+
+        assert_eq!(calculate_with("1 234,56 + 1\u{a0}000,44", &fr), Ok(2235.0));
+        assert_eq!(calculate_with("1'234.56 + 1\u{2019}000.44", &ch), Ok(2235.0));
+        assert_eq!(calculate_with("1,234.56 + 1,000.44", &zh), Ok(2235.0));
+        assert_eq!(calculate_with("12,34,567.89 + 0.11", &en_in), Ok(1234568.0));
+    }
+
+    #[test]
+    fn test_explicit_number_format_rejects_foreign_separators() {
+        let us = CalcOptions::new(2, PercentRounding::DivideBy100ThenRound).with_number_format(NumberFormat::EnUs);
+
+        // en-US 下空格不是分组符，"1 234" 是缺少运算符的两个数字
+        assert_eq!(calculate_with("1 234", &us), Err(CalcError::InvalidExpression { span: Span::new(2, 5), expected: Expected::Operator }));
+        assert_eq!(calculate_with("1'234", &us), Err(CalcError::InvalidCharacter { pos: 1, ch: '\'' }));
+    }
+
+    #[test]
+    fn test_number_format_names() {
+        assert_eq!(NumberFormat::parse("en-US"), Some(NumberFormat::EnUs));
+        assert_eq!(NumberFormat::parse("de_de"), Some(NumberFormat::DeDe));
+        assert_eq!(NumberFormat::parse("AUTO"), Some(NumberFormat::Auto));
+        assert_eq!(NumberFormat::parse("ja-JP"), None);
+    }
+
+    #[test]
+    fn test_number_format_output() {
+        assert_eq!(NumberFormat::EnUs.format(1234567.891, 2), "1,234,567.89");
+        assert_eq!(NumberFormat::DeDe.format(-1234.5, 2), "-1.234,50");
+        assert_eq!(NumberFormat::FrFr.format(1234.5, 1), "1 234,5");
+        assert_eq!(NumberFormat::DeCh.format(1234567.0, 0), "1'234'567");
+        assert_eq!(NumberFormat::EnIn.format(1234567.89, 2), "12,34,567.89");
+        assert_eq!(NumberFormat::Auto.format(999.0, 2), "999.00");
+        // 舍入后为零时不输出负号
+        assert_eq!(NumberFormat::EnUs.format(-0.001, 2), "0.00");
+        // 超过上限的位数按上限输出，不会使格式化失败
+        assert_eq!(NumberFormat::EnUs.format(1234.0, 70000), "1,234.000000000000000");
+    }
+
+    #[test]
+    fn test_strict_grouping_accepts_valid_groups() {
+        let strict = |f| CalcOptions::new(2, PercentRounding::DivideBy100ThenRound).with_number_format(f).with_strict_grouping(true);
+
+        assert_eq!(calculate_with("1,234,567.89 + 1", &strict(NumberFormat::EnUs)), Ok(1234568.89));
+        assert_eq!(calculate_with("1.234.567,89 + 1", &strict(NumberFormat::DeDe)), Ok(1234568.89));
+        assert_eq!(calculate_with("1 234 567,89 + 1", &strict(NumberFormat::FrFr)), Ok(1234568.89));
+        assert_eq!(calculate_with("12,34,567.89 + 1", &strict(NumberFormat::EnIn)), Ok(1234568.89));
+        assert_eq!(calculate_with("12,345 + 1", &strict(NumberFormat::EnIn)), Ok(12346.0));
+
+        // auto 格式下多个点号按千分位处理
+        assert_eq!(calculate_with("1.000.000 + 1", &strict(NumberFormat::Auto)), Ok(1000001.0));
+        assert_eq!(calculate_with("1,234.56 + 1.000,44", &strict(NumberFormat::Auto)), Ok(2235.0));
+    }
+
+    #[test]
+    fn test_strict_grouping_reports_position() {
+        let strict = |f| CalcOptions::new(2, PercentRounding::DivideBy100ThenRound).with_number_format(f).with_strict_grouping(true);
+
+        assert_eq!(calculate_with("1,23.4,5", &strict(NumberFormat::EnUs)), Err(CalcError::InvalidGrouping { pos: 1, ch: ',' }));
+        assert_eq!(calculate_with("10 + 1,2345", &strict(NumberFormat::EnUs)), Err(CalcError::InvalidGrouping { pos: 6, ch: ',' }));
+        assert_eq!(calculate_with("1,234.5,6", &strict(NumberFormat::EnUs)), Err(CalcError::InvalidGrouping { pos: 7, ch: ',' }));
+        assert_eq!(calculate_with("12 34 5", &strict(NumberFormat::FrFr)), Err(CalcError::InvalidGrouping { pos: 2, ch: ' ' }));
+        assert_eq!(calculate_with("1,234,567", &strict(NumberFormat::EnIn)), Err(CalcError::InvalidGrouping { pos: 1, ch: ',' }));
+        assert_eq!(calculate_with("1,234.5", &strict(NumberFormat::DeDe)), Err(CalcError::InvalidGrouping { pos: 5, ch: '.' }));
+        assert_eq!(calculate_with("1,234'567", &strict(NumberFormat::Auto)), Err(CalcError::InvalidGrouping { pos: 5, ch: '\'' }));
+    }
+
+    #[test]
+    fn test_strict_grouping_rejects_missing_operator() {
+        let strict = CalcOptions::new(0, PercentRounding::DivideBy100ThenRound).with_strict_grouping(true);
+
+        // 非严格模式下空格被当作千分位
+        assert_eq!(calculate("100 200", 0, PercentRounding::DivideBy100ThenRound), Ok(100200.0));
+        assert_eq!(calculate_with("100 200", &strict), Err(CalcError::InvalidExpression { span: Span::new(4, 7), expected: Expected::Operator }));
+        assert_eq!(calculate_with("100 + 200", &strict), Ok(300.0));
+    }
+
+    #[test]
+    fn test_error_positions_use_char_offsets() {
+        // 中文字符按一个字符计算位置
+        assert_eq!(calculate("１ + 2", 0, PercentRounding::DivideBy100ThenRound), Err(CalcError::InvalidCharacter { pos: 0, ch: '１' }));
+        assert_eq!(calculate("(1 + 2) * -", 0, PercentRounding::DivideBy100ThenRound), Err(CalcError::UnexpectedEndOfExpression { pos: 11, expected: Expected::Operand }));
+        assert_eq!(calculate("-50.126% / (1 - 1)", 2, PercentRounding::DivideBy100ThenRound), Err(CalcError::DivisionByZero { span: Span::new(11, 18) }));
+    }
+
+    #[test]
+    fn test_error_rendering() {
+        let err = calculate("1 + * 2", 0, PercentRounding::DivideBy100ThenRound).unwrap_err();
+        assert_eq!(err.render("1 + * 2"), "无效表达式: 第 5 个字符处期望操作数\n1 + * 2\n    ^");
+
+        let err = calculate("10 / (5 - 5)", 0, PercentRounding::DivideBy100ThenRound).unwrap_err();
+        assert_eq!(err.render("10 / (5 - 5)"), "除零错误: 第 6-12 个字符的除数为零\n10 / (5 - 5)\n     ^^^^^^^");
+
+        let err = calculate("1 +", 0, PercentRounding::DivideBy100ThenRound).unwrap_err();
+        assert_eq!(err.render("1 +"), "表达式意外结束: 期望操作数\n1 +\n   ^");
+
+        // 宽字符占两列，多行表达式只显示出错的行
+        assert_eq!(render_snippet("现金 @", Span::at(3)), "现金 @\n     ^");
+        assert_eq!(render_snippet("1 +\n2 @", Span::at(6)), "第 2 行: 2 @\n           ^");
+    }
+
+    #[test]
+    fn test_labels_do_not_affect_value() {
+        let options = CalcOptions::new(0, PercentRounding::DivideBy100ThenRound).with_number_format(NumberFormat::EnUs);
+        assert_eq!(calculate_with("1,000[货币资金] + 2,000[应收账款] + 500", &options), Ok(3500.0));
+        assert_eq!(calculate_with("1,000 #cash + 2,000 #ar", &options), calculate_with("1,000 + 2,000", &options));
+        assert!(validate_with("1000[1+1] * 2", 2000.0, &options));
+    }
+
+    #[test]
+    fn test_calculation_steps() {
+        let options = CalcOptions::new(2, PercentRounding::DivideBy100ThenRound);
+        let (result, steps) = calculate_steps("1,000.004[现金] - 12.5%", &options).unwrap();
+        assert_eq!(result, 999.87);
+        assert_eq!(
+            steps.iter().map(|s| s.to_string()).collect::<Vec<_>>(),
+            vec!["1,000.004[现金] → 1000", "12.5% → 0.13", "1000 - 0.13 = 999.87"]
+        );
+    }
+
+    #[test]
+    fn test_sum_and_ranges() {
+        let options = CalcOptions::new(2, PercentRounding::DivideBy100ThenRound);
+        let variables: Variables = [("B2", 100.0), ("B3", 250.5), ("$B$4", 10.0)].into_iter().map(|(name, value)| (name.to_string(), value)).collect();
+        assert_eq!(calculate_in("SUM(B2:B3, 1.004) - $B$4", &options, &variables), Ok(341.5));
+        assert_eq!(calculate_with("SUM(1, 2; 3)", &options), Ok(6.0));
+
+        let ast = parse("SUM(B2:B3)", &options).unwrap();
+        let mut steps = Vec::new();
+        evaluate_traced(&ast, &options, &variables, Some(&mut steps)).unwrap();
+        assert_eq!(steps.iter().map(|s| s.to_string()).collect::<Vec<_>>(), vec!["B2 → 100", "B3 → 250.5", "SUM(100, 250.5) = 350.5"]);
+        // 区域与逐个列出单元格的计算过程和舍入相同
+        let stepped = options.with_step_rounding(true);
+        let trace = |expr: &str| {
+            let mut steps = Vec::new();
+            let value = evaluate_traced(&parse(expr, &stepped).unwrap(), &stepped, &variables, Some(&mut steps)).unwrap();
+            (value, steps)
+        };
+        assert_eq!(trace("SUM(B2:B3, 0.333)"), trace("SUM(B2, B3, 0.333)"));
+        let err = calculate_in("ROUND(B2:B3, 1)", &options, &variables).unwrap_err();
+        assert_eq!(err.to_string(), "参数个数不符: 第 1 个字符处的 ROUND 需要 2 个参数，实际为 3 个");
+
+        let err = calculate_in("SUM(B2:B5)", &options, &variables).unwrap_err();
+        assert_eq!(err, CalcError::UndefinedVariable { name: "B4".to_string(), span: Span::new(4, 9) });
+        let err = calculate_with("avg(1)", &options).unwrap_err();
+        assert_eq!(err.render("avg(1)"), "未知的函数: 第 1 个字符处的 `avg`，支持的函数：SUM、ROUND\navg(1)\n^^^");
+    }
+
+    #[test]
+    fn test_round_function() {
+        let options = CalcOptions::new(4, PercentRounding::DivideBy100ThenRound);
+        assert_eq!(calculate_with("ROUND(2.345, 2)", &options), Ok(2.35));
+        assert_eq!(calculate_with("ROUND(-2.345, 2)", &options), Ok(-2.35));
+        assert_eq!(calculate_with("ROUND(1234.5, -2) + ROUND(2.5, 0.9)", &options), Ok(1203.0));
+        assert_eq!(calculate_with("round(10 / 3, 1) * 3", &options), Ok(9.9));
+        assert_eq!(calculate_with("ROUND(2.345, 10000000000)", &options), Ok(2.345));
+        let err = calculate_with("1 + ROUND(2.5)", &options).unwrap_err();
+        assert_eq!(err, CalcError::ArgumentCount { function: Function::Round, found: 1, span: Span::new(4, 14) });
+        assert_eq!(err.to_string(), "参数个数不符: 第 5 个字符处的 ROUND 需要 2 个参数，实际为 1 个");
+    }
+
+    #[test]
+    fn test_validate_with_tolerance() {
+        let options = CalcOptions::new(2, PercentRounding::DivideBy100ThenRound);
+        assert!(!validate_with("100.00 + 200.01", 300.0, &options));
+        assert!(validate_with("100.00 + 200.01", 300.0, &options.with_tolerance(Tolerance::Absolute(0.01))));
+        assert!(validate_with("100.00 + 200.01", 300.0, &options.with_tolerance(Tolerance::Ulp(1))));
+        assert!(!validate_with("100.00 + 200.01", 300.0, &options.with_tolerance(Tolerance::Relative(0.001))));
+
+        let check = check_with("1 / 3", 0.34, &options.with_tolerance(Tolerance::Ulp(2))).unwrap();
+        assert!(check.passed);
+        assert_eq!((check.difference, check.allowed), (-0.01, 0.02));
+    }
+
+    #[test]
+    fn test_evaluate_expected() {
+        let options = CalcOptions::new(2, PercentRounding::DivideBy100ThenRound).with_number_format(NumberFormat::EnUs);
+
+        // 单个数字不舍入，表达式与百分数按相同规则计算
+        assert_eq!(evaluate_expected("3.805", &options), Ok(3.805));
+        assert_eq!(evaluate_expected("-1,000.5", &options), Ok(-1000.5));
+        assert_eq!(evaluate_expected("500 + 2,500", &options), Ok(3000.0));
+        assert_eq!(evaluate_expected("1 / 3", &options), Ok(0.33));
+        assert_eq!(evaluate_expected("12.5%", &options), Ok(0.13));
+
+        // 必须完整解析
+        assert_eq!(
+            evaluate_expected("100abc", &options),
+            Err(CalcError::InvalidExpression { span: Span::new(3, 6), expected: Expected::Operator })
+        );
+        assert!(evaluate_expected("", &options).is_err());
+        assert!(evaluate_expected("100 200", &options.with_strict_grouping(true)).is_err());
+    }
+}
@@ -0,0 +1,131 @@
+//! # 单元格引用
+//!
+//! 电子表格的单元格地址写作列字母加行号，例如 `B2`、`AA10`；`$B$2` 这样的绝对引用按相同的单元格处理，
+//! 列字母不区分大小写。区域写作 `B2:B10`，按行优先的顺序列出其中的单元格。
+
+use std::fmt;
+
+use serde::{Serialize, Serializer};
+
+/// 最大的列号（XFD）和行号，与 Excel 相同
+const MAX_COLUMN: u32 = 16_384;
+const MAX_ROW: u32 = 1_048_576;
+
+/// 单元格地址，列和行都从 1 开始
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CellRef {
+    pub column: u32,
+    pub row: u32,
+}
+
+/// 单元格区域，`start` 在左上角，`end` 在右下角
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CellRange {
+    pub start: CellRef,
+    pub end: CellRef,
+}
+
+impl CellRef {
+    pub fn new(column: u32, row: u32) -> Self {
+        CellRef { column, row }
+    }
+
+    /// 解析 `B2`、`b2` 或 `$B$2`，不是单元格地址时返回 `None`
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        let rest = text.strip_prefix('$').unwrap_or(text);
+        let letters = rest.find(|c: char| !c.is_ascii_alphabetic())?;
+        let (column, rest) = rest.split_at(letters);
+        let row = rest.strip_prefix('$').unwrap_or(rest);
+        if column.is_empty() || column.len() > 3 || row.is_empty() || !row.bytes().all(|b| b.is_ascii_digit()) || row.starts_with('0') {
+            return None;
+        }
+        let column = column.bytes().fold(0, |number, letter| number * 26 + u32::from(letter.to_ascii_uppercase() - b'A' + 1));
+        let row = row.parse().ok()?;
+        (column <= MAX_COLUMN && row <= MAX_ROW).then_some(CellRef { column, row })
+    }
+}
+
+impl CellRange {
+    /// 两个角可以按任意顺序给出
+    pub fn new(from: CellRef, to: CellRef) -> Self {
+        CellRange {
+            start: CellRef::new(from.column.min(to.column), from.row.min(to.row)),
+            end: CellRef::new(from.column.max(to.column), from.row.max(to.row)),
+        }
+    }
+
+    /// 按行优先的顺序列出区域中的单元格
+    pub fn cells(&self) -> impl Iterator<Item = CellRef> + '_ {
+        (self.start.row..=self.end.row).flat_map(move |row| (self.start.column..=self.end.column).map(move |column| CellRef::new(column, row)))
+    }
+
+    pub fn contains(&self, cell: CellRef) -> bool {
+        (self.start.column..=self.end.column).contains(&cell.column) && (self.start.row..=self.end.row).contains(&cell.row)
+    }
+}
+
+/// 列号对应的字母，例如 1 → A、27 → AA
+pub fn column_name(mut column: u32) -> String {
+    let mut letters = Vec::new();
+    while column > 0 {
+        let rem = (column - 1) % 26;
+        letters.push(b'A' + rem as u8);
+        column = (column - 1) / 26;
+    }
+    letters.reverse();
+    String::from_utf8(letters).unwrap_or_default()
+}
+
+impl fmt::Display for CellRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", column_name(self.column), self.row)
+    }
+}
+
+impl fmt::Display for CellRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.start, self.end)
+    }
+}
+
+/// 语法树中的区域序列化为 `B2:B10`
+impl Serialize for CellRange {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(CellRef::parse("B2"), Some(CellRef::new(2, 2)));
+        assert_eq!(CellRef::parse("$aa$10"), Some(CellRef::new(27, 10)));
+        assert_eq!(CellRef::parse("XFD1048576"), Some(CellRef::new(16_384, 1_048_576)));
+        for text in ["B", "2", "B0", "B02", "XFE1", "ABCD1", "B2C", "流动资产", "B-2", "$$B2"] {
+            assert_eq!(CellRef::parse(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(CellRef::new(1, 1).to_string(), "A1");
+        assert_eq!(CellRef::new(26, 3).to_string(), "Z3");
+        assert_eq!(CellRef::new(27, 10).to_string(), "AA10");
+        assert_eq!(CellRef::new(16_384, 7).to_string(), "XFD7");
+    }
+
+    #[test]
+    fn test_range() {
+        let range = CellRange::new(CellRef::parse("C3").unwrap(), CellRef::parse("B2").unwrap());
+        assert_eq!(range.to_string(), "B2:C3");
+        let cells: Vec<String> = range.cells().map(|cell| cell.to_string()).collect();
+        assert_eq!(cells, vec!["B2", "C2", "B3", "C3"]);
+        assert!(range.contains(CellRef::new(3, 2)));
+        assert!(!range.contains(CellRef::new(1, 2)));
+        assert_eq!(serde_json::to_value(range).unwrap(), "B2:C3");
+    }
+}
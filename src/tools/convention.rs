@@ -0,0 +1,168 @@
+//! # 舍入口径搜索
+//!
+//! 客户报表中的数字往往由电子表格算出，但表格采用的舍入口径未知。给定算式和报表上的数字，
+//! 本模块穷举以下设置的组合，列出能精确复现该数字的全部口径：
+//!
+//! - 小数位数 `0..=max_decimals`
+//! - 舍入方式（`RoundingMode`）
+//! - 百分数处理策略（仅当算式包含百分数且预先舍入时）
+//! - 是否预先舍入（规则1）
+//! - 是否逐步舍入
+
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::tools::calculator::{calculate_with, CalcError, CalcOptions, PercentRounding, RoundingMode};
+use crate::tools::parser::parse;
+
+/// 能复现目标数字的一组设置
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct Convention {
+    pub decimals: u32,
+    pub rounding_mode: RoundingMode,
+    /// 算式不含百分数或不预先舍入时为 `None`
+    pub percent_rounding: Option<PercentRounding>,
+    pub pre_round: bool,
+    pub step_rounding: bool,
+}
+
+/// 列出所有能精确得到 `reported` 的口径，按小数位数从小到大排列
+///
+/// `base` 提供数字格式等与舍入无关的设置。算式本身无法解析时返回错误；某个口径下出现的计算错误
+/// （例如舍入后除数为零）只排除该口径。
+pub fn search(expr: &str, reported: f64, max_decimals: u32, base: &CalcOptions) -> Result<Vec<Convention>, CalcError> {
+    let ast = parse(expr, base)?;
+    let percent_strategies = if ast.literals().iter().any(|literal| literal.percent) {
+        vec![Some(PercentRounding::DivideBy100ThenRound), Some(PercentRounding::RoundThenDivideBy100)]
+    } else {
+        vec![None]
+    };
+
+    let mut conventions = Vec::new();
+    for decimals in 0..=max_decimals {
+        for rounding_mode in RoundingMode::ALL {
+            for pre_round in [true, false] {
+                // 不预先舍入时百分数直接除以 100，两种策略没有区别
+                let strategies = if pre_round { &percent_strategies[..] } else { &[None] };
+                for &percent_rounding in strategies {
+                    for step_rounding in [false, true] {
+                        let convention = Convention {
+                            decimals,
+                            rounding_mode,
+                            percent_rounding,
+                            pre_round,
+                            step_rounding,
+                        };
+                        let options = convention.apply(base);
+                        if calculate_with(expr, &options).is_ok_and(|actual| options.tolerance.check(actual, reported, decimals).passed) {
+                            conventions.push(convention);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(conventions)
+}
+
+impl Convention {
+    /// 在 `base` 的基础上应用这组舍入设置，验证容差固定为精确匹配
+    pub fn apply(&self, base: &CalcOptions) -> CalcOptions {
+        CalcOptions {
+            decimals: self.decimals,
+            percent_rounding: self.percent_rounding.unwrap_or(base.percent_rounding),
+            tolerance: Default::default(),
+            ..*base
+        }
+        .with_rounding_mode(self.rounding_mode)
+        .with_pre_round(self.pre_round)
+        .with_step_rounding(self.step_rounding)
+    }
+}
+
+impl fmt::Display for Convention {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "小数位数 {}，{}", self.decimals, self.rounding_mode.description())?;
+        if let Some(percent_rounding) = self.percent_rounding {
+            write!(f, "，百分数{}", percent_rounding.description())?;
+        }
+        write!(
+            f,
+            "，{}，{}",
+            if self.pre_round { "预先舍入" } else { "不预先舍入" },
+            if self.step_rounding { "逐步舍入" } else { "只舍入最终结果" }
+        )
+    }
+}
+
+impl Serialize for Convention {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::calculator::round_with;
+
+    fn base() -> CalcOptions {
+        CalcOptions::new(2, PercentRounding::DivideBy100ThenRound)
+    }
+
+    #[test]
+    fn test_rounding_modes() {
+        assert_eq!(round_with(2.5, 0, RoundingMode::HalfUp), 3.0);
+        assert_eq!(round_with(2.5, 0, RoundingMode::HalfEven), 2.0);
+        assert_eq!(round_with(-2.5, 0, RoundingMode::HalfEven), -2.0);
+        assert_eq!(round_with(0.29, 2, RoundingMode::Down), 0.29);
+        assert_eq!(round_with(-1.99, 0, RoundingMode::Down), -1.0);
+
+        // 1.15 * 100 = 114.99999999999999，1.005 * 100 = 100.49999999999999：三种方式对尾差的处理一致
+        for mode in [RoundingMode::HalfUp, RoundingMode::HalfEven, RoundingMode::Down] {
+            assert_eq!(round_with(1.15, 2, mode), 1.15);
+            assert_eq!(round_with(1.005, 2, mode), 1.0);
+        }
+    }
+
+    #[test]
+    fn test_search_finds_truncation() {
+        // 10.333… 在 2 位小数下三种舍入方式都得到 10.33
+        let conventions = search("31 / 3", 10.33, 2, &base()).unwrap();
+        assert_eq!(conventions.len(), 12);
+        assert!(conventions.iter().all(|c| c.decimals == 2));
+
+        // 0.666… 只有截断得到 0.66
+        let conventions = search("2 / 3", 0.66, 4, &base()).unwrap();
+        assert_eq!(conventions.len(), 4);
+        assert!(conventions.iter().all(|c| c.decimals == 2 && c.rounding_mode == RoundingMode::Down));
+    }
+
+    #[test]
+    fn test_search_distinguishes_step_rounding() {
+        // 逐步舍入: 1 / 3 → 0.33，0.33 * 3 = 0.99；只舍入最终结果时为 1
+        let conventions = search("1 / 3 * 3", 0.99, 4, &base()).unwrap();
+        assert_eq!(conventions.len(), 6);
+        assert!(conventions.iter().all(|c| c.decimals == 2 && c.step_rounding));
+
+        // 预先舍入时 0.5 + 0.5 + 0.5 = 3（四舍五入）或 0（银行家舍入），只有不预先舍入才得到 2
+        let conventions = search("0.5 + 0.5 + 0.5", 2.0, 0, &base()).unwrap();
+        let display: Vec<String> = conventions.iter().map(|c| c.to_string()).collect();
+        assert!(display.contains(&"小数位数 0，四舍五入，不预先舍入，只舍入最终结果".to_string()));
+        assert!(conventions.iter().all(|c| !c.pre_round && c.rounding_mode != RoundingMode::Down));
+    }
+
+    #[test]
+    fn test_search_includes_percent_strategy() {
+        let conventions = search("1000 * 12.345%", 123.5, 2, &base()).unwrap();
+        assert!(!conventions.is_empty());
+        // 预先舍入时只有先舍入后除以100能得到 123.5；不预先舍入时 123.45 在 1 位小数下四舍五入为 123.5
+        assert!(conventions
+            .iter()
+            .filter(|c| c.pre_round)
+            .all(|c| c.percent_rounding == Some(PercentRounding::RoundThenDivideBy100)));
+        assert!(conventions.iter().filter(|c| !c.pre_round).all(|c| c.percent_rounding.is_none() && c.decimals == 1));
+        assert!(search("1 +", 1.0, 2, &base()).is_err());
+    }
+}
@@ -0,0 +1,231 @@
+//! # CSV 报表
+//!
+//! 报表常以 CSV 导出。`validate_csv` 读取的文件第一行是表头，包含以下列（列名不区分大小写，顺序任意，其余的列忽略）：
+//!
+//! | 列 | 别名 | 含义 |
+//! |---|---|---|
+//! | `label` | `项目`、`名称` | 项目名称，公式通过 `{项目}` 引用 |
+//! | `amount` | `金额` | 报表金额 |
+//! | `formula` | `公式` | 可选，例如 `{流动资产合计} + {非流动资产合计}` |
+//!
+//! 有公式的行按 `batch_validate` 的规则验证：公式的计算结果应等于金额。没有公式的行只提供数值，供后面的公式引用。
+//!
+//! 字段按 RFC 4180 解析：带引号的字段中可以包含分隔符、换行和 `""`（表示一个引号）。分隔符根据表头自动识别为
+//! `,`、`;` 或制表符，以 `;` 分隔的文件通常使用 `1.234,56` 这样的数字格式。
+
+use std::io::BufRead;
+
+use crate::tools::batch::{BatchItem, BatchLine};
+
+/// 可以识别的分隔符
+const DELIMITERS: [char; 3] = [',', ';', '\t'];
+
+/// 各列的名称及别名
+const LABEL_COLUMNS: [&str; 3] = ["label", "项目", "名称"];
+const AMOUNT_COLUMNS: [&str; 2] = ["amount", "金额"];
+const FORMULA_COLUMNS: [&str; 2] = ["formula", "公式"];
+
+/// 一条记录及其起始行号
+#[derive(Debug, PartialEq, Clone)]
+pub struct Record {
+    pub line: u32,
+    pub fields: Vec<String>,
+}
+
+/// 逐条读取 CSV 记录，带引号的字段可以跨行
+pub struct CsvReader<R> {
+    reader: R,
+    delimiter: Option<char>,
+    line: u32,
+}
+
+impl<R: BufRead> CsvReader<R> {
+    /// 分隔符在读取第一条记录时识别
+    pub fn new(reader: R) -> Self {
+        CsvReader { reader, delimiter: None, line: 0 }
+    }
+
+    /// 读取下一条记录，文件结束时返回 `None`
+    pub fn next_record(&mut self) -> Result<Option<Record>, String> {
+        let mut text = String::new();
+        let start = self.line + 1;
+        loop {
+            let mut physical = String::new();
+            let read = self.reader.read_line(&mut physical).map_err(|e| format!("第 {} 行: {}", self.line + 1, e))?;
+            if read == 0 {
+                if text.is_empty() {
+                    return Ok(None);
+                }
+                return Err(format!("第 {} 行: 引号没有闭合", start));
+            }
+            self.line += 1;
+            if start == 1 && text.is_empty() {
+                physical = physical.trim_start_matches('\u{feff}').to_string();
+            }
+            text.push_str(&physical);
+            // 引号个数为偶数时记录结束
+            if text.matches('"').count().is_multiple_of(2) {
+                break;
+            }
+        }
+        let text = text.trim_end_matches(['\n', '\r']);
+        let delimiter = *self.delimiter.get_or_insert_with(|| detect_delimiter(text));
+        Ok(Some(Record { line: start, fields: split_record(text, delimiter) }))
+    }
+}
+
+/// 引号外出现次数最多的分隔符，都没有时为 `,`
+fn detect_delimiter(text: &str) -> char {
+    let mut counts = [0; DELIMITERS.len()];
+    let mut quoted = false;
+    for c in text.chars() {
+        if c == '"' {
+            quoted = !quoted;
+        } else if let Some(index) = DELIMITERS.iter().position(|&delimiter| delimiter == c).filter(|_| !quoted) {
+            counts[index] += 1;
+        }
+    }
+    let (index, _) = counts.iter().enumerate().rev().max_by_key(|&(_, count)| count).unwrap_or((0, &0));
+    if counts[index] == 0 { ',' } else { DELIMITERS[index] }
+}
+
+/// 拆分一条完整的记录
+fn split_record(text: &str, delimiter: char) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            c if c == delimiter && !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+/// 表头中各列的位置
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Columns {
+    label: usize,
+    amount: usize,
+    formula: Option<usize>,
+}
+
+/// 一行报表
+#[derive(Debug, PartialEq, Clone)]
+pub enum StatementRow {
+    /// 空行
+    Blank,
+    /// 没有公式的行：项目名称和金额
+    Value { label: String, amount: String },
+    /// 有公式的行，转换为待验证的一行
+    Check(Result<BatchLine, String>),
+}
+
+impl Columns {
+    /// 从表头识别各列
+    pub fn from_header(header: &Record) -> Result<Self, String> {
+        let find = |names: &[&str]| {
+            header.fields.iter().position(|field| names.iter().any(|name| field.trim().eq_ignore_ascii_case(name)))
+        };
+        let missing = |names: &[&str]| format!("表头中缺少 {} 列（也可以写作 {}）", names[0], names[1..].join("、"));
+        Ok(Columns {
+            label: find(&LABEL_COLUMNS).ok_or_else(|| missing(&LABEL_COLUMNS))?,
+            amount: find(&AMOUNT_COLUMNS).ok_or_else(|| missing(&AMOUNT_COLUMNS))?,
+            formula: find(&FORMULA_COLUMNS),
+        })
+    }
+
+    /// 转换一条记录；缺少的字段按空字段处理
+    pub fn row(&self, record: &Record) -> StatementRow {
+        let field = |index: Option<usize>| index.and_then(|index| record.fields.get(index)).map_or("", |field| field.trim());
+        let (label, amount) = (field(Some(self.label)), field(Some(self.amount)));
+        // 公式可以像电子表格一样以 `=` 开头
+        let formula = field(self.formula);
+        let formula = formula.strip_prefix('=').unwrap_or(formula).trim();
+
+        if !formula.is_empty() {
+            let item = BatchItem {
+                id: None,
+                expression: formula.to_string(),
+                expected: (!amount.is_empty()).then(|| amount.to_string()),
+                label: Some(label.to_string()),
+                decimals: None,
+                percent_rounding: None,
+                tolerance: None,
+            };
+            return StatementRow::Check(BatchLine::from_item(&item));
+        }
+        match (label.is_empty(), amount.is_empty()) {
+            (true, true) => StatementRow::Blank,
+            (true, false) => StatementRow::Check(Err("缺少项目名称".to_string())),
+            (false, true) => StatementRow::Check(Err(format!("[{}] 缺少金额", label))),
+            (false, false) => StatementRow::Value { label: label.to_string(), amount: amount.to_string() },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(text: &str) -> Result<Vec<Record>, String> {
+        let mut reader = CsvReader::new(text.as_bytes());
+        let mut records = Vec::new();
+        while let Some(record) = reader.next_record()? {
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    fn fields(record: &Record) -> Vec<&str> {
+        record.fields.iter().map(String::as_str).collect()
+    }
+
+    #[test]
+    fn test_read_records() {
+        let records = read("\u{feff}label,amount,formula\r\n货币资金,\"1,000.00\",\n\"备注\n第二行\",\"说\"\"明\"\"\",\n").unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(fields(&records[0]), vec!["label", "amount", "formula"]);
+        assert_eq!(fields(&records[1]), vec!["货币资金", "1,000.00", ""]);
+        assert_eq!((records[2].line, fields(&records[2])), (3, vec!["备注\n第二行", "说\"明\"", ""]));
+        assert_eq!(read("a,b\n\"c,d\n").unwrap_err(), "第 2 行: 引号没有闭合");
+    }
+
+    #[test]
+    fn test_detect_delimiter() {
+        assert_eq!(detect_delimiter("项目;金额;公式"), ';');
+        assert_eq!(detect_delimiter("label\tamount"), '\t');
+        assert_eq!(detect_delimiter("\"a;b\",amount"), ',');
+        assert_eq!(detect_delimiter("label"), ',');
+        let records = read("项目;金额\n货币资金;1.000,00\n").unwrap();
+        assert_eq!(fields(&records[1]), vec!["货币资金", "1.000,00"]);
+    }
+
+    #[test]
+    fn test_columns() {
+        let header = Record { line: 1, fields: vec!["备注".into(), " Amount ".into(), "项目".into(), "公式".into()] };
+        let columns = Columns::from_header(&header).unwrap();
+        assert_eq!(columns, Columns { label: 2, amount: 1, formula: Some(3) });
+        let header = Record { line: 1, fields: vec!["label".into()] };
+        assert_eq!(Columns::from_header(&header).unwrap_err(), "表头中缺少 amount 列（也可以写作 金额）");
+
+        let row = |fields: &[&str]| columns.row(&Record { line: 2, fields: fields.iter().map(|field| field.to_string()).collect() });
+        assert_eq!(row(&["", "100", "现金"]), StatementRow::Value { label: "现金".into(), amount: "100".into() });
+        assert_eq!(row(&[]), StatementRow::Blank);
+        assert_eq!(row(&["", "100"]), StatementRow::Check(Err("缺少项目名称".to_string())));
+        assert_eq!(row(&["", "", "现金"]), StatementRow::Check(Err("[现金] 缺少金额".to_string())));
+        let StatementRow::Check(Ok(line)) = row(&["", "300", "合计", "={现金} + {存货}"]) else { panic!() };
+        assert_eq!((line.expression.as_str(), line.expected.as_str(), line.label.as_str()), ("{现金} + {存货}", "300", "合计"));
+        // 等式形式的公式不需要金额
+        let StatementRow::Check(Ok(line)) = row(&["", "", "比率", "{现金} / {存货} >= 1"]) else { panic!() };
+        assert_eq!(line.expected, ">= 1");
+    }
+}
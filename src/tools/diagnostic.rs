@@ -0,0 +1,134 @@
+//! # 错误定位
+//!
+//! 计算错误携带出错位置（以字符为单位的偏移，从 0 开始）。本模块负责把位置渲染成
+//! 带插入符 `^` 的代码片段，便于在长表达式中找到出错的地方：
+//!
+//! ```text
+//! 1 + * 2
+//!     ^
+//! ```
+
+use std::fmt;
+
+/// 表达式中的一段区间，`start..end` 为字符偏移（左闭右开）
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default, serde::Serialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    /// 单个字符的区间
+    pub fn at(pos: usize) -> Self {
+        Self::new(pos, pos + 1)
+    }
+
+    /// 覆盖两个区间的最小区间
+    pub fn to(self, other: Span) -> Self {
+        Self::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+/// 出错位置期望出现的内容
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Expected {
+    /// 数字、负号或 `(`
+    Operand,
+    /// `+ - * /`
+    Operator,
+    /// 运算符或闭合的 `)`
+    OperatorOrRightParen,
+    /// 闭合操作数标记的 `]`
+    LabelEnd,
+    /// 闭合标记引用的 `}`
+    ReferenceEnd,
+    /// 区域两端的单元格地址
+    Cell,
+    /// 函数参数之间的 `,`、`;` 或闭合的 `)`
+    ArgumentSeparator,
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expected::Operand => write!(f, "操作数"),
+            Expected::Operator => write!(f, "运算符"),
+            Expected::OperatorOrRightParen => write!(f, "运算符或 `)`"),
+            Expected::LabelEnd => write!(f, "`]`"),
+            Expected::ReferenceEnd => write!(f, "`}}`"),
+            Expected::Cell => write!(f, "单元格地址（如 B2）"),
+            Expected::ArgumentSeparator => write!(f, "`,`、`;` 或 `)`"),
+        }
+    }
+}
+
+/// 片段中出错位置两侧最多保留的字符数，超出部分用省略号代替
+const CONTEXT_CHARS: usize = 30;
+
+/// 渲染出错位置：第一行是表达式所在行的片段，第二行在出错区间下方标注 `^`
+pub fn render_snippet(expr: &str, span: Span) -> String {
+    // 定位出错位置所在的行，多行表达式只显示该行
+    let mut line_start = 0;
+    let mut line_no = 1;
+    for (offset, c) in expr.chars().enumerate() {
+        if offset >= span.start {
+            break;
+        }
+        if c == '\n' {
+            line_start = offset + 1;
+            line_no += 1;
+        }
+    }
+    let line: Vec<char> = expr.chars().skip(line_start).take_while(|&c| c != '\n').collect();
+    let is_multiline = expr.contains('\n');
+
+    let col = span.start - line_start;
+    let width = span.end.saturating_sub(span.start).max(1);
+
+    // 长行只保留出错位置附近的字符
+    let from = col.saturating_sub(CONTEXT_CHARS);
+    let to = (col + width + CONTEXT_CHARS).min(line.len());
+    let mut snippet = String::new();
+    let mut marker = String::new();
+    if from > 0 {
+        snippet.push('…');
+        marker.push(' ');
+    }
+    for (i, &c) in line.iter().enumerate().take(to).skip(from) {
+        snippet.push(c);
+        let fill = if (col..col + width).contains(&i) { '^' } else { ' ' };
+        if i < col + width {
+            // 制表符原样保留以便对齐，宽字符占两列
+            if c == '\t' && fill == ' ' {
+                marker.push('\t');
+            } else {
+                marker.extend(std::iter::repeat_n(fill, char_width(c)));
+            }
+        }
+    }
+    if to < line.len() {
+        snippet.push('…');
+    }
+    // 区间落在行尾（例如表达式意外结束）时补上插入符
+    if col >= line.len() {
+        marker.push('^');
+    }
+
+    if is_multiline {
+        format!("第 {line_no} 行: {snippet}\n{}{marker}", " ".repeat(format!("第 {line_no} 行: ").chars().map(char_width).sum()))
+    } else {
+        format!("{snippet}\n{marker}")
+    }
+}
+
+/// 字符在等宽终端中占用的列数，中日韩字符和全角符号占两列
+fn char_width(c: char) -> usize {
+    match c as u32 {
+        0x1100..=0x115F | 0x2E80..=0xA4CF | 0xAC00..=0xD7A3 | 0xF900..=0xFAFF | 0xFE30..=0xFE4F | 0xFF00..=0xFF60 | 0xFFE0..=0xFFE6 => 2,
+        _ => 1,
+    }
+}
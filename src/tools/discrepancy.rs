@@ -0,0 +1,192 @@
+//! # 差异分析
+//!
+//! 验证失败时，复核人员首先要判断差异是否只是舍入造成的尾差。本模块计算带符号的差异、
+//! 差异相当于多少个最后一位小数的单位，并在以下口径下重新计算，看预期值是否能够对上：
+//!
+//! - 关闭规则1的预先舍入，只对最终结果舍入；
+//! - 表达式包含百分数时，改用另一种百分数处理策略。
+//!
+//! 任一口径下与预期值一致（在所设容差内），就认为差异可以由舍入解释。
+
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::tools::calculator::{calculate_in, round_value, CalcError, CalcOptions, Variables};
+use crate::tools::parser::parse;
+
+/// 末位单位数保留的小数位数
+const ULP_DECIMALS: u32 = 6;
+
+/// 差异方向
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// 实际值大于预期值
+    Over,
+    /// 实际值小于预期值
+    Under,
+    /// 没有差异
+    None,
+}
+
+/// 另一种计算口径下的结果
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Alternative {
+    /// 口径说明
+    pub description: String,
+    /// 该口径下的计算结果
+    pub actual: f64,
+    /// 是否与预期值一致
+    pub matches: bool,
+}
+
+/// 一次验证的差异分析
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Discrepancy {
+    pub actual: f64,
+    pub expected: f64,
+    /// 实际值减预期值
+    pub difference: f64,
+    /// 差异相当于多少个最后一位小数的单位
+    pub ulps: f64,
+    pub direction: Direction,
+    pub alternatives: Vec<Alternative>,
+}
+
+impl Discrepancy {
+    /// 差异是否可以由舍入解释（尾差）
+    pub fn explained_by_rounding(&self) -> bool {
+        self.alternatives.iter().any(|alternative| alternative.matches)
+    }
+
+    /// 单行摘要，用于批量验证的输出
+    pub fn summary(&self) -> String {
+        let conclusion = match self.alternatives.iter().find(|alternative| alternative.matches) {
+            Some(alternative) => format!("{}后一致，属于尾差", alternative.description),
+            None => "不能由舍入解释".to_string(),
+        };
+        format!("{} 个末位单位，{}", self.ulps, conclusion)
+    }
+}
+
+/// 分析算式结果与预期值之间的差异
+pub fn analyze(expr: &str, expected: f64, options: &CalcOptions) -> Result<Discrepancy, CalcError> {
+    analyze_in(expr, expected, options, &Variables::new())
+}
+
+/// 分析可以引用变量的算式
+pub fn analyze_in(expr: &str, expected: f64, options: &CalcOptions, variables: &Variables) -> Result<Discrepancy, CalcError> {
+    let actual = calculate_in(expr, options, variables)?;
+    let difference = round_value(actual - expected, options.decimals + ULP_DECIMALS);
+    let ulps = round_value(difference * 10f64.powi(options.decimals as i32), ULP_DECIMALS);
+    let direction = if difference == 0.0 {
+        Direction::None
+    } else if difference > 0.0 {
+        Direction::Over
+    } else {
+        Direction::Under
+    };
+
+    let mut candidates = Vec::new();
+    if options.pre_round {
+        candidates.push(("关闭预先舍入（规则1）".to_string(), options.with_pre_round(false)));
+    }
+    let has_percent = parse(expr, options).is_ok_and(|ast| ast.literals().iter().any(|literal| literal.percent));
+    if has_percent {
+        let other = options.percent_rounding.other();
+        candidates.push((format!("改用{}", other.description()), CalcOptions { percent_rounding: other, ..*options }));
+    }
+
+    let mut alternatives = Vec::new();
+    for (description, candidate) in candidates {
+        let actual = calculate_in(expr, &candidate, variables)?;
+        let matches = candidate.tolerance.check(actual, expected, candidate.decimals).passed;
+        alternatives.push(Alternative { description, actual, matches });
+    }
+
+    Ok(Discrepancy {
+        actual,
+        expected,
+        difference,
+        ulps,
+        direction,
+        alternatives,
+    })
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Direction::Over => write!(f, "实际值大于预期值"),
+            Direction::Under => write!(f, "实际值小于预期值"),
+            Direction::None => write!(f, "无差异"),
+        }
+    }
+}
+
+impl fmt::Display for Discrepancy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "差异分析:")?;
+        writeln!(f, "  差异: {:+}（{}），合 {} 个末位单位", self.difference, self.direction, self.ulps)?;
+        for alternative in &self.alternatives {
+            writeln!(
+                f,
+                "  {}: {} → {}",
+                alternative.description,
+                alternative.actual,
+                if alternative.matches { "与预期一致" } else { "仍不一致" }
+            )?;
+        }
+        if self.explained_by_rounding() {
+            write!(f, "  结论: 差异可以由舍入解释（尾差）")
+        } else {
+            write!(f, "  结论: 差异不能由舍入解释")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::calculator::PercentRounding;
+
+    fn options(decimals: u32) -> CalcOptions {
+        CalcOptions::new(decimals, PercentRounding::DivideBy100ThenRound)
+    }
+
+    #[test]
+    fn test_rounding_difference() {
+        // 逐项舍入后合计为 3，未舍入的合计为 4.2 → 舍入为 4；预期值按未舍入口径计算
+        let discrepancy = analyze("1.4 + 1.4 + 1.4", 4.0, &options(0)).unwrap();
+        assert_eq!(discrepancy.actual, 3.0);
+        assert_eq!(discrepancy.difference, -1.0);
+        assert_eq!(discrepancy.ulps, -1.0);
+        assert_eq!(discrepancy.direction, Direction::Under);
+        assert_eq!(discrepancy.alternatives.len(), 1);
+        assert_eq!(discrepancy.alternatives[0].actual, 4.0);
+        assert!(discrepancy.explained_by_rounding());
+        assert_eq!(discrepancy.summary(), "-1 个末位单位，关闭预先舍入（规则1）后一致，属于尾差");
+    }
+
+    #[test]
+    fn test_percent_strategy_difference() {
+        // 先除以100后舍入: 0.12345 → 0.12；先舍入后除以100: 12.35 → 0.1235；不预先舍入: 123.45
+        let discrepancy = analyze("1000 * 12.345%", 123.5, &options(2)).unwrap();
+        assert_eq!(discrepancy.actual, 120.0);
+        assert_eq!(discrepancy.direction, Direction::Under);
+        assert_eq!(discrepancy.alternatives.len(), 2);
+        assert!(!discrepancy.alternatives[0].matches);
+        assert!(discrepancy.alternatives[1].matches);
+        assert!(discrepancy.to_string().contains("改用先舍入后除以100: 123.5 → 与预期一致"));
+    }
+
+    #[test]
+    fn test_unexplained_difference() {
+        let discrepancy = analyze("100 + 200", 310.0, &options(2)).unwrap();
+        assert_eq!(discrepancy.difference, -10.0);
+        assert_eq!(discrepancy.ulps, -1000.0);
+        assert!(!discrepancy.explained_by_rounding());
+        assert!(discrepancy.to_string().ends_with("结论: 差异不能由舍入解释"));
+    }
+}
@@ -0,0 +1,143 @@
+//! # 文件输入
+//!
+//! 十万行以上的账簿不适合放进一个 JSON 数组，`batch_validate` 可以改为从本地文件逐块读取。
+//! 服务器只读取启动时用 `--allow-dir` 指定的目录（及其子目录）和客户端通过 MCP roots 提供的目录中的文件；
+//! 两者都没有时不允许读取文件。
+
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+
+/// 允许读取的目录
+#[derive(Debug, Clone, Default)]
+pub struct FileAccess {
+    dirs: Vec<PathBuf>,
+}
+
+impl FileAccess {
+    /// 目录不存在时返回错误
+    pub fn new(dirs: &[PathBuf]) -> io::Result<Self> {
+        let dirs = dirs.iter().map(|dir| dir.canonicalize()).collect::<io::Result<_>>()?;
+        Ok(FileAccess { dirs })
+    }
+
+    /// 加上客户端提供的 roots；不是 `file://` 的 URI 和不存在的目录忽略
+    pub fn with_roots<'a>(&self, uris: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut access = self.clone();
+        for uri in uris {
+            if let Some(dir) = file_uri_path(uri).and_then(|path| path.canonicalize().ok()) {
+                if !access.dirs.contains(&dir) {
+                    access.dirs.push(dir);
+                }
+            }
+        }
+        access
+    }
+
+    /// 打开文件；相对路径依次在允许的目录中查找
+    pub fn open(&self, path: &str) -> Result<BufReader<File>, String> {
+        if self.dirs.is_empty() {
+            return Err("未启用文件输入：启动服务器时用 --allow-dir 指定允许读取的目录，或由客户端提供 roots".to_string());
+        }
+        let path = Path::new(path.trim());
+        let candidates: Vec<PathBuf> = if path.is_absolute() {
+            vec![path.to_path_buf()]
+        } else {
+            self.dirs.iter().map(|dir| dir.join(path)).collect()
+        };
+        // 比较解析符号链接和 `..` 之后的路径；某个目录中的候选经符号链接逃出允许的目录时，继续查找其余的目录
+        let mut escaped = false;
+        for resolved in candidates.iter().filter_map(|candidate| candidate.canonicalize().ok()) {
+            if !self.dirs.iter().any(|dir| resolved.starts_with(dir)) {
+                escaped = true;
+                continue;
+            }
+            return File::open(&resolved)
+                .map(BufReader::new)
+                .map_err(|e| format!("无法打开文件 '{}': {}", path.display(), e));
+        }
+        if escaped {
+            Err(format!("文件 '{}' 不在允许读取的目录中", path.display()))
+        } else {
+            Err(format!("找不到文件 '{}'", path.display()))
+        }
+    }
+}
+
+/// `file://` URI 对应的路径，`%XX` 转义的字符会被还原
+fn file_uri_path(uri: &str) -> Option<PathBuf> {
+    let rest = uri.strip_prefix("file://")?;
+    let path = rest.strip_prefix("localhost").unwrap_or(rest);
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut chars = path.bytes();
+    while let Some(byte) = chars.next() {
+        if byte == b'%' {
+            let hex = [chars.next()?, chars.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    Some(PathBuf::from(String::from_utf8(bytes).ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open() {
+        let root = std::env::temp_dir().join(format!("acc_calc_files_{}", std::process::id()));
+        let allowed = root.join("allowed");
+        std::fs::create_dir_all(&allowed).unwrap();
+        std::fs::write(allowed.join("ledger.txt"), "1 + 2|3\n").unwrap();
+        std::fs::write(root.join("secret.txt"), "1|1\n").unwrap();
+
+        let access = FileAccess::new(std::slice::from_ref(&allowed)).unwrap();
+        assert!(access.open("ledger.txt").is_ok());
+        assert!(access.open(allowed.join("ledger.txt").to_str().unwrap()).is_ok());
+        assert!(access.open("../secret.txt").unwrap_err().contains("不在允许读取的目录中"));
+        assert!(access.open(root.join("secret.txt").to_str().unwrap()).is_err());
+        assert!(access.open("missing.txt").unwrap_err().starts_with("找不到文件"));
+        assert!(FileAccess::default().open("ledger.txt").unwrap_err().contains("--allow-dir"));
+        assert!(FileAccess::new(&[root.join("missing")]).is_err());
+
+        // roots 中的目录同样允许读取
+        let uri = format!("file://{}", root.to_str().unwrap().replace(' ', "%20"));
+        let access = access.with_roots([uri.as_str(), "https://example.com", "file:///missing/dir"]);
+        assert!(access.open(root.join("secret.txt").to_str().unwrap()).is_ok());
+        assert!(FileAccess::default().with_roots([uri.as_str()]).open("secret.txt").is_ok());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_open_skips_escaping_candidate() {
+        let root = std::env::temp_dir().join(format!("acc_calc_links_{}", std::process::id()));
+        let (first, second) = (root.join("first"), root.join("second"));
+        std::fs::create_dir_all(&first).unwrap();
+        std::fs::create_dir_all(&second).unwrap();
+        std::fs::write(root.join("secret.txt"), "1|1\n").unwrap();
+        std::fs::write(second.join("ledger.txt"), "1 + 2|3\n").unwrap();
+        std::os::unix::fs::symlink(root.join("secret.txt"), first.join("ledger.txt")).unwrap();
+
+        // 第一个目录中的同名文件是指向目录之外的符号链接，改用第二个目录中的文件
+        let access = FileAccess::new(&[first.clone(), second]).unwrap();
+        let mut content = String::new();
+        io::Read::read_to_string(&mut access.open("ledger.txt").unwrap(), &mut content).unwrap();
+        assert_eq!(content, "1 + 2|3\n");
+        let access = FileAccess::new(&[first]).unwrap();
+        assert!(access.open("ledger.txt").unwrap_err().contains("不在允许读取的目录中"));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_file_uri_path() {
+        assert_eq!(file_uri_path("file:///tmp/%E8%B4%A6%20%E7%B0%BF"), Some(PathBuf::from("/tmp/账 簿")));
+        assert_eq!(file_uri_path("file://localhost/tmp"), Some(PathBuf::from("/tmp")));
+        assert_eq!(file_uri_path("file:///tmp/%2"), None);
+        assert_eq!(file_uri_path("/tmp"), None);
+    }
+}
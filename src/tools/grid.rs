@@ -0,0 +1,134 @@
+//! # 表格中的单元格
+//!
+//! `calculate_grid` 在给定的表格上计算 `SUM(B2:B10) - B11`、`C5 / C20` 这样的表达式。表格可以是二维数组，
+//! 也可以是 CSV 文本；第一行是第 1 行，第一个字段是 A 列。
+//!
+//! 单元格按与数字字面量相同的规则解析和预先舍入：千分位按数字格式识别，百分数按 `percent_rounding` 处理。
+//! 此外 `(1,234.00)` 这样的会计写法表示负数，`-`、`—` 表示零，`1,000 + 5` 这样的表达式不是数字。
+//! 直接引用的单元格必须是数字；区域中的空单元格按零计算（与电子表格的 SUM 相同），但不是数字的单元格
+//! 仍然报错，以免一列中混入的文本被悄悄忽略。
+
+use crate::tools::ast::{CellReference, Expr};
+use crate::tools::calculator::{parse_amount_cell, CalcOptions, Variables};
+use crate::tools::cell::{CellRange, CellRef};
+use crate::tools::csv::CsvReader;
+
+/// 按行存放的单元格文本，各行的长度可以不同
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grid {
+    rows: Vec<Vec<String>>,
+    /// 表格覆盖的区域，创建时计算一次
+    bounds: Option<CellRange>,
+}
+
+impl Grid {
+    pub fn new(rows: Vec<Vec<String>>) -> Self {
+        let columns = rows.iter().map(Vec::len).max().unwrap_or(0) as u32;
+        let bounds = (columns > 0).then(|| CellRange::new(CellRef::new(1, 1), CellRef::new(columns, rows.len() as u32)));
+        Grid { rows, bounds }
+    }
+
+    /// 从 CSV 文本读取表格，分隔符按第一行自动识别
+    pub fn from_csv(text: &str) -> Result<Self, String> {
+        let mut reader = CsvReader::new(text.as_bytes());
+        let mut rows = Vec::new();
+        while let Some(record) = reader.next_record()? {
+            rows.push(record.fields);
+        }
+        Ok(Grid::new(rows))
+    }
+
+    /// 表格覆盖的区域，例如 `A1:D20`；空表格为 `None`
+    pub fn bounds(&self) -> Option<CellRange> {
+        self.bounds
+    }
+
+    /// 单元格的文本；较短的行中缺少的单元格为空
+    fn text(&self, cell: CellRef) -> Result<&str, String> {
+        match self.bounds {
+            Some(bounds) if bounds.contains(cell) => {
+                let row = &self.rows[cell.row as usize - 1];
+                Ok(row.get(cell.column as usize - 1).map_or("", |text| text.trim()))
+            }
+            Some(bounds) => Err(format!("单元格 {} 超出表格范围 {}", cell, bounds)),
+            None => Err(format!("单元格 {} 超出表格范围：表格为空", cell)),
+        }
+    }
+
+    /// 单元格预先舍入后的值，写法见 `parse_amount_cell`；空单元格为 `None`
+    pub fn value(&self, cell: CellRef, options: &CalcOptions) -> Result<Option<f64>, String> {
+        let text = self.text(cell)?;
+        parse_amount_cell(text, options).map_err(|_| format!("单元格 {} 不是数字: '{}'", cell, text))
+    }
+
+    /// 表达式引用的单元格的值，作为求值时的变量
+    ///
+    /// 直接引用的单元格按表达式中的写法（如 `$B$2`）命名，区域中的单元格按规范写法命名。
+    /// 不是单元格地址的名称不在此处理，求值时报告为未定义的变量。
+    pub fn variables(&self, expr: &Expr, options: &CalcOptions, variables: &mut Variables) -> Result<(), String> {
+        for reference in expr.cell_references() {
+            match reference {
+                CellReference::Cell { name, cell } => {
+                    let value = self.value(cell, options)?.ok_or_else(|| format!("单元格 {} 为空", cell))?;
+                    variables.insert(name.to_string(), value);
+                }
+                CellReference::Range(range) => {
+                    if let Some(bounds) = self.bounds.filter(|bounds| !bounds.contains(range.start) || !bounds.contains(range.end)) {
+                        return Err(format!("区域 {} 超出表格范围 {}", range, bounds));
+                    }
+                    for cell in range.cells() {
+                        variables.insert(cell.to_string(), self.value(cell, options)?.unwrap_or(0.0));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::calculator::{evaluate_traced, PercentRounding};
+    use crate::tools::parser::parse;
+
+    fn grid() -> Grid {
+        Grid::from_csv("项目,2024\n现金,\"1,000.004\"\n存货,(200.5)\n备注,待定\n,\n比率,12.345%\n").unwrap()
+    }
+
+    fn evaluate(expr: &str, options: &CalcOptions) -> Result<f64, String> {
+        let ast = parse(expr, options).map_err(|e| e.to_string())?;
+        let mut variables = Variables::new();
+        grid().variables(&ast, options, &mut variables)?;
+        evaluate_traced(&ast, options, &variables, None).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn test_value() {
+        let options = CalcOptions::new(2, PercentRounding::RoundThenDivideBy100);
+        let grid = grid();
+        let value = |cell: &str| grid.value(CellRef::parse(cell).unwrap(), &options);
+        assert_eq!(grid.bounds().unwrap().to_string(), "A1:B6");
+        assert_eq!(value("B2"), Ok(Some(1000.0)));
+        assert_eq!(value("B3"), Ok(Some(-200.5)));
+        assert_eq!(value("B5"), Ok(None));
+        assert_eq!(value("B6"), Ok(Some(0.1235)));
+        assert_eq!(value("B4"), Err("单元格 B4 不是数字: '待定'".to_string()));
+        let grid = Grid::new(vec![vec!["-".to_string(), "(1+2)".to_string(), "1,000 + 5".to_string()]]);
+        assert_eq!(grid.value(CellRef::new(1, 1), &options), Ok(Some(0.0)));
+        assert_eq!(grid.value(CellRef::new(2, 1), &options), Err("单元格 B1 不是数字: '(1+2)'".to_string()));
+        assert_eq!(grid.value(CellRef::new(3, 1), &options), Err("单元格 C1 不是数字: '1,000 + 5'".to_string()));
+        assert_eq!(value("C1"), Err("单元格 C1 超出表格范围 A1:B6".to_string()));
+        assert_eq!(Grid::new(Vec::new()).value(CellRef::new(1, 1), &options), Err("单元格 A1 超出表格范围：表格为空".to_string()));
+    }
+
+    #[test]
+    fn test_variables() {
+        let options = CalcOptions::new(2, PercentRounding::RoundThenDivideBy100);
+        assert_eq!(evaluate("SUM(B2:B3) - $B$2", &options), Ok(-200.5));
+        assert_eq!(evaluate("sum(b2, B3; B5:B5)", &options), Ok(799.5));
+        assert_eq!(evaluate("B5 * 2", &options), Err("单元格 B5 为空".to_string()));
+        assert_eq!(evaluate("SUM(B2:B4)", &options), Err("单元格 B4 不是数字: '待定'".to_string()));
+        assert_eq!(evaluate("SUM(B2:C3)", &options), Err("区域 B2:C3 超出表格范围 A1:B6".to_string()));
+    }
+}
@@ -0,0 +1,169 @@
+//! # 重要性水平
+//!
+//! 审计人员按重要性对差异分类，而不只是区分通过和失败。`batch_validate` 把每个有差异的失败行归入三类：
+//!
+//! | 分类 | 含义 |
+//! |---|---|
+//! | 尾差（trivial） | 差异可以由舍入解释，见差异分析 |
+//! | 低于重要性水平（below_threshold） | 差异的绝对值小于重要性水平 |
+//! | 重要（material） | 其余的差异，包括未指定重要性水平时 |
+//!
+//! 重要性水平可以写成金额，例如 `500`，也可以写成基准的百分比，例如 `0.5% of {资产总计}`；
+//! 金额和基准都可以用 `{标记}` 引用批量验证中的行。
+
+use std::fmt;
+
+use crate::tools::calculator::{evaluate_expected_in, round_value, CalcError, CalcOptions, Variables};
+
+/// 按百分比计算的重要性水平保留的小数位数，用于去掉浮点运算的尾差
+const THRESHOLD_DECIMALS: u32 = 10;
+
+/// 重要性水平
+#[derive(Debug, PartialEq, Clone)]
+pub enum Materiality {
+    /// 金额（可以是表达式）
+    Amount(String),
+    /// 基准的百分比
+    Percent { percent: f64, base: String },
+}
+
+/// 差异的分类
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Severity {
+    /// 尾差：可以由舍入解释
+    Trivial,
+    /// 低于重要性水平
+    BelowThreshold,
+    /// 重要
+    Material,
+}
+
+impl Materiality {
+    /// 支持的写法，用于错误提示
+    pub const SYNTAX: &'static str = "500（金额）、0.5% of {资产总计}（基准的百分比）";
+
+    /// 解析重要性水平，写法有误时返回错误信息
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let input = input.trim();
+        let invalid = || format!("无效的重要性水平 '{}'，支持的写法：{}", input, Self::SYNTAX);
+        if input.is_empty() {
+            return Err(invalid());
+        }
+        // 末尾补一个空格，使 "1% of" 也被识别为缺少基准
+        let Some(at) = format!("{} ", input).to_ascii_lowercase().find(" of ") else {
+            return Ok(Materiality::Amount(input.to_string()));
+        };
+
+        let (percent, base) = (input[..at].trim(), input.get(at + 4..).unwrap_or("").trim());
+        let percent = percent
+            .strip_suffix('%')
+            .and_then(|percent| percent.trim().parse::<f64>().ok())
+            .filter(|percent| percent.is_finite() && *percent >= 0.0)
+            .ok_or_else(invalid)?;
+        if base.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Materiality::Percent { percent, base: base.to_string() })
+    }
+
+    /// 计算重要性水平的金额（绝对值）
+    pub fn evaluate(&self, options: &CalcOptions, variables: &Variables) -> Result<f64, CalcError> {
+        match self {
+            Materiality::Amount(amount) => Ok(evaluate_expected_in(amount, options, variables)?.abs()),
+            Materiality::Percent { percent, base } => {
+                let base = evaluate_expected_in(base, options, variables)?.abs();
+                Ok(round_value(base * percent / 100.0, THRESHOLD_DECIMALS))
+            }
+        }
+    }
+
+    /// 金额或基准的文本，用于推断小数位数和报告错误
+    pub fn source(&self) -> &str {
+        match self {
+            Materiality::Amount(amount) => amount,
+            Materiality::Percent { base, .. } => base,
+        }
+    }
+}
+
+impl Severity {
+    pub const ALL: [Severity; 3] = [Severity::Trivial, Severity::BelowThreshold, Severity::Material];
+
+    /// 对一个差异分类；`threshold` 为 `None` 时不能由舍入解释的差异都是重要的
+    pub fn classify(difference: f64, explained_by_rounding: bool, threshold: Option<f64>) -> Self {
+        if explained_by_rounding {
+            Severity::Trivial
+        } else if threshold.is_some_and(|threshold| difference.abs() < threshold) {
+            Severity::BelowThreshold
+        } else {
+            Severity::Material
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Trivial => "trivial",
+            Severity::BelowThreshold => "below_threshold",
+            Severity::Material => "material",
+        }
+    }
+}
+
+impl fmt::Display for Materiality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Materiality::Amount(amount) => write!(f, "{}", amount),
+            Materiality::Percent { percent, base } => write!(f, "{}% of {}", percent, base),
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Trivial => write!(f, "尾差"),
+            Severity::BelowThreshold => write!(f, "低于重要性水平"),
+            Severity::Material => write!(f, "重要"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::calculator::PercentRounding;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Materiality::parse(" 500 "), Ok(Materiality::Amount("500".to_string())));
+        assert_eq!(
+            Materiality::parse("0.5% OF {资产总计}"),
+            Ok(Materiality::Percent { percent: 0.5, base: "{资产总计}".to_string() })
+        );
+        assert!(Materiality::parse("").is_err());
+        assert!(Materiality::parse("0.5 of {资产总计}").is_err());
+        assert!(Materiality::parse("-1% of 100").is_err());
+        assert!(Materiality::parse("1% of ").is_err());
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let options = CalcOptions::new(2, PercentRounding::RoundThenDivideBy100);
+        let mut variables = Variables::new();
+        variables.insert("资产总计".to_string(), -20000.0);
+        let threshold = Materiality::parse("0.5% of {资产总计}").unwrap();
+        assert_eq!(threshold.evaluate(&options, &variables), Ok(100.0));
+        variables.insert("资产总计".to_string(), 10200.0);
+        assert_eq!(threshold.evaluate(&options, &variables), Ok(51.0));
+        assert_eq!(Materiality::parse("1000").unwrap().evaluate(&options, &variables), Ok(1000.0));
+        assert!(Materiality::parse("1% of {负债总计}").unwrap().evaluate(&options, &variables).is_err());
+    }
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(Severity::classify(0.01, true, Some(100.0)), Severity::Trivial);
+        assert_eq!(Severity::classify(-99.0, false, Some(100.0)), Severity::BelowThreshold);
+        assert_eq!(Severity::classify(100.0, false, Some(100.0)), Severity::Material);
+        assert_eq!(Severity::classify(0.5, false, None), Severity::Material);
+    }
+}
@@ -1,9 +1,49 @@
+pub mod assertion;
+pub mod ast;
+pub mod batch;
 pub mod calculator;
+pub mod cell;
+pub mod convention;
+pub mod csv;
+pub mod diagnostic;
+pub mod discrepancy;
+pub mod files;
+pub mod grid;
+pub mod materiality;
+pub mod number_format;
+pub mod output;
+pub mod overrides;
+pub mod parser;
+pub mod precision;
+pub mod runner;
+pub mod script;
+pub mod statement;
+pub mod table;
+pub mod tolerance;
+pub mod typo;
+pub mod xlsx;
 
 use rust_mcp_sdk::macros::{mcp_tool, JsonSchema};
-use rust_mcp_sdk::schema::{schema_utils::CallToolError, CallToolResult, TextContent};
+use rust_mcp_sdk::schema::{schema_utils::CallToolError, CallToolResult};
 
-pub use calculator::{calculate, validate, PercentRounding};
+pub use calculator::{calculate_steps, calculate_with, check_with, evaluate_expected, literal_value, CalcOptions, PercentRounding};
+pub use assertion::{split_equation, Assertion};
+pub use batch::{BatchItem, BatchLine, ReferenceValue};
+pub use convention::search as search_conventions;
+pub use discrepancy::analyze;
+pub use files::FileAccess;
+pub use grid::Grid;
+pub use materiality::Materiality;
+pub use parser::parse;
+pub use precision::{check_decimals, Decimals, DEFAULT_DECIMALS};
+pub use runner::{validate_csv, BatchConfig, BatchRunner, Block};
+pub use script::run_script;
+pub use statement::{infer_statement_decimals, StatementNode};
+pub use table::{infer_table_decimals, TableLayout, TableRow};
+pub use tolerance::Tolerance;
+pub use typo::{difference_hints, suggest_in as suggest_typos};
+pub use number_format::NumberFormat;
+pub use output::{output_schema, report_text, tool_result, CalculateOutput, ConventionMatch, ConventionOutput, GridOutput, GridResult, ParseOutput, ScriptOutput, Settings, SyntaxTree, ValidateOutput};
 pub use rust_mcp_sdk::tool_box;
 
 #[mcp_tool(
@@ -19,10 +59,16 @@ pub use rust_mcp_sdk::tool_box;
 pub struct CalculateTool {
     /// 要计算的算术表达式（运算符支持：加、减、乘、除、括号和百分号），支持千分位分隔符（美式: 1,234.56, 欧式: 1.234,56, 空格: 1 234.56, 撇号: 1'234.56）
     pub expression: String,
-    /// 计算前和结果要保留的小数位数，默认为2
+    /// 计算前和结果要保留的小数位数，默认为2，最多 15 位
     pub decimals: Option<u32>,
     /// 百分数处理策略（仅当表达式包含百分数时有效）：divide_by_100_then_round（先除以100后舍入）或 round_then_divide_by_100（先舍入后除以100），默认是 round_then_divide_by_100
     pub percent_rounding: Option<String>,
+    /// 数字格式：en-US（1,234.56）、de-DE（1.234,56）、fr-FR（1 234,56）、de-CH（1'234.56）、zh-CN（1,234.56）、en-IN（12,34,567.89）或 auto（自动识别），默认是 auto。指定后表达式和预期值都按该格式确定性地解析
+    pub number_format: Option<String>,
+    /// 严格分组模式：拒绝不构成合法三位分组（en-IN 为 2-2-3 分组）的分隔符并报告出错字符的位置；auto 格式下不再把空格视为千分位分隔符，因此 "100 200" 会被识别为缺少运算符。默认 false
+    pub strict_grouping: Option<bool>,
+    /// 是否输出逐步的计算过程（每个数字预先舍入后的值、每一步运算的结果），默认 false。数字后可以用 [标记] 或 #标记 注明来源，标记会出现在计算过程中
+    pub show_steps: Option<bool>,
 }
 
 #[mcp_tool(
@@ -36,20 +82,28 @@ pub struct CalculateTool {
 )]
 #[derive(serde::Deserialize, serde::Serialize, Clone, Debug, JsonSchema)]
 pub struct ValidateTool {
-    /// 要验证的算术表达式（支持千分位分隔符：美式、欧式、空格、撇号格式）
+    /// 要验证的算术表达式（支持千分位分隔符：美式、欧式、空格、撇号格式），也可以是等式或断言，例如 1,000 + 2,000 = 500 + 2,500、(300 - 200) / 300 between 20% and 40%，此时不需要 expected
     pub expression: String,
-    /// 预期的结果值（支持百分数和千分位格式，如：50.5%, 1,234.56, 1.234,56）
-    pub expected: String,
-    /// 要保留的小数位数，默认为2
-    pub decimals: Option<u32>,
+    /// 预期的结果值（支持百分数和千分位格式，如：50.5%, 1,234.56, 1.234,56），也可以是按相同规则计算的表达式；必须完整解析，不允许多余的字符。也可以是断言：>= 0、< 5%、!= 0 或 between 20% and 40%（含两端），边界不做舍入
+    pub expected: Option<String>,
+    /// 要保留的小数位数，默认为2；为 "auto" 时按预期值显示的小数位数确定（百分数按百分数单位计数，12.35% 对应 4 位小数），预先舍入和结果舍入都使用该位数
+    pub decimals: Option<Decimals>,
     /// 百分数处理策略（仅当表达式或预期值包含百分数时有效）：divide_by_100_then_round（先除以100后舍入）或 round_then_divide_by_100（先舍入后除以100），默认是 divide_by_100_then_round (0.126% → 50.13% → 0.5013)
     pub percent_rounding: Option<String>,
+    /// 数字格式：en-US（1,234.56）、de-DE（1.234,56）、fr-FR（1 234,56）、de-CH（1'234.56）、zh-CN（1,234.56）、en-IN（12,34,567.89）或 auto（自动识别），默认是 auto。指定后表达式和预期值都按该格式确定性地解析
+    pub number_format: Option<String>,
+    /// 严格分组模式：拒绝不构成合法三位分组（en-IN 为 2-2-3 分组）的分隔符并报告出错字符的位置；auto 格式下不再把空格视为千分位分隔符，因此 "100 200" 会被识别为缺少运算符。默认 false
+    pub strict_grouping: Option<bool>,
+    /// 容差：0.01（绝对容差，差异不超过 0.01）、0.1%（相对容差，差异不超过预期值的 0.1%）或 2ulp（不超过 2 个最后一位小数的单位，按 decimals 计算），默认精确匹配
+    pub tolerance: Option<String>,
+    /// 验证失败时是否检查操作数的录入错误（数字换位、漏输数字、正负号录反、小数点错位），列出能使算式成立的修正，默认 false
+    pub typo_check: Option<bool>,
 }
 
 #[mcp_tool(
     name = "batch_validate",
     title = "批量验证算术表达式",
-    description = "同时验证多个算式的计算结果是否与预期值相符。支持批量处理多个表达式，提高验证效率。每个表达式都支持千分位分隔符（美式、欧式、空格、撇号格式）和完整的运算符集合。支持为每个表达式添加标记（如'流动资产合计'）以便识别错误的算式。",
+    description = "同时验证多个算式的计算结果是否与预期值相符。支持批量处理多个表达式，提高验证效率。每个表达式都支持千分位分隔符（美式、欧式、空格、撇号格式）和完整的运算符集合。支持为每个表达式添加标记（如'流动资产合计'）以便识别错误的算式。推荐通过 items 传入结构化的项，expressions 字符串列表保留用于兼容。",
     destructive_hint = false,
     idempotent_hint = true,
     open_world_hint = false,
@@ -57,170 +111,401 @@ pub struct ValidateTool {
 )]
 #[derive(serde::Deserialize, serde::Serialize, Clone, Debug, JsonSchema)]
 pub struct BatchValidateTool {
-    /// 要验证的表达式列表，格式为 "expression|expected" 或 "expression|expected|label"
+    /// 要验证的项，每项是一个对象：expression（必填）、expected、label、id，以及覆盖该项设置的 decimals、percent_rounding、tolerance。与 expressions 可以同时使用，items 排在后面
+    pub items: Option<Vec<BatchItem>>,
+    /// 本地文件的路径，每行一项，写法同 expressions 或 items 中的一个 JSON 对象；空行跳过。文件逐块读取，适合十万行以上的账簿，排在 expressions 和 items 之后。服务器需以 --allow-dir 启动，只能读取允许的目录中的文件，相对路径在允许的目录中查找
+    pub file: Option<String>,
+    /// （兼容旧版）要验证的表达式列表，格式为 "expression|expected" 或 "expression|expected|label"；expected 可以是断言，例如 "a - b|>= 0" 或 "x|between 20% and 40%|毛利率"。可以再加一个字段覆盖该行的设置，多个设置用分号分隔，例如 "1,234.5678 * 12.3456%|152.4691|利息|decimals=4;percent=divide_by_100_then_round;tol=0.01"，支持的键：decimals（dp）、percent（percent_rounding）、tol（tolerance）
+    pub expressions: Option<Vec<String>>,
+    /// 要保留的小数位数，默认为2；为 "auto" 时每行按各自预期值显示的小数位数确定（百分数按百分数单位计数）
+    pub decimals: Option<Decimals>,
+    /// 百分数处理策略（仅当表达式包含百分数时有效）：divide_by_100_then_round（先除以100后舍入）或 round_then_divide_by_100（先舍入后除以100），默认是 round_then_divide_by_100
+    pub percent_rounding: Option<String>,
+    /// 数字格式：en-US（1,234.56）、de-DE（1.234,56）、fr-FR（1 234,56）、de-CH（1'234.56）、zh-CN（1,234.56）、en-IN（12,34,567.89）或 auto（自动识别），默认是 auto。指定后表达式和预期值都按该格式确定性地解析
+    pub number_format: Option<String>,
+    /// 严格分组模式：拒绝不构成合法三位分组（en-IN 为 2-2-3 分组）的分隔符并报告出错字符的位置；auto 格式下不再把空格视为千分位分隔符，因此 "100 200" 会被识别为缺少运算符。默认 false
+    pub strict_grouping: Option<bool>,
+    /// 容差：0.01（绝对容差，差异不超过 0.01）、0.1%（相对容差，差异不超过预期值的 0.1%）或 2ulp（不超过 2 个最后一位小数的单位，按 decimals 计算），默认精确匹配
+    pub tolerance: Option<String>,
+    /// 验证失败时是否检查操作数的录入错误（数字换位、漏输数字、正负号录反、小数点错位），列出能使算式成立的修正，默认 false
+    pub typo_check: Option<bool>,
+    /// 遇到第一个失败的行即停止，其余的行计为跳过，默认 false
+    pub fail_fast: Option<bool>,
+    /// 失败的行达到该数量后停止，其余的行计为跳过；默认不限制
+    pub max_failures: Option<u32>,
+    /// 只输出失败的行和汇总，默认 false
+    pub failures_only: Option<bool>,
+    /// 结果行的总字符数上限，超出的行被省略（汇总中的计数不受影响）；默认不限制
+    pub max_output_chars: Option<u32>,
+    /// 重要性水平：金额（如 500）或基准的百分比（如 "0.5% of {资产总计}"，基准可以引用带标记的行）。失败行的差异分为尾差（可由舍入解释）、低于重要性水平和重要，汇总中给出每类的行数和差异绝对值的合计；未指定时不能由舍入解释的差异都视为重要
+    pub materiality: Option<String>,
+    /// 表达式和预期值可以用 {标记} 引用前面带该标记的行，例如 "{流动资产合计} + {非流动资产合计}|10,000|资产总计"。被引用的行提供的值：expected（预期值，默认；断言不是等式时取计算结果）或 computed（计算结果）
+    pub reference_value: Option<String>,
+}
+
+#[mcp_tool(
+    name = "parse",
+    title = "解析算术表达式",
+    description = "把算术表达式解析为语法树，不进行计算。返回规范形式（去掉千分位、只保留必要括号）和 JSON 格式的语法树；数字字面量保留原始文本、识别出的数字格式和位置（字符偏移），便于检查表达式的结构或定位格式问题。",
+    destructive_hint = false,
+    idempotent_hint = true,
+    open_world_hint = false,
+    read_only_hint = true
+)]
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, JsonSchema)]
+pub struct ParseTool {
+    /// 要解析的算术表达式（运算符支持：加、减、乘、除、括号和百分号）
+    pub expression: String,
+    /// 数字格式：en-US、de-DE、fr-FR、de-CH、zh-CN、en-IN 或 auto（自动识别），默认是 auto
+    pub number_format: Option<String>,
+    /// 严格分组模式：拒绝不构成合法三位分组（en-IN 为 2-2-3 分组）的分隔符，默认 false
+    pub strict_grouping: Option<bool>,
+}
+
+#[mcp_tool(
+    name = "calculate_script",
+    title = "计算多步算式脚本",
+    description = "按顺序计算由分号或换行分隔的多条语句，例如 gross = 12,000 * 1.13; tax = gross * 13% / 1.13; gross - tax。每条赋值的结果按与 calculate 相同的规则舍入后保存，后续语句引用舍入后的值，使中间数与工作底稿一致。返回每个变量的值和最后一条语句的结果。",
+    destructive_hint = false,
+    idempotent_hint = true,
+    open_world_hint = false,
+    read_only_hint = true
+)]
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, JsonSchema)]
+pub struct CalculateScriptTool {
+    /// 脚本：由分号或换行分隔的语句，语句为 变量 = 表达式 或单独的表达式；变量名以字母、汉字或 _ 开头，必须先赋值后引用
+    pub script: String,
+    /// 每个数字、每条赋值和最终结果要保留的小数位数，默认为2，最多 15 位
+    pub decimals: Option<u32>,
+    /// 百分数处理策略：divide_by_100_then_round（先除以100后舍入）或 round_then_divide_by_100（先舍入后除以100），默认是 round_then_divide_by_100
+    pub percent_rounding: Option<String>,
+    /// 数字格式：en-US、de-DE、fr-FR、de-CH、zh-CN、en-IN 或 auto（自动识别），默认是 auto
+    pub number_format: Option<String>,
+    /// 严格分组模式：拒绝不构成合法三位分组（en-IN 为 2-2-3 分组）的分隔符，默认 false
+    pub strict_grouping: Option<bool>,
+}
+
+#[mcp_tool(
+    name = "find_rounding_convention",
+    title = "搜索舍入口径",
+    description = "给定算式和报表上的数字，穷举小数位数、舍入方式（四舍五入、银行家舍入、截断）、百分数处理策略、是否预先舍入和是否逐步舍入的组合，列出所有能精确复现该数字的口径，用于反推客户表格的计算方法。",
+    destructive_hint = false,
+    idempotent_hint = true,
+    open_world_hint = false,
+    read_only_hint = true
+)]
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, JsonSchema)]
+pub struct FindRoundingConventionTool {
+    /// 算术表达式（运算符支持：加、减、乘、除、括号和百分号）
+    pub expression: String,
+    /// 报表上的数字（支持百分数和千分位格式）
+    pub reported: String,
+    /// 搜索的最大小数位数（0 到 10），默认为4
+    pub max_decimals: Option<u32>,
+    /// 数字格式：en-US、de-DE、fr-FR、de-CH、zh-CN、en-IN 或 auto（自动识别），默认是 auto
+    pub number_format: Option<String>,
+    /// 严格分组模式：拒绝不构成合法三位分组（en-IN 为 2-2-3 分组）的分隔符，默认 false
+    pub strict_grouping: Option<bool>,
+}
+
+#[mcp_tool(
+    name = "validate_statement",
+    title = "验证报表层级",
+    description = "验证以树形结构给出的财务报表（例如 资产 → 流动资产 → 货币资金）：逐个核对每个小计、合计是否等于其子项之和，并核对会计恒等式 资产 = 负债 + 所有者权益（报表中有这三项时自动核对）。求和沿用 calculate 的舍入规则：子项金额先按小数位数舍入再相加，和再舍入。报告每个不一致的项目及其子项之和。",
+    destructive_hint = false,
+    idempotent_hint = true,
+    open_world_hint = false,
+    read_only_hint = true
+)]
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, JsonSchema)]
+pub struct ValidateStatementTool {
+    /// 报表的顶层项目，每项是一个对象：label（项目名称）、amount（报表金额，有子项时可省略，省略时取子项之和）、subtract（在父项中减去，例如 减：累计折旧）、children（子项，结构相同）
+    pub statement: Vec<StatementNode>,
+    /// 要核对的恒等式，用 {项目} 引用项目的金额，例如 "{资产} = {负债} + {所有者权益}"；也可以是断言，例如 "{流动资产} >= {流动负债}"。未指定时，报表中有资产、负债和所有者权益（或股东权益）则自动核对会计恒等式
+    pub identities: Option<Vec<String>>,
+    /// 要保留的小数位数，默认为2；为 "auto" 时取报表金额中最多的小数位数
+    pub decimals: Option<Decimals>,
+    /// 数字格式：en-US、de-DE、fr-FR、de-CH、zh-CN、en-IN 或 auto（自动识别），默认是 auto
+    pub number_format: Option<String>,
+    /// 严格分组模式：拒绝不构成合法三位分组（en-IN 为 2-2-3 分组）的分隔符，默认 false
+    pub strict_grouping: Option<bool>,
+    /// 容差：0.01（绝对容差）、0.1%（相对容差）或 2ulp（末位单位），默认精确匹配
+    pub tolerance: Option<String>,
+}
+
+#[mcp_tool(
+    name = "crossfoot_table",
+    title = "交叉核对表格",
+    description = "交叉核对报表附注中的二维表格（例如 分部 × 期间、资产类别 × 变动项目）：每一行的明细之和应等于合计列，每一列的明细之和应等于合计行，合计行与合计列交叉处的总计两个方向都要相符。单元格按数字格式解析，(1,234) 表示负数，空单元格和 - 表示零；求和沿用 calculate 的舍入规则。按行标签和列标签报告每个不符的合计单元格。",
+    destructive_hint = false,
+    idempotent_hint = true,
+    open_world_hint = false,
+    read_only_hint = true
+)]
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, JsonSchema)]
+pub struct CrossfootTableTool {
+    /// 列标签，例如 ["2023", "2024", "合计"]
+    pub columns: Vec<String>,
+    /// 表格的行，每行是一个对象：label（行标签）、cells（按列的顺序排列的单元格，个数与列标签相同）
+    pub rows: Vec<TableRow>,
+    /// 合计行的标签，默认为最后一行
+    pub total_row: Option<String>,
+    /// 合计列的标签，默认为最后一列
+    pub total_column: Option<String>,
+    /// 要保留的小数位数，默认为2；为 "auto" 时取单元格中最多的小数位数
+    pub decimals: Option<Decimals>,
+    /// 数字格式：en-US、de-DE、fr-FR、de-CH、zh-CN、en-IN 或 auto（自动识别），默认是 auto
+    pub number_format: Option<String>,
+    /// 严格分组模式：拒绝不构成合法三位分组（en-IN 为 2-2-3 分组）的分隔符，默认 false
+    pub strict_grouping: Option<bool>,
+    /// 容差：0.01（绝对容差）、0.1%（相对容差）或 2ulp（末位单位），默认精确匹配
+    pub tolerance: Option<String>,
+}
+
+#[mcp_tool(
+    name = "validate_csv",
+    title = "验证 CSV 报表",
+    description = "读取本地的 CSV 报表并逐行验证。文件第一行是表头，包含 label（项目）、amount（金额）和可选的 formula（公式）列；公式用 {项目} 引用前面的行，例如 {流动资产合计} + {非流动资产合计}。有公式的行按 calculate 的舍入规则计算并与金额比较，报告每行通过或失败；没有公式的行只提供数值。分隔符（逗号、分号或制表符）根据表头自动识别。文件逐块读取，适合数千行以上的报表；只能读取 --allow-dir 指定的目录或客户端 roots 中的文件。",
+    destructive_hint = false,
+    idempotent_hint = true,
+    open_world_hint = false,
+    read_only_hint = true
+)]
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, Default, JsonSchema)]
+pub struct ValidateCsvTool {
+    /// CSV 文件的路径；相对路径在允许的目录中查找
+    pub path: String,
+    /// 要保留的小数位数，默认为2；为 "auto" 时每行按各自金额显示的小数位数确定
+    pub decimals: Option<Decimals>,
+    /// 百分数处理策略（仅当公式包含百分数时有效）：divide_by_100_then_round 或 round_then_divide_by_100，默认是 round_then_divide_by_100
+    pub percent_rounding: Option<String>,
+    /// 数字格式：en-US、de-DE、fr-FR、de-CH、zh-CN、en-IN 或 auto（自动识别），默认是 auto
+    pub number_format: Option<String>,
+    /// 严格分组模式：拒绝不构成合法三位分组（en-IN 为 2-2-3 分组）的分隔符，默认 false
+    pub strict_grouping: Option<bool>,
+    /// 容差：0.01（绝对容差）、0.1%（相对容差）或 2ulp（末位单位），默认精确匹配
+    pub tolerance: Option<String>,
+    /// 失败的行达到该数量后停止，其余有公式的行计为跳过；默认不限制
+    pub max_failures: Option<u32>,
+    /// 只输出失败的行和汇总，默认 false
+    pub failures_only: Option<bool>,
+    /// 结果行的总字符数上限，超出的行被省略（汇总中的计数不受影响）；默认不限制
+    pub max_output_chars: Option<u32>,
+    /// 重要性水平：金额（如 500）或基准的百分比（如 "0.5% of {资产总计}"），写法同 batch_validate
+    pub materiality: Option<String>,
+}
+
+#[mcp_tool(
+    name = "calculate_grid",
+    title = "在表格上计算",
+    description = "在给定的表格上计算引用单元格的表达式，例如 SUM(B2:B10) - B11、C5 / C20。表格以二维数组（grid）或 CSV 文本（csv）给出，第一行是第 1 行，第一列是 A 列；支持 $B$2 这样的绝对引用和 SUM 函数，区域只能作为 SUM 的参数。单元格按与数字字面量相同的规则解析和预先舍入，(1,234) 表示负数；直接引用空单元格或不是数字的单元格时报告出错的单元格地址，区域中的空单元格按零计算。表达式可以写成等式或断言（如 SUM(B2:B4) = B5、C5 / C20 >= 10%），此时报告是否通过。",
+    destructive_hint = false,
+    idempotent_hint = true,
+    open_world_hint = false,
+    read_only_hint = true
+)]
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, JsonSchema)]
+pub struct CalculateGridTool {
+    /// 要计算的表达式，例如 ["SUM(B2:B10) - B11", "C5 / C20", "SUM(B2:B4) = B5"]
     pub expressions: Vec<String>,
-    /// 要保留的小数位数，默认为2
+    /// 表格，每个元素是一行单元格的文本；与 csv 二选一
+    pub grid: Option<Vec<Vec<String>>>,
+    /// CSV 文本形式的表格，分隔符（逗号、分号或制表符）根据第一行自动识别；与 grid 二选一
+    pub csv: Option<String>,
+    /// 计算前和结果要保留的小数位数，默认为2，最多 15 位
     pub decimals: Option<u32>,
-    /// 百分数处理策略（仅当表达式包含百分数时有效）：divide_by_100_then_round（先除以100后舍入）或 round_then_divide_by_100（先舍入后除以100），默认是 round_then_divide_by_100
+    /// 百分数处理策略（仅当单元格或表达式包含百分数时有效）：divide_by_100_then_round 或 round_then_divide_by_100，默认是 round_then_divide_by_100
     pub percent_rounding: Option<String>,
+    /// 数字格式：en-US、de-DE、fr-FR、de-CH、zh-CN、en-IN 或 auto（自动识别），默认是 auto
+    pub number_format: Option<String>,
+    /// 严格分组模式：拒绝不构成合法三位分组（en-IN 为 2-2-3 分组）的分隔符，默认 false
+    pub strict_grouping: Option<bool>,
+    /// 容差：0.01（绝对容差）、0.1%（相对容差）或 2ulp（末位单位），默认精确匹配；用于等式形式的表达式
+    pub tolerance: Option<String>,
 }
 
+#[mcp_tool(
+    name = "recalculate_xlsx",
+    title = "重新计算 Excel 工作簿",
+    description = "读取本地的 .xlsx 工作簿，把其中的公式（+ - * /、括号、百分号、SUM、ROUND 和单元格区域）转换为 calculate 的表达式，按指定的小数位数和百分数策略重新计算：常量单元格预先舍入，每个公式的结果舍入后再供其他公式引用。列出文件中缓存的值（按相同规则舍入后）与重新计算的结果不符的单元格，以及不受支持的公式。设置了百分比格式的单元格按百分数处理。只能读取 --allow-dir 指定的目录或客户端 roots 中的文件。",
+    destructive_hint = false,
+    idempotent_hint = true,
+    open_world_hint = false,
+    read_only_hint = true
+)]
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, JsonSchema)]
+pub struct RecalculateXlsxTool {
+    /// .xlsx 文件的路径；相对路径在允许的目录中查找
+    pub path: String,
+    /// 只重新计算该工作表，默认全部工作表
+    pub sheet: Option<String>,
+    /// 计算前和结果要保留的小数位数，默认为2，最多 15 位
+    pub decimals: Option<u32>,
+    /// 百分数处理策略（百分比格式的单元格和公式中的百分数）：divide_by_100_then_round 或 round_then_divide_by_100，默认是 round_then_divide_by_100
+    pub percent_rounding: Option<String>,
+    /// 容差：0.01（绝对容差）、0.1%（相对容差）或 2ulp（末位单位），默认精确匹配
+    pub tolerance: Option<String>,
+}
 
 impl BatchValidateTool {
     pub async fn run_tool(
         params: Self,
-        _context: &(),
+        context: &FileAccess,
     ) -> Result<CallToolResult, CallToolError> {
-        let mut results = Vec::new();
-        let mut all_passed = true;
-        
-        for (index, expr_line) in params.expressions.iter().enumerate() {
-            let parts: Vec<&str> = expr_line.split('|').collect();
-            
-            if parts.len() < 2 {
-                results.push(format!("行 {}: 格式错误 - 需要 'expression|expected' 格式", index + 1));
-                all_passed = false;
-                continue;
-            }
-            
-            let expression = parts[0].trim();
-            
-            let label = if parts.len() > 2 {
-                parts[2].trim().to_string()
-            } else {
-                String::new()
-            };
-            
-            let label_prefix = if label.is_empty() {
-                String::new()
-            } else {
-                format!("[{}] ", label)
-            };
-            
-            let percent_rounding = params.percent_rounding.as_deref().unwrap_or("round_then_divide_by_100");
-            let strategy = match parse_percent_rounding(percent_rounding) {
-                Ok(s) => s,
-                Err(_) => {
-                    results.push(format!("行 {}: {}无效的百分数处理策略 '{}'", index + 1, label_prefix, percent_rounding));
-                    all_passed = false;
-                    continue;
-                }
-            };
-            
-            let decimals = params.decimals.unwrap_or(2);
-            let expected = match parse_expected_value(parts[1].trim(), decimals, strategy) {
-                Ok(val) => val,
-                Err(_) => {
-                    results.push(format!("行 {}: {}无效的预期值 '{}'", index + 1, label_prefix, parts[1]));
-                    all_passed = false;
-                    continue;
-                }
-            };
-            
-            let is_valid = validate(expression, expected, decimals, strategy);
-            
-            if is_valid {
-                results.push(format!("行 {}: {}{} = {} (通过)", index + 1, label_prefix, expression, expected));
-            } else {
-                // 计算实际值以便显示差异
-                match calculate(expression, decimals, strategy) {
-                    Ok(actual) => {
-                        results.push(format!("行 {}: {}{} ≠ {} (实际: {})", index + 1, label_prefix, expression, expected, actual));
-                    }
-                    Err(e) => {
-                        results.push(format!("行 {}: {}{} - 计算错误: {:?}", index + 1, label_prefix, expression, e));
-                    }
-                }
-                all_passed = false;
-            }
+        let invalid = |message: String| CallToolError::new(crate::error::ServiceError::InvalidExpression(message));
+        if params.expressions.is_none() && params.items.is_none() && params.file.is_none() {
+            return Err(invalid("缺少要验证的项：请指定 items、expressions 或 file".to_string()));
         }
-        
-        let summary = if all_passed {
-            format!("批量验证完成！所有 {} 个表达式均通过验证", params.expressions.len())
-        } else {
-            let passed_count = results.iter().filter(|r| r.contains("(通过)")).count();
-            let total_count = params.expressions.len();
-            format!("批量验证完成！{}/{} 个表达式通过验证", passed_count, total_count)
+        let config = BatchConfig {
+            decimals: params.decimals,
+            percent_rounding: parse_percent_rounding(params.percent_rounding.as_deref().unwrap_or("round_then_divide_by_100"))?,
+            number_format: parse_number_format(params.number_format.as_deref().unwrap_or("auto"))?,
+            strict_grouping: params.strict_grouping.unwrap_or(false),
+            tolerance: parse_tolerance(params.tolerance.as_deref().unwrap_or(""))?,
+            typo_check: params.typo_check.unwrap_or(false),
+            reference_value: parse_reference_value(params.reference_value.as_deref().unwrap_or("expected"))?,
+            max_failures: if params.fail_fast.unwrap_or(false) { Some(1) } else { params.max_failures.filter(|&max| max > 0) },
+            failures_only: params.failures_only.unwrap_or(false),
+            max_output_chars: params.max_output_chars,
+            materiality: params.materiality.as_deref().map(parse_materiality).transpose()?,
+            threads: BatchConfig::available_threads(),
         };
-        
-        let mut output = vec![summary, "".to_string()];
-        output.extend(results);
-        
-        Ok(CallToolResult::text_content(vec![TextContent::from(
-            output.join("\n")
-        )]))
+        // 先打开文件，路径有误时不做任何验证
+        let file = params.file.as_deref().map(|path| context.open(path)).transpose().map_err(invalid)?;
+
+        // 大批量的验证占用多个核，不在异步运行时的线程上执行
+        let (text, output) = tokio::task::spawn_blocking(move || {
+            let mut inline = Block::default();
+            let lines = params.expressions.iter().flatten().map(|line| BatchLine::parse(line))
+                .chain(params.items.iter().flatten().map(BatchLine::from_item));
+            for (index, line) in lines.enumerate() {
+                inline.push(index as u32 + 1, line);
+            }
+            let mut runner = BatchRunner::new(&config);
+            runner.run(&inline);
+            if let Some(file) = file {
+                runner.run_file(file, inline.len() as u32)
+                    .map_err(|e| format!("读取文件 '{}' 出错: {}", params.file.as_deref().unwrap_or(""), e))?;
+            }
+            Ok(runner.finish())
+        })
+        .await
+        .map_err(|e| CallToolError::new(crate::error::ServiceError::Generic(e.to_string())))?
+        .map_err(invalid)?;
+        tool_result(text, &output)
     }
 }
 
+fn parse_materiality(materiality: &str) -> Result<Materiality, CallToolError> {
+    Materiality::parse(materiality).map_err(|message| CallToolError::new(crate::error::ServiceError::InvalidExpression(message)))
+}
+
 fn parse_percent_rounding(strategy: &str) -> Result<PercentRounding, CallToolError> {
-    match strategy {
-        "divide_by_100_then_round" => Ok(PercentRounding::DivideBy100ThenRound),
-        "round_then_divide_by_100" => Ok(PercentRounding::RoundThenDivideBy100),
-        _ => Err(CallToolError::new(crate::error::ServiceError::InvalidExpression(
-            format!("无效的百分数处理策略: {}，支持的策略：divide_by_100_then_round, round_then_divide_by_100", strategy)
-        ))),
-    }
+    PercentRounding::parse(strategy).ok_or_else(|| CallToolError::new(crate::error::ServiceError::InvalidExpression(
+        format!("无效的百分数处理策略: {}，支持的策略：{}", strategy, PercentRounding::NAMES)
+    )))
 }
 
-fn parse_expected_value(expected_str: &str, decimals: u32, strategy: PercentRounding) -> Result<f64, CallToolError> {
-    // 使用和计算器相同的逻辑来解析预期值
-    let dummy_expr = expected_str.trim();
-    
-    // 如果包含百分号，需要按照策略处理
-    if dummy_expr.contains('%') {
-        // 创建一个简单的表达式来利用现有的计算逻辑
-        let calc_result = calculate(dummy_expr, decimals, strategy)
-            .map_err(|e| CallToolError::new(crate::error::ServiceError::from(e)))?;
-        Ok(calc_result)
-    } else {
-        // 不包含百分号，使用现有的数字解析逻辑
-        let mut chars = dummy_expr.chars().peekable();
-        let num_str = consume_number_for_expected(&mut chars);
-        
-        if !num_str.is_empty() {
-            let parsed = num_str.parse::<f64>()
-                .map_err(|_| CallToolError::new(crate::error::ServiceError::InvalidExpression(
-                    format!("无法解析预期值: {}", expected_str)
-                )))?;
-            Ok(parsed)
-        } else {
-            Err(CallToolError::new(crate::error::ServiceError::InvalidExpression(
-                format!("无效的预期值格式: {}", expected_str)
-            )))
+/// 非等式断言的验证输出：断言、实际值和结果，同时填写结构化结果
+fn assertion_report(expression: &str, assertion: &Assertion, options: &CalcOptions, structured: &mut ValidateOutput) -> String {
+    let mut output = format!("表达式: {}\n断言: {}", expression, assertion);
+    match calculate_with(expression, options) {
+        Ok(actual) => {
+            structured.actual = Some(actual);
+            structured.passed = assertion.check(actual, options);
+            output.push_str(&format!("\n实际值: {}\n验证结果: {}", actual, if structured.passed { "通过" } else { "失败" }));
+        }
+        Err(e) => {
+            structured.error = Some(e.render(expression));
+            output.push_str(&format!("\n验证结果: 失败\n计算错误: {}", e.render(expression)));
         }
     }
+    output
 }
 
-// 辅助函数：为预期值解析数字（复用计算器的逻辑）
-fn consume_number_for_expected(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
-    use crate::tools::calculator::normalize_number;
-    
-    let mut num_str = String::new();
-    let mut has_digit = false;
-    
-    while let Some(&c) = chars.peek() {
-        if c.is_ascii_digit() {
-            num_str.push(c);
-            has_digit = true;
-            chars.next();
-        } else if (c == '.' || c == ',' || c == ' ' || c == '\'' || c == '-') && has_digit {
-            // 支持负数和千分位分隔符
-            num_str.push(c);
-            chars.next();
-        } else if c == '-' && !has_digit {
-            // 负号在开头
-            num_str.push(c);
-            chars.next();
-        } else {
-            break;
-        }
+/// 列出表达式中的操作数及其预先舍入后的值；表达式中没有任何操作数标记时返回 `None`
+fn labelled_operands(expression: &str, options: &CalcOptions) -> Option<String> {
+    let ast = parse(expression, options).ok()?;
+    let literals = ast.literals();
+    if literals.iter().all(|literal| literal.label.is_none()) {
+        return None;
+    }
+    let operands: Vec<String> = literals
+        .iter()
+        .map(|literal| match &literal.label {
+            Some(label) => format!("{}={}", label, literal_value(literal, options)),
+            None => format!("{}", literal_value(literal, options)),
+        })
+        .collect();
+    Some(operands.join(", "))
+}
+
+/// 录入错误检查的输出，每条建议一行；没有发现时返回空字符串
+fn typo_report(expression: &str, expected: f64, difference: f64, options: &CalcOptions, variables: &calculator::Variables) -> String {
+    let mut lines = difference_hints(difference, options.decimals);
+    if let Ok(suggestions) = suggest_typos(expression, expected, options, variables) {
+        lines.extend(suggestions.iter().map(|suggestion| suggestion.to_string()));
+    }
+    if lines.is_empty() {
+        return String::new();
     }
-    
-    if has_digit {
-        normalize_number(&num_str)
-    } else {
-        num_str
+    let mut report = String::from("\n可能的录入错误:");
+    for line in lines {
+        report.push_str(&format!("\n  - {}", line));
     }
+    report
+}
+
+fn parse_reference_value(name: &str) -> Result<ReferenceValue, CallToolError> {
+    ReferenceValue::parse(name).ok_or_else(|| CallToolError::new(crate::error::ServiceError::InvalidExpression(
+        format!("无效的引用取值: {}，支持：{}", name, ReferenceValue::NAMES)
+    )))
+}
+
+fn parse_decimals(decimals: Option<u32>) -> Result<u32, CallToolError> {
+    check_decimals(decimals.unwrap_or(DEFAULT_DECIMALS))
+        .map_err(|e| CallToolError::new(crate::error::ServiceError::InvalidExpression(e)))
+}
+
+fn parse_tolerance(tolerance: &str) -> Result<Tolerance, CallToolError> {
+    Tolerance::parse(tolerance).ok_or_else(|| CallToolError::new(crate::error::ServiceError::InvalidExpression(
+        format!("无效的容差: {}，支持的写法：{}", tolerance, Tolerance::SYNTAX)
+    )))
+}
+
+fn parse_number_format(format: &str) -> Result<NumberFormat, CallToolError> {
+    NumberFormat::parse(format).ok_or_else(|| CallToolError::new(crate::error::ServiceError::InvalidExpression(
+        format!("无效的数字格式: {}，支持的格式：{}", format, NumberFormat::NAMES)
+    )))
+}
+
+/// 确定要验证的算式和预期值：`expression` 可以是等式 `左边 = 右边`，此时不能再指定 `expected`
+fn split_validation<'a>(expression: &'a str, expected: Option<&'a str>) -> Result<(&'a str, &'a str), CallToolError> {
+    let invalid = |message: &str| CallToolError::new(crate::error::ServiceError::InvalidExpression(message.to_string()));
+    match (split_equation(expression), expected) {
+        (Some(_), Some(_)) => Err(invalid("等式形式的表达式不能同时指定预期值")),
+        (Some(equation), None) => Ok(equation),
+        (None, Some(expected)) => Ok((expression, expected)),
+        (None, None) => Err(invalid("缺少预期值：请指定 expected，或使用等式形式 `左边 = 右边`")),
+    }
+}
+
+fn parse_expected_value(expected_str: &str, options: &CalcOptions) -> Result<f64, CallToolError> {
+    // 预期值按与算式相同的规则完整解析，多余的字符会被报告为错误
+    let expected = expected_str.trim();
+    evaluate_expected(expected, options)
+        .map_err(|e| CallToolError::new(crate::error::ServiceError::InvalidExpression(
+            format!("无法解析预期值: {}", e.render(expected))
+        )))
+}
+
+/// 解析预期值或断言（`>= 0`、`between 1 and 2` 等）
+fn parse_assertion(expected_str: &str, options: &CalcOptions) -> Result<Assertion, CallToolError> {
+    let expected = expected_str.trim();
+    Assertion::parse(expected, options)
+        .map_err(|e| CallToolError::new(crate::error::ServiceError::InvalidExpression(
+            format!("无法解析预期值: {}", e.render(expected))
+        )))
 }
 
 impl CalculateTool {
@@ -230,14 +515,33 @@ impl CalculateTool {
     ) -> Result<CallToolResult, CallToolError> {
         let percent_rounding = params.percent_rounding.as_deref().unwrap_or("round_then_divide_by_100");
         let strategy = parse_percent_rounding(percent_rounding)?;
-        let decimals = params.decimals.unwrap_or(2);
+        let number_format = parse_number_format(params.number_format.as_deref().unwrap_or("auto"))?;
+        let options = CalcOptions::new(parse_decimals(params.decimals)?, strategy)
+            .with_number_format(number_format)
+            .with_strict_grouping(params.strict_grouping.unwrap_or(false));
         
-        let result = calculate(&params.expression, decimals, strategy)
-            .map_err(|e| CallToolError::new(crate::error::ServiceError::from(e)))?;
+        let (result, steps) = calculate_steps(&params.expression, &options)
+            .map_err(|e| CallToolError::new(crate::error::ServiceError::from_calc(&params.expression, e)))?;
+        
+        let mut output = format!("表达式: {}\n结果: {}", params.expression, result);
+        let mut step_lines = None;
+        if params.show_steps.unwrap_or(false) {
+            let mut lines: Vec<String> = steps.iter().map(|step| step.to_string()).collect();
+            lines.push(format!("最终舍入 → {}", result));
+            output.push_str("\n计算步骤:");
+            for (index, line) in lines.iter().enumerate() {
+                output.push_str(&format!("\n  {}. {}", index + 1, line));
+            }
+            step_lines = Some(lines);
+        }
         
-        Ok(CallToolResult::text_content(vec![TextContent::from(
-            format!("表达式: {}\n结果: {}", params.expression, result)
-        )]))
+        tool_result(output, &CalculateOutput {
+            expression: params.expression.clone(),
+            value: result,
+            formatted: number_format.format(result, options.decimals),
+            settings: Settings::new(&options),
+            steps: step_lines,
+        })
     }
 }
 
@@ -248,30 +552,374 @@ impl ValidateTool {
     ) -> Result<CallToolResult, CallToolError> {
         let percent_rounding = params.percent_rounding.as_deref().unwrap_or("round_then_divide_by_100");
         let strategy = parse_percent_rounding(percent_rounding)?;
-        let decimals = params.decimals.unwrap_or(2);
+        let number_format = parse_number_format(params.number_format.as_deref().unwrap_or("auto"))?;
+        let tolerance = parse_tolerance(params.tolerance.as_deref().unwrap_or(""))?;
+        let (expression, expected) = split_validation(&params.expression, params.expected.as_deref())?;
+        let decimals = params.decimals.unwrap_or_default().resolve(expected, number_format);
+        let options = CalcOptions::new(decimals, strategy)
+            .with_number_format(number_format)
+            .with_strict_grouping(params.strict_grouping.unwrap_or(false))
+            .with_tolerance(tolerance);
         
-        // 解析预期值，支持百分数和千分位
-        let expected_value = parse_expected_value(&params.expected, decimals, strategy)?;
+        // 解析预期值，支持百分数、千分位、表达式和比较断言
+        let assertion = parse_assertion(expected, &options)?;
+        let mut structured = ValidateOutput {
+            expression: expression.to_string(),
+            expected: expected.to_string(),
+            assertion: assertion.to_string(),
+            expected_value: None,
+            actual: None,
+            difference: None,
+            allowed: None,
+            passed: false,
+            error: None,
+            settings: Settings::new(&options),
+        };
+        let expected_value = match assertion {
+            Assertion::Equal(value) => value,
+            assertion => {
+                let output = assertion_report(expression, &assertion, &options, &mut structured);
+                return tool_result(output, &structured);
+            }
+        };
         
-        let is_valid = validate(&params.expression, expected_value, decimals, strategy);
+        let check = check_with(expression, expected_value, &options);
+        let is_valid = check.as_ref().is_ok_and(|check| check.passed);
+        structured.expected_value = Some(expected_value);
+        structured.passed = is_valid;
+        if let Ok(check) = &check {
+            structured.actual = Some(check.actual);
+            structured.difference = Some(check.difference);
+            structured.allowed = Some(check.allowed);
+        }
         
-        Ok(CallToolResult::text_content(vec![TextContent::from(
-            format!(
-                "表达式: {}\n预期值: {} (解析为: {})\n验证结果: {}",
-                params.expression,
-                params.expected,
-                expected_value,
-                if is_valid { "通过" } else { "失败" }
-            )
-        )]))
+        let mut output = format!(
+            "表达式: {}\n预期值: {} (解析为: {})\n验证结果: {}\n容差: {}",
+            expression,
+            expected,
+            expected_value,
+            if is_valid { "通过" } else { "失败" },
+            tolerance
+        );
+        if params.decimals == Some(Decimals::Auto) {
+            output.push_str(&format!("\n小数位数: {}（由预期值 {} 推断）", decimals, expected));
+        }
+        match check {
+            Ok(check) => {
+                output.push_str(&format!("\n差异: {}", check));
+                if !is_valid {
+                    if let Some(operands) = labelled_operands(expression, &options) {
+                        output.push_str(&format!("\n操作数: {}", operands));
+                    }
+                    if let Ok(discrepancy) = analyze(expression, expected_value, &options) {
+                        output.push_str(&format!("\n{}", discrepancy));
+                    }
+                    if params.typo_check.unwrap_or(false) {
+                        output.push_str(&typo_report(expression, expected_value, check.difference, &options, &calculator::Variables::new()));
+                    }
+                }
+            }
+            // 计算出错导致验证失败时，附上出错位置
+            Err(e) => {
+                structured.error = Some(e.render(expression));
+                output.push_str(&format!("\n计算错误: {}", e.render(expression)));
+            }
+        }
+        
+        tool_result(output, &structured)
     }
 }
 
-tool_box!(
-    CalculatorTools,
-    [
-        CalculateTool,
-        ValidateTool,
-        BatchValidateTool
-    ]
-);
\ No newline at end of file
+impl ParseTool {
+    pub async fn run_tool(
+        params: Self,
+        _context: &(),
+    ) -> Result<CallToolResult, CallToolError> {
+        let number_format = parse_number_format(params.number_format.as_deref().unwrap_or("auto"))?;
+        // 解析不涉及舍入，小数位数和百分数策略不影响结果
+        let options = CalcOptions::new(2, PercentRounding::RoundThenDivideBy100)
+            .with_number_format(number_format)
+            .with_strict_grouping(params.strict_grouping.unwrap_or(false));
+        
+        let ast = parse(&params.expression, &options)
+            .map_err(|e| CallToolError::new(crate::error::ServiceError::from_calc(&params.expression, e)))?;
+        let tree = serde_json::to_string_pretty(&ast)
+            .map_err(|e| CallToolError::new(crate::error::ServiceError::from(e)))?;
+        
+        let text = format!("表达式: {}\n规范形式: {}\n语法树:\n{}", params.expression, ast, tree);
+        let tree = serde_json::to_value(&ast)
+            .map_err(|e| CallToolError::new(crate::error::ServiceError::from(e)))?;
+        tool_result(text, &ParseOutput {
+            expression: params.expression.clone(),
+            canonical: ast.to_string(),
+            tree: SyntaxTree(tree),
+        })
+    }
+}
+
+impl CalculateScriptTool {
+    pub async fn run_tool(
+        params: Self,
+        _context: &(),
+    ) -> Result<CallToolResult, CallToolError> {
+        let percent_rounding = params.percent_rounding.as_deref().unwrap_or("round_then_divide_by_100");
+        let strategy = parse_percent_rounding(percent_rounding)?;
+        let number_format = parse_number_format(params.number_format.as_deref().unwrap_or("auto"))?;
+        let options = CalcOptions::new(parse_decimals(params.decimals)?, strategy)
+            .with_number_format(number_format)
+            .with_strict_grouping(params.strict_grouping.unwrap_or(false));
+        
+        let result = run_script(&params.script, &options)
+            .map_err(|e| CallToolError::new(crate::error::ServiceError::from_calc(&params.script, e)))?;
+        
+        let mut output = String::from("中间结果:");
+        for binding in &result.bindings {
+            output.push_str(&format!("\n  {} = {}", binding.name, binding.value));
+        }
+        if result.bindings.is_empty() {
+            output.push_str(" 无");
+        }
+        output.push_str(&format!("\n结果: {}", result.value));
+        
+        tool_result(output, &ScriptOutput {
+            formatted: number_format.format(result.value, options.decimals),
+            settings: Settings::new(&options),
+            bindings: result.bindings,
+            value: result.value,
+        })
+    }
+}
+
+/// 搜索舍入口径时允许的最大小数位数
+const MAX_SEARCH_DECIMALS: u32 = 10;
+
+impl FindRoundingConventionTool {
+    pub async fn run_tool(
+        params: Self,
+        _context: &(),
+    ) -> Result<CallToolResult, CallToolError> {
+        let number_format = parse_number_format(params.number_format.as_deref().unwrap_or("auto"))?;
+        let max_decimals = params.max_decimals.unwrap_or(4);
+        if max_decimals > MAX_SEARCH_DECIMALS {
+            return Err(CallToolError::new(crate::error::ServiceError::InvalidExpression(
+                format!("最大小数位数不能超过 {}: {}", MAX_SEARCH_DECIMALS, max_decimals)
+            )));
+        }
+        // 报表数字按最高精度读取，百分数不因舍入失真
+        let base = CalcOptions::new(MAX_SEARCH_DECIMALS, PercentRounding::DivideBy100ThenRound)
+            .with_number_format(number_format)
+            .with_strict_grouping(params.strict_grouping.unwrap_or(false));
+        let reported = parse_expected_value(&params.reported, &base)?;
+        
+        let conventions = search_conventions(&params.expression, reported, max_decimals, &base)
+            .map_err(|e| CallToolError::new(crate::error::ServiceError::from_calc(&params.expression, e)))?;
+        
+        let mut output = format!("表达式: {}\n报表数字: {} (解析为: {})", params.expression, params.reported, reported);
+        if conventions.is_empty() {
+            output.push_str(&format!("\n未找到能复现该数字的口径（小数位数 0-{}）", max_decimals));
+        } else {
+            output.push_str(&format!("\n找到 {} 种口径:", conventions.len()));
+            for (index, convention) in conventions.iter().enumerate() {
+                output.push_str(&format!("\n  {}. {}", index + 1, convention));
+            }
+        }
+        
+        tool_result(output, &ConventionOutput {
+            expression: params.expression.clone(),
+            reported,
+            conventions: conventions.iter().map(ConventionMatch::from).collect(),
+        })
+    }
+}
+
+impl ValidateStatementTool {
+    pub async fn run_tool(
+        params: Self,
+        _context: &(),
+    ) -> Result<CallToolResult, CallToolError> {
+        let number_format = parse_number_format(params.number_format.as_deref().unwrap_or("auto"))?;
+        let decimals = match params.decimals.unwrap_or_default() {
+            Decimals::Fixed(decimals) => decimals,
+            Decimals::Auto => infer_statement_decimals(&params.statement, number_format),
+        };
+        let options = CalcOptions::new(decimals, PercentRounding::RoundThenDivideBy100)
+            .with_number_format(number_format)
+            .with_strict_grouping(params.strict_grouping.unwrap_or(false))
+            .with_tolerance(parse_tolerance(params.tolerance.as_deref().unwrap_or(""))?);
+
+        let (text, output) = statement::validate(&params.statement, params.identities.as_deref(), &options);
+        tool_result(text, &output)
+    }
+}
+
+impl CrossfootTableTool {
+    pub async fn run_tool(
+        params: Self,
+        _context: &(),
+    ) -> Result<CallToolResult, CallToolError> {
+        let number_format = parse_number_format(params.number_format.as_deref().unwrap_or("auto"))?;
+        let decimals = match params.decimals.unwrap_or_default() {
+            Decimals::Fixed(decimals) => decimals,
+            Decimals::Auto => infer_table_decimals(&params.rows, number_format),
+        };
+        let options = CalcOptions::new(decimals, PercentRounding::RoundThenDivideBy100)
+            .with_number_format(number_format)
+            .with_strict_grouping(params.strict_grouping.unwrap_or(false))
+            .with_tolerance(parse_tolerance(params.tolerance.as_deref().unwrap_or(""))?);
+        let table = TableLayout::new(&params.columns, &params.rows, params.total_row.as_deref(), params.total_column.as_deref())
+            .map_err(|message| CallToolError::new(crate::error::ServiceError::InvalidExpression(message)))?;
+
+        let (text, output) = table::validate(&table, &options);
+        tool_result(text, &output)
+    }
+}
+
+impl ValidateCsvTool {
+    pub async fn run_tool(
+        params: Self,
+        context: &FileAccess,
+    ) -> Result<CallToolResult, CallToolError> {
+        let invalid = |message: String| CallToolError::new(crate::error::ServiceError::InvalidExpression(message));
+        let config = params.batch_config()?;
+        let file = context.open(&params.path).map_err(invalid)?;
+        let (text, output) = tokio::task::spawn_blocking(move || {
+            validate_csv(&config, file).map_err(|e| format!("读取文件 '{}' 出错: {}", params.path, e))
+        })
+        .await
+        .map_err(|e| CallToolError::new(crate::error::ServiceError::Generic(e.to_string())))?
+        .map_err(invalid)?;
+        tool_result(text, &output)
+    }
+
+    /// 工具参数对应的批量验证设置，命令行模式也使用
+    pub fn batch_config(&self) -> Result<BatchConfig, CallToolError> {
+        Ok(BatchConfig {
+            decimals: self.decimals,
+            percent_rounding: parse_percent_rounding(self.percent_rounding.as_deref().unwrap_or("round_then_divide_by_100"))?,
+            number_format: parse_number_format(self.number_format.as_deref().unwrap_or("auto"))?,
+            strict_grouping: self.strict_grouping.unwrap_or(false),
+            tolerance: parse_tolerance(self.tolerance.as_deref().unwrap_or(""))?,
+            typo_check: false,
+            reference_value: ReferenceValue::Expected,
+            max_failures: self.max_failures.filter(|&max| max > 0),
+            failures_only: self.failures_only.unwrap_or(false),
+            max_output_chars: self.max_output_chars,
+            materiality: self.materiality.as_deref().map(parse_materiality).transpose()?,
+            threads: BatchConfig::available_threads(),
+        })
+    }
+}
+
+impl CalculateGridTool {
+    pub async fn run_tool(
+        params: Self,
+        _context: &(),
+    ) -> Result<CallToolResult, CallToolError> {
+        let invalid = |message: String| CallToolError::new(crate::error::ServiceError::InvalidExpression(message));
+        let grid = match (params.grid, params.csv.as_deref()) {
+            (Some(rows), None) => Grid::new(rows),
+            (None, Some(csv)) => Grid::from_csv(csv).map_err(|e| invalid(format!("无法读取 CSV 表格: {}", e)))?,
+            (Some(_), Some(_)) => return Err(invalid("grid 和 csv 只能指定一个".to_string())),
+            (None, None) => return Err(invalid("缺少表格：请指定 grid 或 csv".to_string())),
+        };
+        let options = CalcOptions::new(parse_decimals(params.decimals)?, parse_percent_rounding(params.percent_rounding.as_deref().unwrap_or("round_then_divide_by_100"))?)
+            .with_number_format(parse_number_format(params.number_format.as_deref().unwrap_or("auto"))?)
+            .with_strict_grouping(params.strict_grouping.unwrap_or(false))
+            .with_tolerance(parse_tolerance(params.tolerance.as_deref().unwrap_or(""))?);
+
+        let bounds = grid.bounds().map(|bounds| bounds.to_string());
+        let mut lines = Vec::new();
+        let mut results = Vec::new();
+        for expression in &params.expressions {
+            let (formula, expected) = match split_equation(expression) {
+                Some((formula, expected)) => (formula.trim(), Some(expected.trim())),
+                None => (expression.trim(), None),
+            };
+            let mut result = GridResult { expression: expression.clone(), value: None, assertion: None, passed: false, error: None };
+            match evaluate_on_grid(&grid, formula, expected, &options) {
+                Ok((value, assertion)) => {
+                    result.passed = assertion.is_none_or(|assertion| assertion.check(value, &options));
+                    match assertion {
+                        Some(assertion) => {
+                            let verdict = if result.passed { "通过" } else { "不通过" };
+                            lines.push(format!("{}: {} → {} {} ({})", expression, formula, value, assertion, verdict));
+                        }
+                        None => lines.push(format!("{} = {}", expression, value)),
+                    }
+                    result.value = Some(value);
+                    result.assertion = assertion.map(|assertion| assertion.to_string());
+                }
+                Err(error) => {
+                    lines.push(format!("{}: 错误: {}", expression, error.replace('\n', "\n    ")));
+                    result.error = Some(error);
+                }
+            }
+            results.push(result);
+        }
+
+        let all_passed = results.iter().all(|result| result.passed);
+        let text = report_text(format!("表格范围: {}", bounds.as_deref().unwrap_or("（空）")), &options, lines);
+        tool_result(text, &GridOutput { bounds, results, all_passed, settings: Settings::new(&options) })
+    }
+}
+
+/// 在表格上计算一个表达式，有预期值时一并解析断言
+fn evaluate_on_grid(grid: &Grid, formula: &str, expected: Option<&str>, options: &CalcOptions) -> Result<(f64, Option<Assertion>), String> {
+    let ast = parse(formula, options).map_err(|e| e.render(formula))?;
+    let mut variables = calculator::Variables::new();
+    grid.variables(&ast, options, &mut variables)?;
+    let value = calculator::evaluate_traced(&ast, options, &variables, None).map_err(|e| e.render(formula))?;
+    let Some(expected) = expected else { return Ok((options.round(value), None)) };
+    for operand in assertion::operands(expected) {
+        if let Ok(ast) = parse(operand, options) {
+            grid.variables(&ast, options, &mut variables)?;
+        }
+    }
+    let assertion = Assertion::parse_in(expected, options, &variables).map_err(|e| format!("无法解析预期值: {}", e.render(expected)))?;
+    Ok((options.round(value), Some(assertion)))
+}
+
+impl RecalculateXlsxTool {
+    pub async fn run_tool(
+        params: Self,
+        context: &FileAccess,
+    ) -> Result<CallToolResult, CallToolError> {
+        let invalid = |message: String| CallToolError::new(crate::error::ServiceError::InvalidExpression(message));
+        let options = CalcOptions::new(parse_decimals(params.decimals)?, parse_percent_rounding(params.percent_rounding.as_deref().unwrap_or("round_then_divide_by_100"))?)
+            .with_number_format(NumberFormat::EnUs)
+            .with_tolerance(parse_tolerance(params.tolerance.as_deref().unwrap_or(""))?);
+        let file = context.open(&params.path).map_err(invalid)?;
+        let (text, output) = tokio::task::spawn_blocking(move || xlsx::recalculate_workbook(file, params.sheet.as_deref(), &options)
+            .map_err(|e| format!("读取文件 '{}' 出错: {}", params.path, e)))
+            .await
+            .map_err(|e| CallToolError::new(crate::error::ServiceError::Generic(e.to_string())))?
+            .map_err(invalid)?;
+        tool_result(text, &output)
+    }
+}
+
+// `tool_box!` 以工具结构体名作为枚举变体名，变体与结构体同名、统一以 `Tool` 结尾；
+// 宏调用上的属性不作用于展开的枚举，因此放在只包含该枚举的模块上
+#[allow(clippy::enum_variant_names)]
+mod registry {
+    use super::*;
+
+    tool_box!(
+        CalculatorTools,
+        [
+            CalculateTool,
+            ValidateTool,
+            BatchValidateTool,
+            ParseTool,
+            CalculateScriptTool,
+            FindRoundingConventionTool,
+            ValidateStatementTool,
+            CrossfootTableTool,
+            ValidateCsvTool,
+            CalculateGridTool,
+            RecalculateXlsxTool
+        ]
+    );
+}
+
+pub use registry::CalculatorTools;
@@ -0,0 +1,163 @@
+//! # 数字格式（区域设置）
+//!
+//! `normalize_number` 通过启发式规则猜测小数点与千分位分隔符，例如 `1,234` 会被识别为欧式小数 1.234。
+//! 当调用方明确知道数据来源的格式时，可以指定 `NumberFormat`，由它确定性地决定小数点和分组字符，
+//! 表达式与预期值都按同一格式解析。
+
+use crate::tools::calculator::normalize_number;
+use crate::tools::precision::MAX_DECIMALS;
+
+/// 定义数字字面量的格式
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum NumberFormat {
+    /// 自动识别，沿用 `normalize_number` 的启发式规则。
+    #[default]
+    Auto,
+    /// 美式: 1,234,567.89
+    EnUs,
+    /// 德式: 1.234.567,89
+    DeDe,
+    /// 法式: 1 234 567,89（普通空格、不换行空格或窄不换行空格分组）
+    FrFr,
+    /// 瑞士: 1'234'567.89
+    DeCh,
+    /// 中文: 1,234,567.89
+    ZhCn,
+    /// 印度: 12,34,567.89（lakh/crore 分组）
+    EnIn,
+}
+
+impl NumberFormat {
+    /// 所有支持的格式名称，用于错误提示
+    pub const NAMES: &'static str = "auto, en-US, de-DE, fr-FR, de-CH, zh-CN, en-IN";
+
+    /// 从名称解析格式，大小写不敏感，`_` 与 `-` 等价
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().replace('_', "-").as_str() {
+            "auto" => Some(NumberFormat::Auto),
+            "en-us" => Some(NumberFormat::EnUs),
+            "de-de" => Some(NumberFormat::DeDe),
+            "fr-fr" => Some(NumberFormat::FrFr),
+            "de-ch" => Some(NumberFormat::DeCh),
+            "zh-cn" => Some(NumberFormat::ZhCn),
+            "en-in" => Some(NumberFormat::EnIn),
+            _ => None,
+        }
+    }
+
+    /// 格式的规范名称
+    pub fn name(&self) -> &'static str {
+        match self {
+            NumberFormat::Auto => "auto",
+            NumberFormat::EnUs => "en-US",
+            NumberFormat::DeDe => "de-DE",
+            NumberFormat::FrFr => "fr-FR",
+            NumberFormat::DeCh => "de-CH",
+            NumberFormat::ZhCn => "zh-CN",
+            NumberFormat::EnIn => "en-IN",
+        }
+    }
+
+    /// 推断 `auto` 格式下一个原始数字字符串实际使用的格式
+    ///
+    /// 选择第一个能解释全部分隔符、且解析结果与自动识别一致的格式；没有分隔符时返回 `Auto`。
+    pub fn detect(raw: &str) -> Self {
+        let raw = raw.trim();
+        if raw.chars().all(|c| c.is_ascii_digit()) {
+            return NumberFormat::Auto;
+        }
+        let auto = normalize_number(raw);
+        [NumberFormat::EnUs, NumberFormat::DeDe, NumberFormat::FrFr, NumberFormat::DeCh]
+            .into_iter()
+            .find(|f| raw.chars().all(|c| c.is_ascii_digit() || f.is_separator(c)) && f.normalize(raw) == auto)
+            .unwrap_or(NumberFormat::Auto)
+    }
+
+    /// 小数点字符；`Auto` 没有固定的小数点
+    pub fn decimal_separator(&self) -> Option<char> {
+        match self {
+            NumberFormat::Auto => None,
+            NumberFormat::EnUs | NumberFormat::DeCh | NumberFormat::ZhCn | NumberFormat::EnIn => Some('.'),
+            NumberFormat::DeDe | NumberFormat::FrFr => Some(','),
+        }
+    }
+
+    /// 千分位分组字符
+    pub fn group_separators(&self) -> &'static [char] {
+        match self {
+            NumberFormat::Auto => &['.', ',', ' ', '\''],
+            NumberFormat::EnUs | NumberFormat::ZhCn | NumberFormat::EnIn => &[','],
+            NumberFormat::DeDe => &['.'],
+            NumberFormat::FrFr => &[' ', '\u{a0}', '\u{202f}'],
+            NumberFormat::DeCh => &['\'', '\u{2019}'],
+        }
+    }
+
+    /// 判断字符在数字内部（已出现数字之后）是否可以作为分隔符被消费
+    pub fn is_separator(&self, c: char) -> bool {
+        self.decimal_separator() == Some(c) || self.group_separators().contains(&c)
+    }
+
+    /// 按格式输出数字：保留 `decimals` 位小数（最多 `MAX_DECIMALS` 位）并插入分组字符；`Auto` 按美式输出
+    pub fn format(&self, value: f64, decimals: u32) -> String {
+        let fixed = format!("{:.*}", decimals.min(MAX_DECIMALS) as usize, value.abs());
+        let (integer, fraction) = match fixed.split_once('.') {
+            Some((integer, fraction)) => (integer, Some(fraction)),
+            None => (fixed.as_str(), None),
+        };
+        let (group, decimal) = match self {
+            NumberFormat::Auto | NumberFormat::EnUs | NumberFormat::ZhCn | NumberFormat::EnIn => (',', '.'),
+            NumberFormat::DeDe => ('.', ','),
+            NumberFormat::FrFr => (' ', ','),
+            NumberFormat::DeCh => ('\'', '.'),
+        };
+
+        // 从个位向左分组：印度格式先 3 位再每 2 位，其余每 3 位
+        let digits: Vec<char> = integer.chars().collect();
+        let mut grouped = Vec::new();
+        let mut size = 3;
+        let mut end = digits.len();
+        while end > size {
+            grouped.push(digits[end - size..end].iter().collect::<String>());
+            end -= size;
+            if *self == NumberFormat::EnIn {
+                size = 2;
+            }
+        }
+        grouped.push(digits[..end].iter().collect());
+        grouped.reverse();
+
+        let mut output = String::new();
+        // 舍入后为零时不输出负号
+        if value < 0.0 && fixed.chars().any(|c| c.is_ascii_digit() && c != '0') {
+            output.push('-');
+        }
+        output.push_str(&grouped.join(&group.to_string()));
+        if let Some(fraction) = fraction {
+            output.push(decimal);
+            output.push_str(fraction);
+        }
+        output
+    }
+
+    /// 标准化数字字符串：移除分组字符并把小数点统一为 `.`
+    pub fn normalize(&self, input: &str) -> String {
+        let Some(decimal) = self.decimal_separator() else {
+            return normalize_number(input);
+        };
+        let groups = self.group_separators();
+
+        input
+            .trim()
+            .chars()
+            .filter(|c| !groups.contains(c))
+            .map(|c| if c == decimal { '.' } else { c })
+            .collect()
+    }
+}
+
+impl serde::Serialize for NumberFormat {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
@@ -0,0 +1,539 @@
+//! # 结构化输出
+//!
+//! 每个工具除了给人阅读的文本外，还在 `structuredContent` 中返回结构化的结果，调用方不必从
+//! "结果:"、"(通过)" 之类的文本中解析数值和结论。本模块定义各工具的输出结构，并由 `#[derive(JsonSchema)]`
+//! 生成 `tools/list` 中声明的输出模式。
+
+use rust_mcp_sdk::macros::JsonSchema;
+use rust_mcp_sdk::schema::{schema_utils::CallToolError, CallToolResult, TextContent, ToolOutputSchema};
+use serde::Serialize;
+
+use crate::tools::calculator::{round_value, CalcOptions};
+use crate::tools::convention::Convention;
+use crate::tools::materiality::Severity;
+use crate::tools::script::Binding;
+use crate::tools::tolerance::Tolerance;
+
+/// 差异合计保留的小数位数，用于去掉浮点运算的尾差
+const DIFFERENCE_DECIMALS: u32 = 10;
+
+/// 计算所用的设置
+#[derive(Serialize, Debug, Clone, PartialEq, JsonSchema)]
+pub struct Settings {
+    /// 小数位数（decimals 为 auto 时为推断出的位数）
+    pub decimals: u32,
+    /// 百分数处理策略：divide_by_100_then_round 或 round_then_divide_by_100
+    pub percent_rounding: String,
+    /// 数字格式，例如 en-US、auto
+    pub number_format: String,
+    /// 容差，例如 ±0.01、±0.1%、±2 ulp；精确匹配时为 null
+    pub tolerance: Option<String>,
+}
+
+impl Settings {
+    pub fn new(options: &CalcOptions) -> Self {
+        Settings {
+            decimals: options.decimals,
+            percent_rounding: options.percent_rounding.name().to_string(),
+            number_format: options.number_format.name().to_string(),
+            tolerance: (options.tolerance != Tolerance::Exact).then(|| options.tolerance.to_string()),
+        }
+    }
+}
+
+/// `calculate` 的结果
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct CalculateOutput {
+    pub expression: String,
+    /// 最终舍入后的结果
+    pub value: f64,
+    /// 按数字格式和小数位数输出的结果，例如 1,234.50
+    pub formatted: String,
+    pub settings: Settings,
+    /// 逐步的计算过程，仅在 show_steps 为 true 时提供
+    pub steps: Option<Vec<String>>,
+}
+
+/// `validate` 的结果
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct ValidateOutput {
+    /// 要验证的算式（等式或断言形式时为左边）
+    pub expression: String,
+    /// 预期值或断言的原始文本
+    pub expected: String,
+    /// 规范化的断言，例如 = 3000、>= 0、介于 0.2 和 0.4 之间
+    pub assertion: String,
+    /// 预期值解析出的数值；断言不是等式时为 null
+    pub expected_value: Option<f64>,
+    /// 算式的计算结果；计算出错时为 null
+    pub actual: Option<f64>,
+    /// 实际值减预期值；断言不是等式或计算出错时为 null
+    pub difference: Option<f64>,
+    /// 容差允许的最大差异；断言不是等式或计算出错时为 null
+    pub allowed: Option<f64>,
+    pub passed: bool,
+    /// 计算错误信息
+    pub error: Option<String>,
+    pub settings: Settings,
+}
+
+/// `batch_validate` 中一行的结果
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct BatchResult {
+    /// 行号，从 1 开始，expressions 在前、items 在后
+    pub line: u32,
+    pub id: Option<String>,
+    pub label: Option<String>,
+    /// 该行无法解析时为 null
+    pub expression: Option<String>,
+    /// 预期值或断言的原始文本；该行无法解析时为 null
+    pub expected: Option<String>,
+    pub expected_value: Option<f64>,
+    pub actual: Option<f64>,
+    pub difference: Option<f64>,
+    pub passed: bool,
+    /// 格式错误、无效的设置、无效的预期值或计算错误
+    pub error: Option<String>,
+    /// 该行实际使用的设置；该行无法解析时为 null
+    pub settings: Option<Settings>,
+    /// 差异的分类：trivial（尾差）、below_threshold（低于重要性水平）或 material（重要）；通过或没有差异时为 null
+    pub severity: Option<String>,
+}
+
+impl BatchResult {
+    /// 无法解析的一行
+    pub fn invalid(line: u32, error: String) -> Self {
+        BatchResult {
+            line,
+            id: None,
+            label: None,
+            expression: None,
+            expected: None,
+            expected_value: None,
+            actual: None,
+            difference: None,
+            passed: false,
+            error: Some(error),
+            settings: None,
+            severity: None,
+        }
+    }
+}
+
+/// 一类差异的行数和差异绝对值的合计
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq, JsonSchema)]
+pub struct SeverityTotal {
+    pub count: u32,
+    pub total: f64,
+}
+
+impl SeverityTotal {
+    pub fn add(&mut self, difference: f64) {
+        self.count += 1;
+        self.total = round_value(self.total + difference.abs(), DIFFERENCE_DECIMALS);
+    }
+}
+
+/// 按重要性汇总的差异
+#[derive(Serialize, Debug, Clone, Default, PartialEq, JsonSchema)]
+pub struct MaterialitySummary {
+    /// 重要性水平的金额；未指定或无法计算时为 null
+    pub threshold: Option<f64>,
+    pub trivial: SeverityTotal,
+    pub below_threshold: SeverityTotal,
+    pub material: SeverityTotal,
+}
+
+impl MaterialitySummary {
+    pub fn total(&mut self, severity: Severity) -> &mut SeverityTotal {
+        match severity {
+            Severity::Trivial => &mut self.trivial,
+            Severity::BelowThreshold => &mut self.below_threshold,
+            Severity::Material => &mut self.material,
+        }
+    }
+}
+
+/// `batch_validate` 的结果
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct BatchOutput {
+    pub total: u32,
+    pub passed: u32,
+    pub failed: u32,
+    /// 因 fail_fast 或 max_failures 提前停止而未验证的行数
+    pub skipped: u32,
+    /// 因 max_output_chars 未列出的结果行数
+    pub omitted: u32,
+    pub all_passed: bool,
+    /// 失败行的差异按重要性分类的汇总
+    pub materiality: MaterialitySummary,
+    /// 各行的结果；failures_only 为 true 时只包含失败的行
+    pub results: Vec<BatchResult>,
+}
+
+/// `parse` 的语法树，结构见 `ast::Expr`
+#[derive(Serialize, Debug, Clone)]
+#[serde(transparent)]
+pub struct SyntaxTree(pub serde_json::Value);
+
+impl SyntaxTree {
+    pub fn json_schema() -> serde_json::Map<String, serde_json::Value> {
+        let mut map = serde_json::Map::new();
+        map.insert("type".to_string(), serde_json::Value::String("object".to_string()));
+        map.insert(
+            "description".to_string(),
+            serde_json::Value::String("语法树节点，type 为 number、unary、binary、group、variable、call 或 range".to_string()),
+        );
+        map
+    }
+}
+
+/// `parse` 的结果
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct ParseOutput {
+    pub expression: String,
+    /// 规范形式
+    pub canonical: String,
+    pub tree: SyntaxTree,
+}
+
+/// `calculate_script` 的结果
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct ScriptOutput {
+    /// 按赋值顺序排列的中间结果
+    pub bindings: Vec<Binding>,
+    /// 最后一条语句的值
+    pub value: f64,
+    /// 按数字格式和小数位数输出的结果
+    pub formatted: String,
+    pub settings: Settings,
+}
+
+/// 一种能复现报表数字的口径
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct ConventionMatch {
+    pub decimals: u32,
+    /// 舍入方式：half_up、half_even 或 down
+    pub rounding_mode: String,
+    /// 百分数处理策略；算式不含百分数或不预先舍入时为 null
+    pub percent_rounding: Option<String>,
+    pub pre_round: bool,
+    pub step_rounding: bool,
+    /// 口径的中文说明
+    pub description: String,
+}
+
+impl From<&Convention> for ConventionMatch {
+    fn from(convention: &Convention) -> Self {
+        ConventionMatch {
+            decimals: convention.decimals,
+            rounding_mode: convention.rounding_mode.name().to_string(),
+            percent_rounding: convention.percent_rounding.map(|strategy| strategy.name().to_string()),
+            pre_round: convention.pre_round,
+            step_rounding: convention.step_rounding,
+            description: convention.to_string(),
+        }
+    }
+}
+
+/// `find_rounding_convention` 的结果
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct ConventionOutput {
+    pub expression: String,
+    /// 报表数字解析出的数值
+    pub reported: f64,
+    pub conventions: Vec<ConventionMatch>,
+}
+
+/// 子项的名称和金额
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct StatementChild {
+    pub label: String,
+    /// 舍入后的金额，在父项中减去的项为负数
+    pub amount: f64,
+}
+
+/// 报表中一个小计或合计的核对结果
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct StatementNodeResult {
+    /// 从顶层到该项的项目名称，例如 ["资产", "流动资产"]
+    pub path: Vec<String>,
+    /// 报表金额
+    pub reported: f64,
+    /// 子项之和
+    pub children_sum: f64,
+    /// 子项之和减报表金额
+    pub difference: f64,
+    /// 容差允许的最大差异
+    pub allowed: f64,
+    pub passed: bool,
+    pub children: Vec<StatementChild>,
+}
+
+/// 恒等式的核对结果
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct IdentityResult {
+    pub identity: String,
+    /// 规范化的右边，例如 = 10000；无法计算时为 null
+    pub assertion: Option<String>,
+    /// 左边的值；无法计算时为 null
+    pub actual: Option<f64>,
+    pub passed: bool,
+    pub error: Option<String>,
+}
+
+/// 无法核对的项目
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct StatementError {
+    pub path: Vec<String>,
+    pub error: String,
+}
+
+/// `validate_statement` 的结果
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct StatementOutput {
+    /// 核对的小计、合计和恒等式的个数
+    pub checked: u32,
+    /// 不符的小计、合计和不成立的恒等式的个数
+    pub failed: u32,
+    /// 全部成立且没有无法核对的项目
+    pub all_passed: bool,
+    pub nodes: Vec<StatementNodeResult>,
+    pub identities: Vec<IdentityResult>,
+    pub errors: Vec<StatementError>,
+    pub settings: Settings,
+}
+
+/// 表格中一个合计单元格的核对结果
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct FootingResult {
+    /// 核对方向：row（该行的明细列之和）或 column（该列的明细行之和）
+    pub axis: String,
+    /// 合计单元格所在的行和列
+    pub row: String,
+    pub column: String,
+    /// 表格中的合计
+    pub reported: f64,
+    /// 明细之和
+    pub detail_sum: f64,
+    /// 明细之和减表格中的合计
+    pub difference: f64,
+    /// 容差允许的最大差异
+    pub allowed: f64,
+    pub passed: bool,
+    /// 参与求和的明细，标签为另一方向的列或行
+    pub details: Vec<StatementChild>,
+}
+
+/// 无法解析的单元格
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct TableCellError {
+    pub row: String,
+    pub column: String,
+    pub error: String,
+}
+
+/// `crossfoot_table` 的结果
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct CrossfootOutput {
+    /// 核对的合计单元格个数（总计按两个方向各计一次）
+    pub checked: u32,
+    /// 不符的个数
+    pub failed: u32,
+    /// 全部相符且没有无法解析的单元格
+    pub all_passed: bool,
+    pub footings: Vec<FootingResult>,
+    pub errors: Vec<TableCellError>,
+    pub settings: Settings,
+}
+
+/// 表格上一个表达式的计算结果
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct GridResult {
+    pub expression: String,
+    /// 计算结果；出错时为 null
+    pub value: Option<f64>,
+    /// 等式或断言形式的表达式中的断言，例如 `= 1000`、`>= 0`
+    pub assertion: Option<String>,
+    /// 是否满足断言；没有断言时只要求计算成功
+    pub passed: bool,
+    pub error: Option<String>,
+}
+
+/// `calculate_grid` 的结果
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct GridOutput {
+    /// 表格的范围，例如 A1:D20
+    pub bounds: Option<String>,
+    pub results: Vec<GridResult>,
+    /// 全部计算成功且满足断言
+    pub all_passed: bool,
+    pub settings: Settings,
+}
+
+/// 缓存的值与重新计算的结果不符的单元格
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct CellDifference {
+    pub sheet: String,
+    /// 单元格地址，例如 B5
+    pub cell: String,
+    pub formula: String,
+    /// 文件中缓存的值
+    pub cached: f64,
+    /// 按规则重新计算的结果
+    pub recomputed: f64,
+    /// 重新计算的结果减缓存的值按相同规则舍入后的值；百分比格式的单元格以百分点为单位
+    pub difference: f64,
+    /// 容差允许的最大差异
+    pub allowed: f64,
+}
+
+/// 不受支持或无法重新计算的公式
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct FormulaError {
+    pub sheet: String,
+    pub cell: String,
+    pub formula: String,
+    pub error: String,
+}
+
+/// `recalculate_xlsx` 的结果
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct RecalculateOutput {
+    /// 重新计算的工作表
+    pub sheets: Vec<String>,
+    /// 公式单元格的个数
+    pub formulas: u32,
+    /// 与缓存的值相符的个数
+    pub matched: u32,
+    /// 重新计算成功但没有缓存数值的个数
+    pub uncached: u32,
+    pub differences: Vec<CellDifference>,
+    pub unsupported: Vec<FormulaError>,
+    /// 没有不符的单元格，也没有不受支持的公式
+    pub all_matched: bool,
+    pub settings: Settings,
+}
+
+/// 核对报告的文本：汇总、小数位数和容差，空一行后是各项的结果
+pub fn report_text(summary: String, options: &CalcOptions, lines: Vec<String>) -> String {
+    let mut text = vec![summary, format!("小数位数: {}，容差: {}", options.decimals, options.tolerance), String::new()];
+    text.extend(lines);
+    text.join("\n")
+}
+
+/// 列示参与求和的各项，例如 `货币资金 1000 + 应收账款 1999 - 累计折旧 200`；第一项为正数时不带符号
+pub fn signed_terms(terms: &[(String, f64)]) -> String {
+    let terms: Vec<String> = terms.iter().enumerate().map(|(index, (label, amount))| {
+        let sign = if *amount < 0.0 { "- " } else if index == 0 { "" } else { "+ " };
+        format!("{}{} {}", sign, label, amount.abs())
+    }).collect();
+    terms.join(" ")
+}
+
+/// 同时返回文本和结构化结果
+pub fn tool_result<T: Serialize>(text: String, output: &T) -> Result<CallToolResult, CallToolError> {
+    let structured = match serde_json::to_value(output).map_err(|e| CallToolError::new(crate::error::ServiceError::from(e)))? {
+        serde_json::Value::Object(map) => map,
+        _ => unreachable!("输出结构总是序列化为对象"),
+    };
+    Ok(CallToolResult::text_content(vec![TextContent::from(text)]).with_structured_content(structured))
+}
+
+/// 工具声明的输出模式，未知的工具返回 `None`
+pub fn output_schema(tool_name: &str) -> Option<ToolOutputSchema> {
+    let schema = match tool_name {
+        "calculate" => CalculateOutput::json_schema(),
+        "validate" => ValidateOutput::json_schema(),
+        "batch_validate" | "validate_csv" => BatchOutput::json_schema(),
+        "parse" => ParseOutput::json_schema(),
+        "calculate_script" => ScriptOutput::json_schema(),
+        "find_rounding_convention" => ConventionOutput::json_schema(),
+        "validate_statement" => StatementOutput::json_schema(),
+        "crossfoot_table" => CrossfootOutput::json_schema(),
+        "calculate_grid" => GridOutput::json_schema(),
+        "recalculate_xlsx" => RecalculateOutput::json_schema(),
+        _ => return None,
+    };
+    let properties = match schema.get("properties") {
+        Some(serde_json::Value::Object(properties)) => properties
+            .iter()
+            .filter_map(|(name, property)| Some((name.clone(), property.as_object()?.clone())))
+            .collect(),
+        _ => Default::default(),
+    };
+    let required = match schema.get("required") {
+        Some(serde_json::Value::Array(required)) => required.iter().filter_map(|name| Some(name.as_str()?.to_string())).collect(),
+        _ => Vec::new(),
+    };
+    Some(ToolOutputSchema::new(required, Some(properties)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::calculator::PercentRounding;
+
+    #[test]
+    fn test_settings() {
+        let options = CalcOptions::new(4, PercentRounding::DivideBy100ThenRound).with_tolerance(Tolerance::Absolute(0.01));
+        assert_eq!(
+            Settings::new(&options),
+            Settings {
+                decimals: 4,
+                percent_rounding: "divide_by_100_then_round".to_string(),
+                number_format: "auto".to_string(),
+                tolerance: Some("±0.01".to_string()),
+            }
+        );
+        assert_eq!(Settings::new(&options.with_tolerance(Tolerance::Exact)).tolerance, None);
+    }
+
+    #[test]
+    fn test_signed_terms() {
+        let terms = |terms: &[(&str, f64)]| signed_terms(&terms.iter().map(|(label, amount)| (label.to_string(), *amount)).collect::<Vec<_>>());
+        assert_eq!(terms(&[("货币资金", 1000.0), ("应收账款", 1999.0), ("累计折旧", -200.0)]), "货币资金 1000 + 应收账款 1999 - 累计折旧 200");
+        assert_eq!(terms(&[("2024", -100.0), ("2023", 0.5)]), "- 2024 100 + 2023 0.5");
+        assert_eq!(terms(&[]), "");
+    }
+
+    #[test]
+    fn test_output_schemas() {
+        for tool in ["calculate", "validate", "batch_validate", "parse", "calculate_script", "find_rounding_convention", "validate_statement", "crossfoot_table", "validate_csv", "calculate_grid", "recalculate_xlsx"] {
+            let schema = output_schema(tool).unwrap();
+            assert!(schema.properties.is_some_and(|properties| !properties.is_empty()), "{}", tool);
+        }
+        let batch = output_schema("batch_validate").unwrap();
+        assert!(batch.required.contains(&"results".to_string()));
+        let results = &batch.properties.unwrap()["results"];
+        assert_eq!(results["items"]["properties"]["passed"]["type"], "boolean");
+        assert!(output_schema("unknown").is_none());
+    }
+
+    #[test]
+    fn test_materiality_summary() {
+        let mut summary = MaterialitySummary::default();
+        summary.total(Severity::Trivial).add(-0.01);
+        summary.total(Severity::Material).add(0.1);
+        summary.total(Severity::Material).add(-0.2);
+        assert_eq!(summary.trivial, SeverityTotal { count: 1, total: 0.01 });
+        assert_eq!(summary.material, SeverityTotal { count: 2, total: 0.3 });
+        assert_eq!(summary.below_threshold.count, 0);
+    }
+
+    #[test]
+    fn test_tool_result() {
+        let output = CalculateOutput {
+            expression: "1 + 2".to_string(),
+            value: 3.0,
+            formatted: "3.00".to_string(),
+            settings: Settings::new(&CalcOptions::new(2, PercentRounding::RoundThenDivideBy100)),
+            steps: None,
+        };
+        let result = tool_result("结果: 3".to_string(), &output).unwrap();
+        let structured = result.structured_content.unwrap();
+        assert_eq!(structured["value"], 3.0);
+        assert_eq!(structured["settings"]["decimals"], 2);
+        assert_eq!(result.content.len(), 1);
+    }
+}
@@ -0,0 +1,130 @@
+//! # 单行设置
+//!
+//! 一张报表往往同时包含保留 2 位小数的金额和保留 4 位小数的比率。`batch_validate` 的每一行
+//! 可以附加一个 `key=value` 字段覆盖工具级的设置，多个设置用 `;` 分隔：
+//!
+//! ```text
+//! 1,234.5678 * 12.3456%|152.4691|利息|decimals=4;percent=divide_by_100_then_round;tol=0.01
+//! ```
+//!
+//! 设置字段与标记的先后顺序不限；不含任何已知键的字段视为标记。
+
+use crate::tools::calculator::PercentRounding;
+use crate::tools::precision::{Decimals, MAX_DECIMALS};
+use crate::tools::tolerance::Tolerance;
+
+/// 支持的键，括号内为别名
+pub const KEYS: &str = "decimals（dp）、percent（percent_rounding）、tol（tolerance）";
+
+/// 单行的设置覆盖，未指定的设置沿用工具参数
+#[derive(Debug, PartialEq, Copy, Clone, Default)]
+pub struct LineOverrides {
+    pub decimals: Option<Decimals>,
+    pub percent_rounding: Option<PercentRounding>,
+    pub tolerance: Option<Tolerance>,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+enum Key {
+    Decimals,
+    Percent,
+    Tolerance,
+}
+
+impl Key {
+    fn parse(key: &str) -> Option<Self> {
+        match key.trim().to_ascii_lowercase().as_str() {
+            "decimals" | "dp" => Some(Key::Decimals),
+            "percent" | "percent_rounding" => Some(Key::Percent),
+            "tol" | "tolerance" => Some(Key::Tolerance),
+            _ => None,
+        }
+    }
+}
+
+impl LineOverrides {
+    /// 解析设置字段；字段中没有任何已知的键时返回 `Ok(None)`，由调用方当作标记
+    pub fn parse(field: &str) -> Result<Option<Self>, String> {
+        let settings: Vec<&str> = field.split(';').map(str::trim).filter(|s| !s.is_empty()).collect();
+        let is_settings = settings
+            .iter()
+            .any(|setting| setting.split_once('=').is_some_and(|(key, _)| Key::parse(key).is_some()));
+        if !is_settings {
+            return Ok(None);
+        }
+
+        let mut overrides = LineOverrides::default();
+        for setting in settings {
+            let (key, value) = setting.split_once('=').ok_or_else(|| format!("无效的设置 '{}'，应为 key=value", setting))?;
+            overrides.set(key, value)?;
+        }
+        Ok(Some(overrides))
+    }
+
+    /// 按键设置一项，键和值的写法有误时返回错误信息
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let value = value.trim();
+        match Key::parse(key).ok_or_else(|| format!("未知的设置 '{}'，支持的键：{}", key.trim(), KEYS))? {
+            Key::Decimals => {
+                let decimals = Decimals::parse(value).ok_or_else(|| format!("无效的小数位数 '{}'，支持 0-{} 的整数或 auto", value, MAX_DECIMALS))?;
+                self.decimals = Some(decimals);
+            }
+            Key::Percent => {
+                let strategy = PercentRounding::parse(value)
+                    .ok_or_else(|| format!("无效的百分数处理策略 '{}'，支持的策略：{}", value, PercentRounding::NAMES))?;
+                self.percent_rounding = Some(strategy);
+            }
+            Key::Tolerance => {
+                let tolerance = Tolerance::parse(value).ok_or_else(|| format!("无效的容差 '{}'，支持的写法：{}", value, Tolerance::SYNTAX))?;
+                self.tolerance = Some(tolerance);
+            }
+        }
+        Ok(())
+    }
+
+    /// 合并另一个字段中的设置，后出现的设置优先
+    pub fn merge(self, other: LineOverrides) -> Self {
+        LineOverrides {
+            decimals: other.decimals.or(self.decimals),
+            percent_rounding: other.percent_rounding.or(self.percent_rounding),
+            tolerance: other.tolerance.or(self.tolerance),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_settings() {
+        assert_eq!(
+            LineOverrides::parse("decimals=4;percent=divide_by_100_then_round;tol=0.01"),
+            Ok(Some(LineOverrides {
+                decimals: Some(Decimals::Fixed(4)),
+                percent_rounding: Some(PercentRounding::DivideBy100ThenRound),
+                tolerance: Some(Tolerance::Absolute(0.01)),
+            }))
+        );
+        assert_eq!(
+            LineOverrides::parse(" DP = auto ; "),
+            Ok(Some(LineOverrides { decimals: Some(Decimals::Auto), ..Default::default() }))
+        );
+    }
+
+    #[test]
+    fn test_labels_are_not_settings() {
+        assert_eq!(LineOverrides::parse("流动资产合计"), Ok(None));
+        assert_eq!(LineOverrides::parse("a=b"), Ok(None));
+        assert_eq!(LineOverrides::parse("2"), Ok(None));
+    }
+
+    #[test]
+    fn test_invalid_settings() {
+        assert!(LineOverrides::parse("decimals=-1").is_err());
+        assert_eq!(LineOverrides::parse("decimals=16"), Err("无效的小数位数 '16'，支持 0-15 的整数或 auto".to_string()));
+        assert!(LineOverrides::parse("tol=abc").is_err());
+        assert!(LineOverrides::parse("decimals=2;rounding=up").is_err());
+        assert!(LineOverrides::parse("decimals=2;oops").is_err());
+    }
+}
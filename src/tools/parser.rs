@@ -0,0 +1,669 @@
+//! # 语法分析
+//!
+//! 把表达式字符串解析为语法树（`Expr`），分为两步：
+//! 1. **词法分析 (Tokenization)**: 把字符序列切分为带位置的词元。数字字面量在此阶段按 `NumberFormat`
+//!    标准化（严格分组模式下同时校验分组），但不进行任何舍入。
+//! 2. **语法分析 (Parsing)**: 递归下降解析，语法如下：
+//!
+//! ```text
+//! expr    := term (('+' | '-') term)*
+//! term    := unary (('*' | '/') unary)*
+//! unary   := '-' unary | primary
+//! primary := NUMBER '%'? LABEL? | call | IDENT | '(' expr ')'
+//! call    := IDENT '(' arg ((',' | ';') arg)* ')'
+//! arg     := IDENT ':' IDENT | expr
+//! ```
+//!
+//! `LABEL` 是紧跟在数字后面的操作数标记，写作 `[货币资金]` 或 `#cash`，只用于标识数字的来源，不参与计算。
+//! `IDENT` 是以字母（含汉字）或 `_` 开头的变量名，单元格地址（`B2`、`$B$2`）也是变量名。
+//! 函数的参数可以是区域 `B2:B10`。参数之间的 `,` 紧跟在数字后面时可能被读作千分位分隔符，因此函数调用中
+//! 数字后面的 `, `（逗号加空白）总是分隔参数；也可以用 `;` 分隔参数。
+//!
+//! 脚本（`parse_script`）由分号或换行分隔的语句组成，括号内的换行不分隔语句：
+//!
+//! ```text
+//! script    := statement? ((';' | '\n') statement?)*
+//! statement := IDENT '=' expr | expr
+//! ```
+
+use crate::tools::ast::{BinaryOp, Expr, Function, NumberLiteral, Statement, UnaryOp};
+use crate::tools::cell::{CellRange, CellRef};
+use crate::tools::calculator::{normalize_strict, CalcError, CalcOptions};
+use crate::tools::diagnostic::{Expected, Span};
+use crate::tools::number_format::NumberFormat;
+
+/// 定义词元（Token）类型
+#[derive(Debug, PartialEq, Clone)]
+enum TokenKind {
+    Number(NumberLiteral),
+    Percent,
+    Label(String),
+    Ident(String),
+    Assign,
+    /// 语句分隔符，只在脚本中出现
+    Separator,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    LeftParen,
+    RightParen,
+    /// 区域中的 `:`，只在函数调用中出现
+    Colon,
+    /// 参数分隔符 `,` 或 `;`，只在函数调用中出现
+    Comma,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+struct Token {
+    kind: TokenKind,
+    span: Span,
+}
+
+/// 把表达式解析为语法树
+///
+/// 只用到 `options` 中的数字格式和严格分组设置，舍入在求值时进行。
+pub fn parse(expr: &str, options: &CalcOptions) -> Result<Expr, CalcError> {
+    let chars: Vec<char> = expr.chars().collect();
+    let tokens = tokenize(&chars, options, false)?;
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        len: chars.len(),
+        depth: 0,
+        height: 0,
+    };
+
+    let ast = parser.parse_expr()?;
+    parser.expect_end()?;
+    Ok(ast)
+}
+
+/// 把脚本解析为语句列表，空语句（空行、多余的分号）被忽略
+pub fn parse_script(script: &str, options: &CalcOptions) -> Result<Vec<Statement>, CalcError> {
+    let chars: Vec<char> = script.chars().collect();
+    let tokens = tokenize(&chars, options, true)?;
+
+    let mut statements = Vec::new();
+    for chunk in tokens.split_inclusive(|t| t.kind == TokenKind::Separator) {
+        let (tokens, len) = match chunk.split_last() {
+            Some((Token { kind: TokenKind::Separator, span }, rest)) => (rest, span.start),
+            _ => (chunk, chars.len()),
+        };
+        if tokens.is_empty() {
+            continue;
+        }
+        let mut parser = Parser {
+            tokens: tokens.to_vec(),
+            pos: 0,
+            len,
+            depth: 0,
+            height: 0,
+        };
+        statements.push(parser.parse_statement()?);
+    }
+
+    if statements.is_empty() {
+        return Err(CalcError::UnexpectedEndOfExpression { pos: chars.len(), expected: Expected::Operand });
+    }
+    Ok(statements)
+}
+
+// --- 词法分析 ---
+
+/// 切分词元；`script` 为真时识别 `=` 和语句分隔符
+fn tokenize(chars: &[char], options: &CalcOptions, script: bool) -> Result<Vec<Token>, CalcError> {
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    // 括号深度，括号内的换行只是空白
+    let mut depth = 0usize;
+    // 每层括号是否为函数调用的参数列表
+    let mut calls: Vec<bool> = Vec::new();
+
+    while pos < chars.len() {
+        let c = chars[pos];
+        let in_call = calls.last() == Some(&true);
+        let kind = match c {
+            '0'..='9' => {
+                let mut end = scan_number(chars, pos, options.number_format, options.strict_grouping);
+                if in_call {
+                    // 逗号加空白分隔参数，不是千分位分隔符
+                    // 空白不是分隔符时数字停在逗号之后，因此也要看数字之后的一个字符
+                    let scanned = &chars[pos..(end + 1).min(chars.len())];
+                    if let Some(offset) = scanned.windows(2).position(|pair| pair[0] == ',' && pair[1].is_whitespace()) {
+                        end = pos + offset;
+                    }
+                }
+                let literal = read_literal(chars, pos, end, options)?;
+                let span = literal.span;
+                tokens.push(Token { kind: TokenKind::Number(literal), span });
+                pos = end;
+                continue;
+            }
+            '[' => {
+                // 方括号标记，直到对应的 `]`
+                let Some(offset) = chars[pos + 1..].iter().position(|&c| c == ']') else {
+                    return Err(CalcError::UnexpectedEndOfExpression { pos: chars.len(), expected: Expected::LabelEnd });
+                };
+                let end = pos + 1 + offset;
+                let label: String = chars[pos + 1..end].iter().collect();
+                tokens.push(Token { kind: TokenKind::Label(label.trim().to_string()), span: Span::new(pos, end + 1) });
+                pos = end + 1;
+                continue;
+            }
+            '{' => {
+                // 标记引用 `{流动资产合计}`，按变量处理，名称可以包含空格和任意字符
+                let Some(offset) = chars[pos + 1..].iter().position(|&c| c == '}') else {
+                    return Err(CalcError::UnexpectedEndOfExpression { pos: chars.len(), expected: Expected::ReferenceEnd });
+                };
+                let end = pos + 1 + offset;
+                let name: String = chars[pos + 1..end].iter().collect::<String>().trim().to_string();
+                if name.is_empty() {
+                    return Err(CalcError::InvalidCharacter { pos: end, ch: '}' });
+                }
+                tokens.push(Token { kind: TokenKind::Ident(name), span: Span::new(pos, end + 1) });
+                pos = end + 1;
+                continue;
+            }
+            '#' => {
+                // 井号标记，直到空白或运算符；函数的参数列表中逗号分隔参数，也结束标记
+                let end = chars[pos + 1..]
+                    .iter()
+                    .position(|&c| c.is_whitespace() || "+-*/()%[]#;=".contains(c) || (in_call && c == ','))
+                    .map_or(chars.len(), |offset| pos + 1 + offset);
+                if end == pos + 1 {
+                    return Err(CalcError::InvalidCharacter { pos, ch: c });
+                }
+                let label: String = chars[pos + 1..end].iter().collect();
+                tokens.push(Token { kind: TokenKind::Label(label), span: Span::new(pos, end) });
+                pos = end;
+                continue;
+            }
+            c if c.is_alphabetic() || c == '_' || (c == '$' && chars.get(pos + 1).is_some_and(|c| c.is_alphanumeric())) => {
+                // `$` 出现在 `$B$2` 这样的绝对引用中
+                let end = chars[pos..]
+                    .iter()
+                    .position(|&c| !(c.is_alphanumeric() || c == '_' || c == '$'))
+                    .map_or(chars.len(), |offset| pos + offset);
+                let name: String = chars[pos..end].iter().collect();
+                tokens.push(Token { kind: TokenKind::Ident(name), span: Span::new(pos, end) });
+                pos = end;
+                continue;
+            }
+            ',' | ';' if in_call => TokenKind::Comma,
+            ':' if in_call => TokenKind::Colon,
+            '=' if script => TokenKind::Assign,
+            ';' if script => TokenKind::Separator,
+            '\n' if script && depth == 0 => TokenKind::Separator,
+            '%' => TokenKind::Percent,
+            '+' => TokenKind::Plus,
+            '-' => TokenKind::Minus,
+            '*' => TokenKind::Star,
+            '/' => TokenKind::Slash,
+            '(' => {
+                depth += 1;
+                calls.push(matches!(tokens.last(), Some(Token { kind: TokenKind::Ident(_), .. })));
+                TokenKind::LeftParen
+            }
+            ')' => {
+                depth = depth.saturating_sub(1);
+                calls.pop();
+                TokenKind::RightParen
+            }
+            ' ' | '\t' | '\n' | '\r' => {
+                // Skip whitespace
+                pos += 1;
+                continue;
+            }
+            _ => return Err(CalcError::InvalidCharacter { pos, ch: c }),
+        };
+        tokens.push(Token { kind, span: Span::at(pos) });
+        pos += 1;
+    }
+
+    Ok(tokens)
+}
+
+/// 从 `start` 开始扫描一个原始数字（含千分位分隔符），返回数字结束的位置
+///
+/// 哪些字符可以作为分隔符由 `number_format` 决定；严格分组模式下，`auto` 格式不再把空格视为分隔符，
+/// 这样 `100 200` 会被识别为缺少运算符的两个数字。
+pub fn scan_number(chars: &[char], start: usize, number_format: NumberFormat, strict_grouping: bool) -> usize {
+    let mut end = start;
+    let mut has_digit = false;
+
+    while let Some(&c) = chars.get(end) {
+        if c.is_ascii_digit() {
+            has_digit = true;
+        } else if !(number_format.is_separator(c)
+            && has_digit
+            && !(strict_grouping && number_format == NumberFormat::Auto && c == ' '))
+        {
+            break;
+        }
+        // 只有在已经有数字的情况下才消费分隔符
+        end += 1;
+    }
+
+    end
+}
+
+/// 把 `chars[start..end]` 解析为数字字面量（不含百分号）
+fn read_literal(chars: &[char], start: usize, end: usize, options: &CalcOptions) -> Result<NumberLiteral, CalcError> {
+    let raw: String = chars[start..end].iter().collect();
+    // 末尾的空白属于数字与下一个词元之间的间隔
+    let text = raw.trim_end().to_string();
+    let span = Span::new(start, start + text.chars().count());
+
+    let normalized = if options.strict_grouping {
+        normalize_strict(&raw, start, options.number_format)?
+    } else {
+        options.number_format.normalize(&raw)
+    };
+    let value = normalized.parse::<f64>().map_err(|_| CalcError::InvalidNumber { span })?;
+    let format = match options.number_format {
+        NumberFormat::Auto => NumberFormat::detect(&text),
+        format => format,
+    };
+
+    Ok(NumberLiteral {
+        text,
+        value,
+        percent: false,
+        label: None,
+        format,
+        span,
+    })
+}
+
+// --- 语法分析 ---
+
+/// 括号、取负和函数调用最多嵌套的层数，解析是递归的，每层都占用调用栈
+pub const MAX_NESTING: usize = 100;
+
+/// 语法树的最大深度：嵌套和连续的运算（`1 + 2 + 3` 为两层）各算一层
+///
+/// 求值、输出和遍历语法树都是递归的，限制深度使它们不会耗尽调用栈。
+pub const MAX_DEPTH: usize = 500;
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// 表达式的字符数，用于报告表达式意外结束的位置
+    len: usize,
+    /// 正在解析的括号、取负和函数调用的层数
+    depth: usize,
+    /// 最近解析完的子表达式的深度
+    height: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    /// 进入一层括号、取负或函数调用，`pos` 是报告嵌套过深时的位置
+    fn enter(&mut self, pos: usize) -> Result<(), CalcError> {
+        self.depth += 1;
+        if self.depth > MAX_NESTING {
+            return Err(CalcError::TooDeep { pos });
+        }
+        Ok(())
+    }
+
+    /// 记录刚解析完的子表达式的深度
+    fn set_height(&mut self, height: usize, pos: usize) -> Result<(), CalcError> {
+        if height > MAX_DEPTH {
+            return Err(CalcError::TooLong { pos });
+        }
+        self.height = height;
+        Ok(())
+    }
+
+    /// 确认所有词元都已消费
+    fn expect_end(&self) -> Result<(), CalcError> {
+        match self.peek() {
+            None => Ok(()),
+            Some(Token { kind: TokenKind::RightParen, span }) => Err(CalcError::MismatchedParens { pos: span.start, ch: ')' }),
+            Some(token) => Err(CalcError::InvalidExpression { span: token.span, expected: Expected::Operator }),
+        }
+    }
+
+    /// statement := IDENT '=' expr | expr
+    fn parse_statement(&mut self) -> Result<Statement, CalcError> {
+        if let [Token { kind: TokenKind::Ident(name), span: name_span }, Token { kind: TokenKind::Assign, .. }, ..] = &self.tokens[self.pos..] {
+            let (name, start) = (name.clone(), name_span.start);
+            self.pos += 2;
+            let expr = self.parse_expr()?;
+            self.expect_end()?;
+            let span = Span::new(start, expr.span().end);
+            return Ok(Statement::Assign { name, expr, span });
+        }
+        let expr = self.parse_expr()?;
+        self.expect_end()?;
+        Ok(Statement::Expr { expr })
+    }
+
+    /// expr := term (('+' | '-') term)*
+    fn parse_expr(&mut self) -> Result<Expr, CalcError> {
+        let mut lhs = self.parse_term()?;
+        let mut height = self.height;
+        while let Some((op, pos)) = self.peek().and_then(|t| match t.kind {
+            TokenKind::Plus => Some((BinaryOp::Add, t.span.start)),
+            TokenKind::Minus => Some((BinaryOp::Subtract, t.span.start)),
+            _ => None,
+        }) {
+            self.next();
+            let rhs = self.parse_term()?;
+            height = height.max(self.height) + 1;
+            self.set_height(height, pos)?;
+            lhs = binary(op, lhs, rhs);
+        }
+        self.height = height;
+        Ok(lhs)
+    }
+
+    /// term := unary (('*' | '/') unary)*
+    fn parse_term(&mut self) -> Result<Expr, CalcError> {
+        let mut lhs = self.parse_unary()?;
+        let mut height = self.height;
+        while let Some((op, pos)) = self.peek().and_then(|t| match t.kind {
+            TokenKind::Star => Some((BinaryOp::Multiply, t.span.start)),
+            TokenKind::Slash => Some((BinaryOp::Divide, t.span.start)),
+            _ => None,
+        }) {
+            self.next();
+            let rhs = self.parse_unary()?;
+            height = height.max(self.height) + 1;
+            self.set_height(height, pos)?;
+            lhs = binary(op, lhs, rhs);
+        }
+        self.height = height;
+        Ok(lhs)
+    }
+
+    /// unary := '-' unary | primary
+    fn parse_unary(&mut self) -> Result<Expr, CalcError> {
+        if let Some(Token { kind: TokenKind::Minus, span }) = self.peek() {
+            let start = span.start;
+            self.next();
+            self.enter(start)?;
+            let operand = self.parse_unary()?;
+            self.depth -= 1;
+            self.set_height(self.height + 1, start)?;
+            let span = Span::new(start, operand.span().end);
+            return Ok(Expr::Unary {
+                op: UnaryOp::Negate,
+                operand: Box::new(operand),
+                span,
+            });
+        }
+        self.parse_primary()
+    }
+
+    /// primary := NUMBER '%'? LABEL? | call | IDENT | '(' expr ')'
+    fn parse_primary(&mut self) -> Result<Expr, CalcError> {
+        let Some(token) = self.next() else {
+            return Err(CalcError::UnexpectedEndOfExpression { pos: self.len, expected: Expected::Operand });
+        };
+
+        match token.kind {
+            TokenKind::Number(mut literal) => {
+                if let Some(Token { kind: TokenKind::Percent, span }) = self.peek() {
+                    literal.percent = true;
+                    literal.span.end = span.end;
+                    self.next();
+                }
+                if let Some(Token { kind: TokenKind::Label(label), .. }) = self.peek() {
+                    literal.label = Some(label.clone()).filter(|label| !label.is_empty());
+                    self.next();
+                }
+                self.height = 0;
+                Ok(Expr::Number(literal))
+            }
+            TokenKind::Ident(name) if matches!(self.peek(), Some(Token { kind: TokenKind::LeftParen, .. })) => self.parse_call(name, token.span),
+            TokenKind::Ident(name) => {
+                self.height = 0;
+                Ok(Expr::Variable { name, span: token.span })
+            }
+            TokenKind::LeftParen => {
+                self.enter(token.span.start)?;
+                let inner = self.parse_expr()?;
+                self.depth -= 1;
+                self.set_height(self.height + 1, token.span.start)?;
+                match self.next() {
+                    Some(Token { kind: TokenKind::RightParen, span }) => Ok(Expr::Group {
+                        inner: Box::new(inner),
+                        span: token.span.to(span),
+                    }),
+                    Some(other) => Err(CalcError::InvalidExpression { span: other.span, expected: Expected::OperatorOrRightParen }),
+                    None => Err(CalcError::MismatchedParens { pos: token.span.start, ch: '(' }),
+                }
+            }
+            _ => Err(CalcError::InvalidExpression { span: token.span, expected: Expected::Operand }),
+        }
+    }
+}
+
+impl Parser {
+    /// call := IDENT '(' arg ((',' | ';') arg)* ')'
+    fn parse_call(&mut self, name: String, name_span: Span) -> Result<Expr, CalcError> {
+        let function = Function::parse(&name).ok_or(CalcError::UnknownFunction { name, span: name_span })?;
+        let open = self.next().map_or(name_span, |token| token.span);
+        self.enter(open.start)?;
+        let mut args = Vec::new();
+        let mut height = 0;
+        loop {
+            args.push(self.parse_argument()?);
+            height = height.max(self.height + 1);
+            match self.next() {
+                Some(Token { kind: TokenKind::Comma, .. }) => {}
+                Some(Token { kind: TokenKind::RightParen, span }) => {
+                    self.depth -= 1;
+                    self.set_height(height, open.start)?;
+                    let span = name_span.to(span);
+                    return match function.arity() {
+                        Some(arity) if arity != args.len() => Err(CalcError::ArgumentCount { function, found: args.len(), span }),
+                        _ => Ok(Expr::Call { function, args, span }),
+                    };
+                }
+                Some(other) => return Err(CalcError::InvalidExpression { span: other.span, expected: Expected::ArgumentSeparator }),
+                None => return Err(CalcError::MismatchedParens { pos: open.start, ch: '(' }),
+            }
+        }
+    }
+
+    /// arg := IDENT ':' IDENT | expr
+    fn parse_argument(&mut self) -> Result<Expr, CalcError> {
+        let [Token { kind: TokenKind::Ident(from), span: from_span }, Token { kind: TokenKind::Colon, span: colon }, rest @ ..] = &self.tokens[self.pos..] else {
+            return self.parse_expr();
+        };
+        let cell = |name: &str, span: Span| CellRef::parse(name).ok_or(CalcError::InvalidExpression { span, expected: Expected::Cell });
+        let start = cell(from, *from_span)?;
+        let (end, to_span) = match rest.first() {
+            Some(Token { kind: TokenKind::Ident(to), span }) => (cell(to, *span)?, *span),
+            Some(other) => return Err(CalcError::InvalidExpression { span: other.span, expected: Expected::Cell }),
+            None => return Err(CalcError::UnexpectedEndOfExpression { pos: colon.end, expected: Expected::Cell }),
+        };
+        let span = from_span.to(to_span);
+        self.pos += 3;
+        self.height = 0;
+        Ok(Expr::Range { range: CellRange::new(start, end), span })
+    }
+}
+
+fn binary(op: BinaryOp, lhs: Expr, rhs: Expr) -> Expr {
+    let span = lhs.span().to(rhs.span());
+    Expr::Binary {
+        op,
+        lhs: Box::new(lhs),
+        rhs: Box::new(rhs),
+        span,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::calculator::PercentRounding;
+
+    fn options() -> CalcOptions {
+        CalcOptions::new(2, PercentRounding::DivideBy100ThenRound)
+    }
+
+    #[test]
+    fn test_literals_keep_text_format_and_span() {
+        let ast = parse("1,234.56 + 1.000,44%", &options()).unwrap();
+        let Expr::Binary { op: BinaryOp::Add, lhs, rhs, span } = ast else {
+            panic!("expected addition");
+        };
+        assert_eq!(span, Span::new(0, 20));
+
+        let Expr::Number(lhs) = *lhs else { panic!("expected literal") };
+        assert_eq!(lhs.text, "1,234.56");
+        assert_eq!(lhs.value, 1234.56);
+        assert_eq!(lhs.format, NumberFormat::EnUs);
+        assert_eq!(lhs.span, Span::new(0, 8));
+
+        let Expr::Number(rhs) = *rhs else { panic!("expected literal") };
+        assert_eq!(rhs.text, "1.000,44");
+        assert!(rhs.percent);
+        assert_eq!(rhs.format, NumberFormat::DeDe);
+        assert_eq!(rhs.span, Span::new(11, 20));
+    }
+
+    #[test]
+    fn test_precedence_and_unary() {
+        let ast = parse("-(1 + 2) * 3 - 4 / -2", &options()).unwrap();
+        let Expr::Binary { op: BinaryOp::Subtract, lhs, rhs, .. } = ast else {
+            panic!("expected subtraction");
+        };
+        assert!(matches!(*lhs, Expr::Binary { op: BinaryOp::Multiply, .. }));
+        assert!(matches!(*rhs, Expr::Binary { op: BinaryOp::Divide, .. }));
+    }
+
+    #[test]
+    fn test_canonical_form() {
+        let canonical = |expr: &str| parse(expr, &options()).unwrap().to_string();
+
+        assert_eq!(canonical("1,234.56+(2 * 3)"), "1234.56 + 2 * 3");
+        assert_eq!(canonical("((1 + 2)) * 3"), "(1 + 2) * 3");
+        assert_eq!(canonical("1 - (2 - 3)"), "1 - (2 - 3)");
+        assert_eq!(canonical("(1 - 2) - 3"), "1 - 2 - 3");
+        assert_eq!(canonical("8 / (4 / 2)"), "8 / (4 / 2)");
+        assert_eq!(canonical("-(50.5%) * -(1 + 1)"), "-50.5% * -(1 + 1)");
+    }
+
+    #[test]
+    fn test_ast_serializes_to_json() {
+        let ast = parse("50% - 1", &options()).unwrap();
+        let json = serde_json::to_value(&ast).unwrap();
+
+        assert_eq!(json["type"], "binary");
+        assert_eq!(json["op"], "subtract");
+        assert_eq!(json["lhs"]["type"], "number");
+        assert_eq!(json["lhs"]["text"], "50");
+        assert_eq!(json["lhs"]["percent"], true);
+        assert_eq!(json["lhs"]["format"], "auto");
+        assert_eq!(json["lhs"]["span"]["end"], 3);
+    }
+
+    #[test]
+    fn test_operand_labels() {
+        let ast = parse("1,000.00[货币资金] + 2,000.00 [应收账款] - 500 #cash", &options()).unwrap();
+        let labels: Vec<Option<&str>> = ast.literals().iter().map(|l| l.label.as_deref()).collect();
+        assert_eq!(labels, vec![Some("货币资金"), Some("应收账款"), Some("cash")]);
+        assert_eq!(ast.literals()[0].span, Span::new(0, 8));
+        assert_eq!(ast.to_string(), "1000[货币资金] + 2000[应收账款] - 500[cash]");
+
+        // 百分号在标记之前
+        let ast = parse("12.5%[毛利率]", &options()).unwrap();
+        assert!(ast.literals()[0].percent);
+        assert_eq!(ast.literals()[0].label.as_deref(), Some("毛利率"));
+    }
+
+    #[test]
+    fn test_invalid_labels() {
+        assert_eq!(parse("1,000[货币资金 + 2", &options()), Err(CalcError::UnexpectedEndOfExpression { pos: 14, expected: Expected::LabelEnd }));
+        assert_eq!(parse("1 # + 2", &options()), Err(CalcError::InvalidCharacter { pos: 2, ch: '#' }));
+        // 标记只能跟在数字之后
+        assert!(parse("[现金] 1", &options()).is_err());
+    }
+
+    #[test]
+    fn test_variables_and_statements() {
+        let ast = parse("gross - 税额 * 2", &options()).unwrap();
+        assert_eq!(ast.to_string(), "gross - 税额 * 2");
+        assert!(matches!(ast, Expr::Binary { lhs, .. } if *lhs == Expr::Variable { name: "gross".to_string(), span: Span::new(0, 5) }));
+
+        // 单个表达式中 `=` 和 `;` 仍然是无效字符
+        assert_eq!(parse("a = 1", &options()), Err(CalcError::InvalidCharacter { pos: 2, ch: '=' }));
+
+        let statements = parse_script("a = 1,5 #x; a * 2", &options()).unwrap();
+        let canonical: Vec<String> = statements.iter().map(|s| s.to_string()).collect();
+        assert_eq!(canonical, vec!["a = 1.5[x]", "a * 2"]);
+        assert_eq!(parse_script("a = = 1", &options()), Err(CalcError::InvalidExpression { span: Span::at(4), expected: Expected::Operand }));
+    }
+
+    #[test]
+    fn test_references() {
+        let ast = parse("{流动资产合计} + { 非流动 资产 }", &options()).unwrap();
+        assert_eq!(ast.to_string(), "流动资产合计 + {非流动 资产}");
+        assert!(matches!(ast, Expr::Binary { lhs, .. } if *lhs == Expr::Variable { name: "流动资产合计".to_string(), span: Span::new(0, 8) }));
+        assert_eq!(parse("{a + 1", &options()), Err(CalcError::UnexpectedEndOfExpression { pos: 6, expected: Expected::ReferenceEnd }));
+        assert_eq!(parse("1 + {}", &options()), Err(CalcError::InvalidCharacter { pos: 5, ch: '}' }));
+    }
+
+    #[test]
+    fn test_calls_and_ranges() {
+        let ast = parse("sum(B2:$B$10, 1,000.5; C3) - B11", &options()).unwrap();
+        assert_eq!(ast.to_string(), "SUM(B2:B10, 1000.5, C3) - B11");
+        let Expr::Binary { lhs, .. } = &ast else { panic!() };
+        let Expr::Call { function: Function::Sum, args, span } = lhs.as_ref() else { panic!() };
+        assert_eq!(*span, Span::new(0, 26));
+        assert!(matches!(&args[0], Expr::Range { range, .. } if range.to_string() == "B2:B10"));
+        // 参数之间的 `, ` 不是千分位
+        assert_eq!(parse("SUM(1, 2)", &options()).unwrap().to_string(), "SUM(1, 2)");
+        let en_us = options().with_number_format(NumberFormat::EnUs);
+        assert_eq!(parse("ROUND(1.005, 2)", &en_us).unwrap().to_string(), "ROUND(1.005, 2)");
+        // 参数中的井号标记止于逗号，参数之外逗号仍属于标记
+        assert_eq!(parse("SUM(1 #a, 2 #b,3)", &options()).unwrap().to_string(), "SUM(1[a], 2[b], 3)");
+        assert_eq!(parse("1 #a,b", &options()).unwrap().to_string(), "1[a,b]");
+
+        assert_eq!(parse("AVG(B2)", &options()), Err(CalcError::UnknownFunction { name: "AVG".to_string(), span: Span::new(0, 3) }));
+        assert_eq!(parse("SUM(B2 B3)", &options()), Err(CalcError::InvalidExpression { span: Span::new(7, 9), expected: Expected::ArgumentSeparator }));
+        assert_eq!(parse("SUM(B2:总计)", &options()), Err(CalcError::InvalidExpression { span: Span::new(7, 9), expected: Expected::Cell }));
+        assert_eq!(parse("SUM(B2", &options()), Err(CalcError::MismatchedParens { pos: 3, ch: '(' }));
+        assert_eq!(parse("B2:B3", &options()), Err(CalcError::InvalidCharacter { pos: 2, ch: ':' }));
+    }
+
+    #[test]
+    fn test_depth_limits() {
+        let nested = |open: &str, depth: usize| format!("{}1{}", open.repeat(depth), ")".repeat(depth));
+        assert!(parse(&nested("(", MAX_NESTING), &options()).is_ok());
+        assert_eq!(parse(&nested("(", MAX_NESTING + 1), &options()), Err(CalcError::TooDeep { pos: MAX_NESTING }));
+        assert_eq!(parse(&nested("(", 20_000), &options()), Err(CalcError::TooDeep { pos: MAX_NESTING }));
+        assert_eq!(parse(&nested("SUM(", MAX_NESTING + 1), &options()), Err(CalcError::TooDeep { pos: MAX_NESTING * 4 + 3 }));
+        assert_eq!(parse(&format!("{}1", "-".repeat(MAX_NESTING + 1)), &options()), Err(CalcError::TooDeep { pos: MAX_NESTING }));
+        assert_eq!(
+            parse(&nested("(", 101), &options()).unwrap_err().to_string(),
+            "嵌套过深: 第 101 个字符处的括号、取负或函数调用超过 100 层"
+        );
+
+        // 连续的运算各算一层：MAX_DEPTH 个运算符可以，再多一个则在该运算符处报错
+        let chain = |terms: usize| vec!["1"; terms].join("+");
+        assert!(parse(&chain(MAX_DEPTH + 1), &options()).is_ok());
+        assert_eq!(parse(&chain(MAX_DEPTH + 2), &options()), Err(CalcError::TooLong { pos: 2 * MAX_DEPTH + 1 }));
+        assert!(parse(&vec!["2"; MAX_DEPTH + 2].join("*"), &options()).is_err());
+        // 括号中的运算与外层的运算合计深度
+        let grouped = format!("({}) + 1", chain(MAX_DEPTH));
+        assert!(parse(&grouped, &options()).is_err());
+        assert!(parse(&format!("({})", chain(MAX_DEPTH - 1)), &options()).is_ok());
+    }
+}
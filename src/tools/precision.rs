@@ -0,0 +1,213 @@
+//! # 小数位数
+//!
+//! 工具参数 `decimals` 可以是固定的位数，也可以是 `"auto"`：由预期值显示的小数位数推断精度。
+//! 百分数按百分数单位计数，`12.35%` 显示 2 位小数，对应的小数 0.1235 需要 4 位。
+
+use std::fmt;
+
+use serde::de::{self, Deserializer, Visitor};
+use serde::{Serialize, Serializer};
+
+use crate::tools::assertion::operands;
+use crate::tools::calculator::{CalcOptions, PercentRounding};
+use crate::tools::number_format::NumberFormat;
+use crate::tools::parser::parse;
+
+/// 未指定小数位数时的默认值
+pub const DEFAULT_DECIMALS: u32 = 2;
+
+/// 小数位数的上限：f64 只有约 15 位有效数字，更多的位数没有意义
+pub const MAX_DECIMALS: u32 = 15;
+
+/// 检查固定的小数位数不超过上限
+pub fn check_decimals(decimals: u32) -> Result<u32, String> {
+    if decimals > MAX_DECIMALS {
+        return Err(format!("小数位数不能超过 {}: {}", MAX_DECIMALS, decimals));
+    }
+    Ok(decimals)
+}
+
+/// 小数位数参数
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Decimals {
+    Fixed(u32),
+    /// 由预期值推断
+    Auto,
+}
+
+impl Default for Decimals {
+    fn default() -> Self {
+        Decimals::Fixed(DEFAULT_DECIMALS)
+    }
+}
+
+impl Decimals {
+    /// 解析不超过 `MAX_DECIMALS` 的非负整数或 `auto`（不区分大小写）
+    pub fn parse(input: &str) -> Option<Self> {
+        let input = input.trim();
+        if input.eq_ignore_ascii_case("auto") {
+            return Some(Decimals::Auto);
+        }
+        input.parse::<u32>().ok().filter(|&decimals| decimals <= MAX_DECIMALS).map(Decimals::Fixed)
+    }
+
+    /// 确定实际使用的小数位数，`Auto` 时按 `number_format` 读取预期值（可以是断言）
+    pub fn resolve(&self, expected: &str, number_format: NumberFormat) -> u32 {
+        match *self {
+            Decimals::Fixed(decimals) => decimals,
+            // 断言取各个边界中最多的小数位数
+            Decimals::Auto => operands(expected).into_iter().map(|operand| infer_decimals(operand, number_format)).max().unwrap_or(0),
+        }
+    }
+
+    /// 工具参数的 JSON Schema：非负整数或字符串 `"auto"`
+    pub fn json_schema() -> serde_json::Map<String, serde_json::Value> {
+        let schema = serde_json::json!({
+            "anyOf": [
+                { "type": "integer", "minimum": 0, "maximum": MAX_DECIMALS },
+                { "type": "string", "enum": ["auto"] }
+            ]
+        });
+        match schema {
+            serde_json::Value::Object(map) => map,
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// 预期值显示的小数位数；百分数按百分数单位计数后再加 2
+///
+/// 预期值是表达式时取各个数字中最多的小数位数。超过 `MAX_DECIMALS` 时取上限。
+pub fn infer_decimals(expected: &str, number_format: NumberFormat) -> u32 {
+    displayed_decimals(expected, number_format).min(MAX_DECIMALS)
+}
+
+fn displayed_decimals(expected: &str, number_format: NumberFormat) -> u32 {
+    let options = CalcOptions::new(0, PercentRounding::DivideBy100ThenRound).with_number_format(number_format);
+    match parse(expected, &options) {
+        Ok(ast) => ast
+            .literals()
+            .iter()
+            .map(|literal| literal_decimals(&literal.text, literal.percent, number_format))
+            .max()
+            .unwrap_or(0),
+        Err(_) => {
+            let expected = expected.trim();
+            match expected.strip_suffix('%') {
+                Some(number) => literal_decimals(number.trim_start_matches(['-', '+']), true, number_format),
+                None => literal_decimals(expected.trim_start_matches(['-', '+']), false, number_format),
+            }
+        }
+    }
+}
+
+/// 一个数字字面量（不含符号和百分号）显示的小数位数
+fn literal_decimals(text: &str, percent: bool, number_format: NumberFormat) -> u32 {
+    let normalized = number_format.normalize(text);
+    let decimals = normalized.split_once('.').map_or(0, |(_, fraction)| fraction.trim().len() as u32);
+    if percent {
+        decimals + 2
+    } else {
+        decimals
+    }
+}
+
+impl fmt::Display for Decimals {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Decimals::Fixed(decimals) => write!(f, "{}", decimals),
+            Decimals::Auto => write!(f, "auto"),
+        }
+    }
+}
+
+impl Serialize for Decimals {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Decimals::Fixed(decimals) => serializer.serialize_u32(*decimals),
+            Decimals::Auto => serializer.serialize_str("auto"),
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Decimals {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct DecimalsVisitor;
+
+        impl Visitor<'_> for DecimalsVisitor {
+            type Value = Decimals;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "非负整数或 \"auto\"")
+            }
+
+            fn visit_u64<E: de::Error>(self, value: u64) -> Result<Decimals, E> {
+                let decimals = u32::try_from(value).map_err(|_| E::custom("小数位数过大"))?;
+                check_decimals(decimals).map(Decimals::Fixed).map_err(E::custom)
+            }
+
+            fn visit_i64<E: de::Error>(self, value: i64) -> Result<Decimals, E> {
+                let decimals = u32::try_from(value).map_err(|_| E::custom("小数位数必须是非负整数"))?;
+                check_decimals(decimals).map(Decimals::Fixed).map_err(E::custom)
+            }
+
+            fn visit_f64<E: de::Error>(self, value: f64) -> Result<Decimals, E> {
+                if value >= 0.0 && value.fract() == 0.0 && value <= u32::MAX as f64 {
+                    check_decimals(value as u32).map(Decimals::Fixed).map_err(E::custom)
+                } else {
+                    Err(E::custom("小数位数必须是非负整数"))
+                }
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Decimals, E> {
+                Decimals::parse(value).ok_or_else(|| E::custom(format!("无效的小数位数: {}，支持 0-{} 的整数或 auto", value.trim(), MAX_DECIMALS)))
+            }
+        }
+
+        deserializer.deserialize_any(DecimalsVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_infer_decimals() {
+        assert_eq!(infer_decimals("1,234.5", NumberFormat::Auto), 1);
+        assert_eq!(infer_decimals("1.234,56", NumberFormat::Auto), 2);
+        assert_eq!(infer_decimals("1000", NumberFormat::Auto), 0);
+        assert_eq!(infer_decimals("-0.125", NumberFormat::Auto), 3);
+        assert_eq!(infer_decimals("12.35%", NumberFormat::Auto), 4);
+        assert_eq!(infer_decimals("50%", NumberFormat::Auto), 2);
+        // 显式格式下 `1,234` 是千分位而不是小数
+        assert_eq!(infer_decimals("1,234", NumberFormat::EnUs), 0);
+        assert_eq!(infer_decimals("1,234", NumberFormat::Auto), 3);
+        // 表达式取最多的小数位数
+        assert_eq!(infer_decimals("500.5 + 2,500.25", NumberFormat::EnUs), 2);
+        assert_eq!(infer_decimals(&format!("0.{}1", "0".repeat(70000)), NumberFormat::EnUs), MAX_DECIMALS);
+    }
+
+    #[test]
+    fn test_resolve_assertion() {
+        assert_eq!(Decimals::Auto.resolve(">= 0.125", NumberFormat::EnUs), 3);
+        assert_eq!(Decimals::Auto.resolve("between 20.5% and 40%", NumberFormat::EnUs), 3);
+        assert_eq!(Decimals::Fixed(2).resolve("< 0.125", NumberFormat::EnUs), 2);
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let parse = |json: &str| serde_json::from_str::<Decimals>(json);
+        assert_eq!(parse("3").unwrap(), Decimals::Fixed(3));
+        assert_eq!(parse("2.0").unwrap(), Decimals::Fixed(2));
+        assert_eq!(parse("\"auto\"").unwrap(), Decimals::Auto);
+        assert_eq!(parse("\"4\"").unwrap(), Decimals::Fixed(4));
+        assert!(parse("-1").is_err());
+        assert!(parse("\"two\"").is_err());
+        assert_eq!(parse("15").unwrap(), Decimals::Fixed(15));
+        assert!(parse("70000").unwrap_err().to_string().starts_with("小数位数不能超过 15: 70000"));
+        assert!(parse("\"16\"").is_err());
+        assert_eq!(Decimals::parse("4294967295"), None);
+        assert_eq!(serde_json::to_string(&Decimals::Auto).unwrap(), "\"auto\"");
+    }
+}
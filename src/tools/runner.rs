@@ -0,0 +1,725 @@
+//! # 批量验证的执行
+//!
+//! 输入按块处理：内联的 `expressions` 和 `items` 为一块，文件每 `BLOCK_LINES` 行一块。块内连续的、
+//! 不引用其他行的行相互独立，分给多个线程并行计算；引用其他行的行要等前面的行求值后才能计算，按顺序处理。
+//! 无论怎样划分，结果都按行号顺序汇总，输出与逐行计算完全一致。
+//!
+//! 汇总时即时应用 `failures_only` 和 `max_output_chars`，只保留要输出的行；失败行的差异在记录时即按重要性分类，
+//! 只累计每类的行数和合计。除输出的行以外，随输入增长的只有两部分：
+//!
+//! - 标记的值：后面任意一行都可能引用，因此保留到验证结束，占用随不同标记的个数增长，重复的标记覆盖原值，
+//!   不带标记的行不占用；
+//! - 重要性水平引用的标记有值之前出现的失败行：分类要等重要性水平算出，每行暂存差异和输出位置，
+//!   算出后立即分类并释放。基准是最后一行的合计时，暂存的行数即此前失败的行数。
+
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+use std::num::NonZeroUsize;
+use std::thread;
+
+use crate::tools::assertion::Assertion;
+use crate::tools::batch::{check_references, label_positions, references, BatchLine, ReferenceValue};
+use crate::tools::calculator::{calculate_in, check_in, evaluate_expected, CalcOptions, PercentRounding, Variables};
+use crate::tools::csv::{Columns, CsvReader, StatementRow};
+use crate::tools::discrepancy::analyze_in;
+use crate::tools::materiality::{Materiality, Severity};
+use crate::tools::number_format::NumberFormat;
+use crate::tools::output::{BatchOutput, BatchResult, MaterialitySummary, Settings};
+use crate::tools::precision::Decimals;
+use crate::tools::tolerance::Tolerance;
+use crate::tools::{labelled_operands, typo_report};
+
+/// 文件每块读取的行数，也是一次并行计算的最大行数
+pub const BLOCK_LINES: usize = 4096;
+
+/// 每个线程至少分到的行数，行数更少时不值得启动线程
+const MIN_LINES_PER_THREAD: usize = 64;
+
+/// 工具参数解析后的设置
+#[derive(Debug, Clone)]
+pub struct BatchConfig {
+    pub decimals: Option<Decimals>,
+    pub percent_rounding: PercentRounding,
+    pub number_format: NumberFormat,
+    pub strict_grouping: bool,
+    pub tolerance: Tolerance,
+    pub typo_check: bool,
+    pub reference_value: ReferenceValue,
+    /// 失败达到该行数后停止，fail_fast 相当于 1
+    pub max_failures: Option<u32>,
+    pub failures_only: bool,
+    pub max_output_chars: Option<u32>,
+    pub materiality: Option<Materiality>,
+    /// 并行计算的线程数，1 表示逐行计算
+    pub threads: usize,
+}
+
+impl BatchConfig {
+    /// 可用的处理器核数
+    pub fn available_threads() -> usize {
+        thread::available_parallelism().map_or(1, NonZeroUsize::get)
+    }
+}
+
+/// 一块待验证的行及其行号
+#[derive(Debug, Default)]
+pub struct Block {
+    pub numbers: Vec<u32>,
+    pub lines: Vec<Result<BatchLine, String>>,
+}
+
+impl Block {
+    pub fn push(&mut self, number: u32, line: Result<BatchLine, String>) {
+        self.numbers.push(number);
+        self.lines.push(line);
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+}
+
+/// 一行的验证结果
+struct Outcome {
+    text: String,
+    output: BatchResult,
+    /// 差异可以由舍入解释
+    rounding_only: bool,
+}
+
+/// 等待重要性水平算出后再分类的失败行
+struct PendingFailure {
+    difference: f64,
+    /// 在输出的行中的位置；未输出时为 `None`
+    kept: Option<usize>,
+}
+
+/// 按顺序汇总各块的结果
+pub struct BatchRunner<'a> {
+    config: &'a BatchConfig,
+    /// 带标记的行的值，供后面的行通过 `{标记}` 引用；没有可用数值的标记记录其行号
+    variables: Variables,
+    unavailable: HashMap<String, u32>,
+    passed: u32,
+    failed: u32,
+    skipped: u32,
+    stopped: bool,
+    results: Vec<String>,
+    outputs: Vec<BatchResult>,
+    chars: usize,
+    omitted: u32,
+    /// 重要性水平的金额，引用的标记都有值后计算一次
+    threshold: Option<f64>,
+    /// 重要性水平引用的标记
+    threshold_labels: Vec<String>,
+    pending: Vec<PendingFailure>,
+    summary_by_severity: MaterialitySummary,
+}
+
+impl<'a> BatchRunner<'a> {
+    pub fn new(config: &'a BatchConfig) -> Self {
+        let mut runner = BatchRunner {
+            config,
+            variables: Variables::new(),
+            unavailable: HashMap::new(),
+            passed: 0,
+            failed: 0,
+            skipped: 0,
+            stopped: false,
+            results: Vec::new(),
+            outputs: Vec::new(),
+            chars: 0,
+            omitted: 0,
+            threshold: None,
+            threshold_labels: config.materiality.as_ref().map_or_else(Vec::new, |materiality| references(materiality.source())),
+            pending: Vec::new(),
+            summary_by_severity: MaterialitySummary::default(),
+        };
+        runner.resolve_threshold();
+        runner
+    }
+
+    /// 验证一块；提前停止后其余的行计为跳过
+    pub fn run(&mut self, block: &Block) {
+        let positions = label_positions(&block.lines);
+        let mut index = 0;
+        while index < block.len() {
+            if !self.stopped && self.config.max_failures.is_some_and(|max| self.failed >= max) {
+                self.stopped = true;
+            }
+            if self.stopped {
+                self.skipped += (block.len() - index) as u32;
+                return;
+            }
+
+            if has_references(&block.lines[index]) {
+                let outcome = self.evaluate_referencing(block, &positions, index);
+                self.record(outcome);
+                index += 1;
+                continue;
+            }
+            let end = (index..block.len())
+                .take(BLOCK_LINES)
+                .find(|&i| has_references(&block.lines[i]))
+                .unwrap_or(block.len().min(index + BLOCK_LINES));
+            let outcomes = evaluate_parallel(self.config, &block.numbers[index..end], &block.lines[index..end], &self.variables);
+            for outcome in outcomes {
+                if self.config.max_failures.is_some_and(|max| self.failed >= max) {
+                    break;
+                }
+                self.record(outcome);
+                index += 1;
+            }
+        }
+    }
+
+    /// 逐块读取并验证文件，`number` 为文件第一行之前的行号；空行跳过但占用行号
+    pub fn run_file(&mut self, reader: impl BufRead, mut number: u32) -> io::Result<()> {
+        let mut records = reader.lines();
+        loop {
+            let mut block = Block::default();
+            while block.len() < BLOCK_LINES {
+                let Some(record) = records.next() else { break };
+                let record = record?;
+                number += 1;
+                if record.trim().is_empty() {
+                    continue;
+                }
+                // 停止后只需要计数
+                let line = if self.stopped { Err(String::new()) } else { BatchLine::parse_record(&record) };
+                block.push(number, line);
+            }
+            if block.is_empty() {
+                return Ok(());
+            }
+            self.run(&block);
+        }
+    }
+
+    /// 逐块读取并验证 CSV 报表；有公式的行参与验证，没有公式的行只定义数值，见 `csv` 模块
+    pub fn run_csv(&mut self, reader: impl BufRead) -> Result<(), String> {
+        let mut records = CsvReader::new(reader);
+        let header = records.next_record()?.ok_or("文件为空，缺少表头")?;
+        let columns = Columns::from_header(&header)?;
+        let mut block = Block::default();
+        while let Some(record) = records.next_record()? {
+            match columns.row(&record) {
+                StatementRow::Blank => {}
+                StatementRow::Check(line) => {
+                    let line = if self.stopped { Err(String::new()) } else { line };
+                    block.push(record.line, line);
+                }
+                StatementRow::Value { label, amount } => {
+                    // 定义的数值只对后面的行可见，先验证之前的行
+                    self.run(&std::mem::take(&mut block));
+                    self.define(record.line, &label, &amount);
+                }
+            }
+            if block.len() >= BLOCK_LINES {
+                self.run(&std::mem::take(&mut block));
+            }
+        }
+        self.run(&block);
+        Ok(())
+    }
+
+    /// 定义一个只提供数值的标记；金额无效时记为失败的行
+    fn define(&mut self, number: u32, label: &str, amount: &str) {
+        if self.stopped {
+            return;
+        }
+        let decimals = self.config.decimals.unwrap_or_default().resolve(amount, self.config.number_format);
+        let options = CalcOptions::new(decimals, self.config.percent_rounding)
+            .with_number_format(self.config.number_format)
+            .with_strict_grouping(self.config.strict_grouping);
+        match evaluate_expected(amount, &options) {
+            Ok(value) => {
+                self.variables.insert(label.to_string(), value);
+                self.unavailable.remove(label);
+                self.label_defined(label);
+            }
+            Err(e) => {
+                let message = format!("无效的金额 '{}': {}", amount, e.render(amount));
+                self.record(Outcome {
+                    text: format!("行 {}: [{}] {}", number, label, message),
+                    output: BatchResult {
+                        label: Some(label.to_string()),
+                        expected: Some(amount.to_string()),
+                        ..BatchResult::invalid(number, message)
+                    },
+                    rounding_only: false,
+                });
+            }
+        }
+    }
+
+    /// 计算引用其他行的一行
+    fn evaluate_referencing(&self, block: &Block, positions: &HashMap<&str, usize>, index: usize) -> Outcome {
+        let defined_earlier = |name: &str| self.variables.contains_key(name) || self.unavailable.contains_key(name);
+        let reference_error = check_references(&block.lines, positions, index, defined_earlier).err().or_else(|| {
+            let Ok(line) = &block.lines[index] else { return None };
+            line.references()
+                .into_iter()
+                .find_map(|name| self.unavailable.get(&name).map(|at| format!("引用的 {{{}}}（第 {} 行）没有可用的数值", name, at)))
+        });
+        evaluate(self.config, block.numbers[index], &block.lines[index], &self.variables, reference_error)
+    }
+
+    /// 按行号顺序记录一行的结果
+    fn record(&mut self, outcome: Outcome) {
+        let Outcome { text, output, rounding_only } = outcome;
+        if let Some(label) = &output.label {
+            let value = match self.config.reference_value {
+                ReferenceValue::Expected => output.expected_value.or(output.actual),
+                ReferenceValue::Computed => output.actual,
+            };
+            match value {
+                Some(value) => {
+                    self.variables.insert(label.clone(), value);
+                    self.unavailable.remove(label);
+                    self.label_defined(label);
+                }
+                None => {
+                    self.variables.remove(label);
+                    self.unavailable.insert(label.clone(), output.line);
+                }
+            }
+        }
+        if output.passed {
+            self.passed += 1;
+        } else {
+            self.failed += 1;
+        }
+
+        // 只保留失败的行，再按字符数截断
+        let listed = !(self.config.failures_only && output.passed);
+        let fits = self.omitted == 0
+            && self.config.max_output_chars.is_none_or(|max| self.chars + text.chars().count() < max as usize);
+        let kept = (listed && fits).then_some(self.results.len());
+        let difference = output.difference.filter(|_| !output.passed);
+        if kept.is_some() {
+            self.chars += text.chars().count() + 1;
+            self.results.push(text);
+            self.outputs.push(output);
+        } else if listed {
+            self.omitted += 1;
+        }
+        if let Some(difference) = difference {
+            if rounding_only || self.config.materiality.is_none() || self.threshold.is_some() {
+                self.classify(difference, Severity::classify(difference, rounding_only, self.threshold), kept);
+            } else {
+                self.pending.push(PendingFailure { difference, kept });
+            }
+        }
+    }
+
+    /// 累计一个失败行的分类，并在输出的行中注明
+    fn classify(&mut self, difference: f64, severity: Severity, kept: Option<usize>) {
+        self.summary_by_severity.total(severity).add(difference);
+        if let Some(kept) = kept {
+            self.outputs[kept].severity = Some(severity.name().to_string());
+            push_on_first_line(&mut self.results[kept], &format!(" 分类: {}", severity));
+        }
+    }
+
+    /// 标记有值后，如果重要性水平引用了它，尝试算出重要性水平
+    fn label_defined(&mut self, label: &str) {
+        if self.threshold.is_none() && self.threshold_labels.iter().any(|name| name == label) {
+            self.resolve_threshold();
+        }
+    }
+
+    /// 按当前的标记计算重要性水平；算出后对暂存的失败行分类
+    fn resolve_threshold(&mut self) {
+        let Some(Ok(threshold)) = self.evaluate_threshold() else { return };
+        self.threshold = Some(threshold);
+        for PendingFailure { difference, kept } in std::mem::take(&mut self.pending) {
+            self.classify(difference, Severity::classify(difference, false, Some(threshold)), kept);
+        }
+    }
+
+    fn evaluate_threshold(&self) -> Option<Result<f64, String>> {
+        let config = self.config;
+        config.materiality.as_ref().map(|materiality| {
+            let decimals = config.decimals.unwrap_or_default().resolve(materiality.source(), config.number_format);
+            let options = CalcOptions::new(decimals, config.percent_rounding).with_number_format(config.number_format);
+            materiality.evaluate(&options, &self.variables).map_err(|e| e.render(materiality.source()))
+        })
+    }
+
+    /// 生成汇总；重要性水平直到最后仍未算出时，暂存的失败行不分类，只区分尾差
+    pub fn finish(mut self) -> (String, BatchOutput) {
+        let config = self.config;
+        // 重要性水平的基准可能是最后才出现的合计行，此时失败行一直暂存到这里
+        self.resolve_threshold();
+        let threshold = match self.threshold {
+            Some(threshold) => Some(Ok(threshold)),
+            None => self.evaluate_threshold(),
+        };
+        let mut summary_by_severity = std::mem::take(&mut self.summary_by_severity);
+        summary_by_severity.threshold = self.threshold;
+
+        let (passed, failed, skipped) = (self.passed, self.failed, self.skipped);
+        let total = passed + failed + skipped;
+        let summary = if self.stopped {
+            format!("批量验证在 {} 行失败后停止：{} 个通过，{} 个失败，{} 个跳过（共 {} 个）", failed, passed, failed, skipped, total)
+        } else if passed == total {
+            format!("批量验证完成！所有 {} 个表达式均通过验证", total)
+        } else {
+            format!("批量验证完成！{}/{} 个表达式通过验证", passed, total)
+        };
+
+        let mut text = vec![summary, format!("默认容差: {}", config.tolerance)];
+        if let (Some(materiality), Some(threshold)) = (&config.materiality, &threshold) {
+            match threshold {
+                Ok(threshold) => text.push(format!("重要性水平: {} = {}", materiality, threshold)),
+                Err(e) => text.push(format!("重要性水平 '{}' 无法计算，只区分尾差: {}", materiality, e.replace('\n', "\n    "))),
+            }
+        }
+        if Severity::ALL.iter().any(|&severity| summary_by_severity.total(severity).count > 0) {
+            let totals: Vec<String> = Severity::ALL
+                .iter()
+                .map(|&severity| {
+                    let total = summary_by_severity.total(severity);
+                    format!("{} {} 行（差异合计 {}）", severity, total.count, total.total)
+                })
+                .collect();
+            text.push(format!("差异分类: {}", totals.join("，")));
+        }
+        text.push("".to_string());
+        text.append(&mut self.results);
+        if self.omitted > 0 {
+            text.push(format!("…… 超出 max_output_chars，省略 {} 行", self.omitted));
+        }
+
+        let output = BatchOutput {
+            total,
+            passed,
+            failed,
+            skipped,
+            omitted: self.omitted,
+            all_passed: passed == total,
+            materiality: summary_by_severity,
+            results: self.outputs,
+        };
+        (text.join("\n"), output)
+    }
+}
+
+/// 验证 CSV 报表并汇总
+pub fn validate_csv(config: &BatchConfig, reader: impl BufRead) -> Result<(String, BatchOutput), String> {
+    let mut runner = BatchRunner::new(config);
+    runner.run_csv(reader)?;
+    Ok(runner.finish())
+}
+
+fn has_references(line: &Result<BatchLine, String>) -> bool {
+    line.as_ref().is_ok_and(|line| !line.references().is_empty())
+}
+
+/// 并行计算互不引用的若干行，结果按原顺序返回
+fn evaluate_parallel(config: &BatchConfig, numbers: &[u32], lines: &[Result<BatchLine, String>], variables: &Variables) -> Vec<Outcome> {
+    let threads = config.threads.min(lines.len() / MIN_LINES_PER_THREAD).max(1);
+    if threads == 1 {
+        return numbers.iter().zip(lines).map(|(&number, line)| evaluate(config, number, line, variables, None)).collect();
+    }
+    let chunk = lines.len().div_ceil(threads);
+    thread::scope(|scope| {
+        let workers: Vec<_> = numbers
+            .chunks(chunk)
+            .zip(lines.chunks(chunk))
+            .map(|(numbers, lines)| {
+                scope.spawn(move || {
+                    numbers.iter().zip(lines).map(|(&number, line)| evaluate(config, number, line, variables, None)).collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+            .collect()
+    })
+}
+
+/// 验证一行；`reference_error` 为引用检查发现的错误
+fn evaluate(config: &BatchConfig, number: u32, line: &Result<BatchLine, String>, variables: &Variables, reference_error: Option<String>) -> Outcome {
+    let line = match line {
+        Ok(line) => line,
+        Err(message) => {
+            return Outcome {
+                text: format!("行 {}: {}", number, message),
+                output: BatchResult::invalid(number, message.clone()),
+                rounding_only: false,
+            };
+        }
+    };
+    let (expression, expected_str, overrides) = (line.expression.as_str(), line.expected.as_str(), line.overrides);
+    let label_prefix = line.prefix();
+    let label = (!line.label.is_empty()).then(|| line.label.clone());
+
+    if let Some(message) = reference_error {
+        return Outcome {
+            text: format!("行 {}: {}{} - 引用错误: {}", number, label_prefix, expression, message),
+            output: BatchResult {
+                id: line.id.clone(),
+                label,
+                expression: Some(expression.to_string()),
+                expected: Some(expected_str.to_string()),
+                ..BatchResult::invalid(number, message)
+            },
+            rounding_only: false,
+        };
+    }
+
+    let strategy = overrides.percent_rounding.unwrap_or(config.percent_rounding);
+    let tolerance = overrides.tolerance.unwrap_or(config.tolerance);
+    let decimals = overrides.decimals.or(config.decimals).unwrap_or_default().resolve(expected_str, config.number_format);
+    let options = CalcOptions::new(decimals, strategy)
+        .with_number_format(config.number_format)
+        .with_strict_grouping(config.strict_grouping)
+        .with_tolerance(tolerance);
+    let mut output = BatchResult {
+        line: number,
+        id: line.id.clone(),
+        label,
+        expression: Some(expression.to_string()),
+        expected: Some(expected_str.to_string()),
+        expected_value: None,
+        actual: None,
+        difference: None,
+        passed: false,
+        error: None,
+        settings: Some(Settings::new(&options)),
+        severity: None,
+    };
+    let mut rounding_only = false;
+
+    let mut text = match Assertion::parse_in(expected_str, &options, variables) {
+        Ok(Assertion::Equal(expected)) => {
+            output.expected_value = Some(expected);
+            let check = check_in(expression, expected, &options, variables);
+            if let Ok(check) = &check {
+                output.actual = Some(check.actual);
+                output.difference = Some(check.difference);
+                output.passed = check.passed;
+            }
+            match check {
+                // 精确匹配时保持简洁的输出
+                Ok(check) if check.passed && tolerance == Tolerance::Exact => {
+                    format!("行 {}: {}{} = {} (通过)", number, label_prefix, expression, expected)
+                }
+                Ok(check) if check.passed => {
+                    format!("行 {}: {}{} ≈ {} (通过，差异 {})", number, label_prefix, expression, expected, check)
+                }
+                Ok(check) => {
+                    let mut text = format!("行 {}: {}{} ≠ {} (实际: {}，差异 {})", number, label_prefix, expression, expected, check.actual, check);
+                    if let Some(operands) = labelled_operands(expression, &options) {
+                        text.push_str(&format!(" 操作数: {}", operands));
+                    }
+                    if let Ok(discrepancy) = analyze_in(expression, expected, &options, variables) {
+                        text.push_str(&format!(" 分析: {}", discrepancy.summary()));
+                        rounding_only = discrepancy.explained_by_rounding();
+                    }
+                    if config.typo_check {
+                        let report = typo_report(expression, expected, check.difference, &options, variables);
+                        if !report.is_empty() {
+                            text.push_str(&report.replace('\n', "\n    "));
+                        }
+                    }
+                    text
+                }
+                Err(e) => {
+                    output.error = Some(e.render(expression));
+                    let detail = e.render(expression).replace('\n', "\n    ");
+                    format!("行 {}: {}{} - 计算错误: {}", number, label_prefix, expression, detail)
+                }
+            }
+        }
+        Ok(assertion) => match calculate_in(expression, &options, variables) {
+            Ok(actual) => {
+                output.actual = Some(actual);
+                output.passed = assertion.check(actual, &options);
+                if output.passed {
+                    format!("行 {}: {}{} {} (通过，实际: {})", number, label_prefix, expression, assertion, actual)
+                } else {
+                    format!("行 {}: {}{} 不满足 {} (实际: {})", number, label_prefix, expression, assertion, actual)
+                }
+            }
+            Err(e) => {
+                output.error = Some(e.render(expression));
+                let detail = e.render(expression).replace('\n', "\n    ");
+                format!("行 {}: {}{} - 计算错误: {}", number, label_prefix, expression, detail)
+            }
+        },
+        Err(e) => {
+            output.error = Some(format!("无效的预期值: {}", e.render(expected_str)));
+            let detail = e.render(expected_str).replace('\n', "\n    ");
+            format!("行 {}: {}无效的预期值 '{}': {}", number, label_prefix, expected_str, detail)
+        }
+    };
+    // 注明该行实际使用的设置
+    push_on_first_line(&mut text, &format!(" [小数位数: {}，百分数: {}，容差: {}]", decimals, strategy.description(), tolerance));
+    Outcome { text, output, rounding_only }
+}
+
+/// 在一行结果的第一行末尾追加说明，其后缩进的详情（录入错误提示、出错位置）保持在下面
+fn push_on_first_line(text: &mut String, suffix: &str) {
+    let end = text.find('\n').unwrap_or(text.len());
+    text.insert_str(end, suffix);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(threads: usize) -> BatchConfig {
+        BatchConfig {
+            decimals: None,
+            percent_rounding: PercentRounding::RoundThenDivideBy100,
+            number_format: NumberFormat::Auto,
+            strict_grouping: false,
+            tolerance: Tolerance::Exact,
+            typo_check: false,
+            reference_value: ReferenceValue::Expected,
+            max_failures: None,
+            failures_only: false,
+            max_output_chars: None,
+            materiality: None,
+            threads,
+        }
+    }
+
+    fn block(lines: &[String]) -> Block {
+        let mut block = Block::default();
+        for (index, line) in lines.iter().enumerate() {
+            block.push(index as u32 + 1, BatchLine::parse(line));
+        }
+        block
+    }
+
+    fn run(config: &BatchConfig, lines: &[String]) -> (String, BatchOutput) {
+        let mut runner = BatchRunner::new(config);
+        runner.run(&block(lines));
+        runner.finish()
+    }
+
+    /// 大量独立的行夹杂着引用前面的行
+    fn ledger(count: usize) -> Vec<String> {
+        (0..count)
+            .map(|i| match i % 500 {
+                499 => format!("{{L{}}} + {{L{}}}|{}|S{}", i - 1, i - 2, 2 * (i - 1) - 1 + (i / 1000) % 2, i),
+                _ if i % 7 == 0 => format!("{} * 1.5%|{}|L{}", i, (i as f64 * 0.015 * 100.0).round() / 100.0 + 0.01, i),
+                _ => format!("{} + 1|{}|L{}", i - 1, i, i),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parallel_matches_sequential() {
+        let lines = ledger(3000);
+        let (text, output) = run(&config(1), &lines);
+        for threads in [2, 3, 8] {
+            let (parallel_text, parallel_output) = run(&config(threads), &lines);
+            assert_eq!(parallel_text, text);
+            assert_eq!(serde_json::to_value(&parallel_output).unwrap(), serde_json::to_value(&output).unwrap());
+        }
+        assert_eq!(output.total, 3000);
+        assert!(output.failed > 0 && output.passed > 0);
+    }
+
+    #[test]
+    fn test_stop_after_failures() {
+        let mut config = config(4);
+        config.max_failures = Some(2);
+        let lines: Vec<String> = (0..1000).map(|i| format!("{}|{}", i, if i == 300 || i == 700 || i == 900 { i + 1 } else { i })).collect();
+        let (text, output) = run(&config, &lines);
+        assert_eq!((output.passed, output.failed, output.skipped, output.total), (699, 2, 299, 1000));
+        assert!(text.starts_with("批量验证在 2 行失败后停止"));
+        // 最后一行失败时不算提前停止
+        let (text, output) = run(&config, &lines[..701]);
+        assert_eq!(output.skipped, 0);
+        assert!(text.starts_with("批量验证完成！699/701"));
+    }
+
+    #[test]
+    fn test_typo_check_with_references() {
+        let config = BatchConfig { typo_check: true, number_format: NumberFormat::EnUs, ..config(1) };
+        let lines = ["1,000|1,000|X".to_string(), "{X} + 1,450 + 500|3040".to_string()];
+        let (text, _) = run(&config, &lines);
+        assert!(text.ends_with("第 1 个操作数 1,450 可能数字换位，应为 1540"), "{}", text);
+        // 设置和分类在结果行上，不在录入错误提示之后
+        assert!(text.contains("(实际: 2950，差异 -90（允许 ±0，超出 90）) 分析: -9000 个末位单位，不能由舍入解释 [小数位数: 2，百分数: 先舍入后除以100，容差: 精确匹配] 分类: 重要\n    可能的录入错误:"), "{}", text);
+
+        let (text, _) = run(&config, &["1|1 +".to_string()]);
+        let first = text.lines().find(|line| line.starts_with("行 1:")).unwrap();
+        assert!(first.ends_with("容差: 精确匹配]"), "{}", text);
+        assert!(text.trim_end().ends_with('^'), "{}", text);
+    }
+
+    #[test]
+    fn test_bounded_output() {
+        let mut config = config(2);
+        config.failures_only = true;
+        config.max_output_chars = Some(200);
+        let lines: Vec<String> = (0..2000).map(|i| format!("{}|{}", i, if i % 10 == 0 { i + 1 } else { i })).collect();
+        let (text, output) = run(&config, &lines);
+        assert_eq!((output.passed, output.failed), (1800, 200));
+        assert!(output.results.len() < 5 && output.results.iter().all(|result| !result.passed));
+        assert_eq!(output.omitted, 200 - output.results.len() as u32);
+        assert!(text.ends_with(&format!("省略 {} 行", output.omitted)));
+    }
+
+    #[test]
+    fn test_classify_when_recorded() {
+        let mut config = config(1);
+        config.materiality = Some(Materiality::parse("1% of {总计}").unwrap());
+        let lines: Vec<String> = ["100|101|A", "100|100.01|B", "1000|1000|总计", "1|2|C", "1|50|D"].iter().map(|line| line.to_string()).collect();
+        let mut runner = BatchRunner::new(&config);
+        runner.run(&block(&lines[..2]));
+        // 基准出现之前的失败行暂存，之后的行在记录时分类
+        assert_eq!(runner.pending.len(), 2);
+        runner.run(&block(&lines[2..]));
+        assert!(runner.pending.is_empty() && runner.threshold == Some(10.0));
+        let (text, output) = runner.finish();
+        assert!(text.contains("重要性水平: 1% of {总计} = 10"));
+        let severities: Vec<_> = output.results.iter().filter_map(|result| result.severity.as_deref()).collect();
+        assert_eq!(severities, vec!["below_threshold", "below_threshold", "below_threshold", "material"]);
+        assert_eq!((output.materiality.below_threshold.count, output.materiality.material.count), (3, 1));
+    }
+
+    #[test]
+    fn test_run_file() {
+        let config = config(2);
+        let mut runner = BatchRunner::new(&config);
+        runner.run(&block(&["1 + 1|2|A".to_string()]));
+        let file = "{A} + 1|3|B\n\n{\"expression\": \"{B} * 2\", \"expected\": \"6\", \"id\": \"r3\"}\n{C}|1\n";
+        runner.run_file(io::Cursor::new(file), 1).unwrap();
+        let (_, output) = runner.finish();
+        // 空行占用行号
+        let lines: Vec<u32> = output.results.iter().map(|result| result.line).collect();
+        assert_eq!(lines, vec![1, 2, 4, 5]);
+        assert_eq!((output.passed, output.failed), (3, 1));
+        assert_eq!(output.results[2].id.as_deref(), Some("r3"));
+    }
+
+    #[test]
+    fn test_run_csv() {
+        let config = config(2);
+        let file = "项目,金额,公式\n货币资金,\"1,000.00\",\n应收账款,\"2,000.00\",\n\n流动资产合计,\"3,000.00\",{货币资金} + {应收账款}\n存货,abc,\n资产总计,\"3,001.00\",={流动资产合计}\n速动比率,,{货币资金} / {存货} >= 1\n";
+        let (text, output) = validate_csv(&config, io::Cursor::new(file)).unwrap();
+        // 没有公式的行只定义数值，不计入结果
+        let lines: Vec<u32> = output.results.iter().map(|result| result.line).collect();
+        assert_eq!(lines, vec![5, 6, 7, 8]);
+        assert_eq!((output.total, output.passed, output.failed), (4, 1, 3));
+        assert!(text.contains("行 6: [存货] 无效的金额 'abc'"));
+        assert!(output.results[2].error.is_none() && output.results[2].actual == Some(3000.0));
+        assert!(output.results[3].error.as_deref().is_some_and(|error| error.contains("存货")));
+
+        assert_eq!(validate_csv(&config, io::Cursor::new("")).unwrap_err(), "文件为空，缺少表头");
+        assert!(validate_csv(&config, io::Cursor::new("公式\n")).unwrap_err().contains("label"));
+    }
+}
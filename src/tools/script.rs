@@ -0,0 +1,114 @@
+//! # 脚本
+//!
+//! 工作底稿中的计算往往分几步完成，例如：
+//!
+//! ```text
+//! gross = 12,000 * 1.13; tax = gross * 13% / 1.13; gross - tax
+//! ```
+//!
+//! 脚本按顺序执行语句。每条赋值的结果按 `CalcOptions` 的规则舍入后保存，后续语句引用的是舍入后的值，
+//! 因此中间数与底稿上列示的数字一致。最后一条语句的值（同样经过舍入）就是脚本的结果。
+
+use rust_mcp_sdk::macros::JsonSchema;
+use serde::Serialize;
+
+use crate::tools::ast::Statement;
+use crate::tools::calculator::{evaluate_traced, CalcError, CalcOptions, Variables};
+use crate::tools::parser::parse_script;
+
+/// 一条赋值语句的结果
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct Binding {
+    pub name: String,
+    /// 舍入后的值
+    pub value: f64,
+}
+
+/// 脚本的执行结果
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScriptResult {
+    /// 按赋值顺序排列的中间结果
+    pub bindings: Vec<Binding>,
+    /// 最后一条语句的值
+    pub value: f64,
+}
+
+/// 执行脚本
+///
+/// 变量必须先赋值后引用，同一变量不能重复赋值。
+pub fn run_script(script: &str, options: &CalcOptions) -> Result<ScriptResult, CalcError> {
+    let statements = parse_script(script, options)?;
+
+    let mut variables = Variables::new();
+    let mut bindings = Vec::new();
+    let mut value = 0.0;
+    for statement in &statements {
+        match statement {
+            Statement::Assign { name, expr, span } => {
+                if variables.contains_key(name) {
+                    return Err(CalcError::DuplicateVariable { name: name.clone(), span: *span });
+                }
+                value = options.round(evaluate_traced(expr, options, &variables, None)?);
+                variables.insert(name.clone(), value);
+                bindings.push(Binding { name: name.clone(), value });
+            }
+            Statement::Expr { expr } => {
+                value = options.round(evaluate_traced(expr, options, &variables, None)?);
+            }
+        }
+    }
+
+    Ok(ScriptResult { bindings, value })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::calculator::PercentRounding;
+    use crate::tools::diagnostic::Span;
+    use crate::tools::number_format::NumberFormat;
+
+    fn options() -> CalcOptions {
+        CalcOptions::new(2, PercentRounding::DivideBy100ThenRound).with_number_format(NumberFormat::EnUs)
+    }
+
+    fn names(result: &ScriptResult) -> Vec<(&str, f64)> {
+        result.bindings.iter().map(|b| (b.name.as_str(), b.value)).collect()
+    }
+
+    #[test]
+    fn test_assignments_in_order() {
+        let result = run_script("gross = 12,000 * 1.13; tax = gross * 13% / 1.13; gross - tax", &options()).unwrap();
+        assert_eq!(names(&result), vec![("gross", 13560.0), ("tax", 1560.0)]);
+        assert_eq!(result.value, 12000.0);
+
+        // 换行同样分隔语句，空行被忽略，括号内的换行不分隔语句
+        let result = run_script("收入 = 100\n\n成本 = (60\n + 10)\n毛利 = 收入 - 成本\n", &options()).unwrap();
+        assert_eq!(names(&result), vec![("收入", 100.0), ("成本", 70.0), ("毛利", 30.0)]);
+        assert_eq!(result.value, 30.0);
+    }
+
+    #[test]
+    fn test_each_assignment_is_rounded() {
+        // a 舍入为 0.33，b 使用舍入后的值
+        let result = run_script("a = 1 / 3; b = a * 3", &options()).unwrap();
+        assert_eq!(names(&result), vec![("a", 0.33), ("b", 0.99)]);
+    }
+
+    #[test]
+    fn test_script_errors() {
+        assert_eq!(
+            run_script("a = 1; b = c + 1", &options()),
+            Err(CalcError::UndefinedVariable { name: "c".to_string(), span: Span::new(11, 12) })
+        );
+        assert_eq!(
+            run_script("a = 1\na = 2", &options()),
+            Err(CalcError::DuplicateVariable { name: "a".to_string(), span: Span::new(6, 11) })
+        );
+        assert_eq!(
+            run_script("a = 1 +; a", &options()),
+            Err(CalcError::UnexpectedEndOfExpression { pos: 7, expected: crate::tools::diagnostic::Expected::Operand })
+        );
+        assert!(run_script(" ; \n", &options()).is_err());
+    }
+}
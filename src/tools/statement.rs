@@ -0,0 +1,451 @@
+//! # 报表层级验证
+//!
+//! 报表是一棵树，例如 资产 → 流动资产 → 货币资金。每个有子项的节点（小计、合计）应等于子项之和，
+//! 资产负债表还要满足会计恒等式 资产 = 负债 + 所有者权益。
+//!
+//! 求和沿用 `calculate` 的舍入规则：子项金额先按小数位数舍入再相加，和再舍入一次。父项与子项比较的是
+//! 报表上列示的金额，因此一个小计出错只报告在该小计上，不会连带上层的合计。
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::tools::assertion::{split_equation, Assertion};
+use crate::tools::batch::references;
+use crate::tools::calculator::{calculate_in, evaluate_expected, CalcError, CalcOptions, Variables};
+use crate::tools::number_format::NumberFormat;
+use crate::tools::output::{report_text, signed_terms, IdentityResult, Settings, StatementChild, StatementError, StatementNodeResult, StatementOutput};
+use crate::tools::precision::infer_decimals;
+use crate::tools::tolerance::{Tolerance, ToleranceCheck};
+
+/// 所有者权益的常见名称
+const EQUITY_LABELS: [&str; 2] = ["所有者权益", "股东权益"];
+
+/// 报表中的一项
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct StatementNode {
+    pub label: String,
+    /// 报表金额；有子项时可以省略，省略时取子项之和
+    pub amount: Option<String>,
+    /// 在父项中减去，例如 "减：累计折旧"
+    pub subtract: Option<bool>,
+    pub children: Option<Vec<StatementNode>>,
+}
+
+impl StatementNode {
+    /// 节点是递归的，手写输出模式，子项只声明为对象
+    pub fn json_schema() -> serde_json::Map<String, serde_json::Value> {
+        let schema = serde_json::json!({
+            "type": "object",
+            "description": "报表中的一项",
+            "properties": {
+                "label": { "type": "string", "description": "项目名称，例如 流动资产" },
+                "amount": {
+                    "type": "string",
+                    "description": "报表金额，例如 1,234.56；有子项时可以省略，省略时取子项之和"
+                },
+                "subtract": { "type": "boolean", "description": "在父项中减去，例如 减：累计折旧，默认 false" },
+                "children": {
+                    "type": "array",
+                    "description": "子项，结构与本项相同",
+                    "items": { "type": "object" }
+                }
+            },
+            "required": ["label"]
+        });
+        match schema {
+            serde_json::Value::Object(map) => map,
+            _ => unreachable!(),
+        }
+    }
+
+    fn children(&self) -> &[StatementNode] {
+        self.children.as_deref().unwrap_or_default()
+    }
+
+    fn sign(&self) -> f64 {
+        if self.subtract.unwrap_or(false) {
+            -1.0
+        } else {
+            1.0
+        }
+    }
+}
+
+/// 一个小计或合计的核对结果
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeCheck {
+    /// 从顶层到该节点的项目名称
+    pub path: Vec<String>,
+    /// 报表金额（舍入后）
+    pub reported: f64,
+    /// 子项的名称和带符号的金额（舍入后）
+    pub children: Vec<(String, f64)>,
+    /// 子项之和
+    pub sum: f64,
+    /// 子项之和与报表金额的比较
+    pub check: ToleranceCheck,
+}
+
+/// 无法核对的节点
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeError {
+    pub path: Vec<String>,
+    pub message: String,
+}
+
+/// 整张报表的核对结果
+#[derive(Debug, Clone, Default)]
+pub struct StatementReport {
+    pub checks: Vec<NodeCheck>,
+    pub errors: Vec<NodeError>,
+    /// 各项目的金额，供恒等式引用：每项都可以用完整路径 `{资产 → 流动资产 → 其他}` 引用，
+    /// 名称唯一的项目还可以只用名称 `{其他}` 引用
+    pub values: Variables,
+    /// 出现在多处的名称及各处的完整路径，恒等式只用名称引用它们时报告为有歧义
+    pub ambiguous: HashMap<String, Vec<String>>,
+}
+
+/// 一个恒等式的核对结果
+#[derive(Debug, Clone, PartialEq)]
+pub struct IdentityCheck {
+    pub identity: String,
+    pub assertion: Assertion,
+    pub actual: f64,
+    pub passed: bool,
+}
+
+/// 核对报表的小计、合计和恒等式并汇总；未指定恒等式时按 `default_identities` 核对
+pub fn validate(roots: &[StatementNode], identities: Option<&[String]>, options: &CalcOptions) -> (String, StatementOutput) {
+    let report = verify(roots, options);
+    let identities = identities.map_or_else(|| default_identities(&report.values), <[String]>::to_vec);
+
+    let mut lines = Vec::new();
+    let mut nodes = Vec::new();
+    for check in &report.checks {
+        let path = check.path.join(" → ");
+        if !check.check.passed {
+            lines.push(format!("{}: {} ≠ 子项之和 {} (差异 {})", path, check.reported, check.sum, check.check));
+            lines.push(format!("    子项: {}", signed_terms(&check.children)));
+        } else if options.tolerance == Tolerance::Exact {
+            lines.push(format!("{}: {} = 子项之和 {} (通过)", path, check.reported, check.sum));
+        } else {
+            lines.push(format!("{}: {} ≈ 子项之和 {} (通过，差异 {})", path, check.reported, check.sum, check.check));
+        }
+        nodes.push(StatementNodeResult {
+            path: check.path.clone(),
+            reported: check.reported,
+            children_sum: check.sum,
+            difference: check.check.difference,
+            allowed: check.check.allowed,
+            passed: check.check.passed,
+            children: check.children.iter().map(|(label, amount)| StatementChild { label: label.clone(), amount: *amount }).collect(),
+        });
+    }
+    let mut identity_results = Vec::new();
+    for identity in &identities {
+        let result = match check_identity(identity, options, &report) {
+            Ok(check) => {
+                if check.passed {
+                    lines.push(format!("恒等式 {}: {} {} (通过)", check.identity, check.actual, check.assertion));
+                } else {
+                    lines.push(format!("恒等式 {}: 不成立，左边 {} 不满足 {}", check.identity, check.actual, check.assertion));
+                }
+                IdentityResult {
+                    identity: check.identity,
+                    assertion: Some(check.assertion.to_string()),
+                    actual: Some(check.actual),
+                    passed: check.passed,
+                    error: None,
+                }
+            }
+            Err(message) => {
+                lines.push(format!("恒等式 {}: 无法计算: {}", identity.trim(), message.replace('\n', "\n    ")));
+                IdentityResult { identity: identity.trim().to_string(), assertion: None, actual: None, passed: false, error: Some(message) }
+            }
+        };
+        identity_results.push(result);
+    }
+    for error in &report.errors {
+        lines.push(format!("{}: {}", error.path.join(" → "), error.message.replace('\n', "\n    ")));
+    }
+
+    let failed_nodes = nodes.iter().filter(|node| !node.passed).count();
+    let failed_identities = identity_results.iter().filter(|identity| !identity.passed).count();
+    let checked = (nodes.len() + identity_results.len()) as u32;
+    let failed = (failed_nodes + failed_identities) as u32;
+    let all_passed = failed == 0 && report.errors.is_empty();
+    let summary = if all_passed {
+        format!("报表验证完成！{} 个小计/合计和 {} 个恒等式全部成立", nodes.len(), identity_results.len())
+    } else {
+        let mut parts = vec![
+            format!("{} 个小计/合计中 {} 个不符", nodes.len(), failed_nodes),
+            format!("{} 个恒等式中 {} 个不成立", identity_results.len(), failed_identities),
+        ];
+        if !report.errors.is_empty() {
+            parts.push(format!("{} 个项目无法核对", report.errors.len()));
+        }
+        format!("报表验证发现不一致：{}", parts.join("，"))
+    };
+    (report_text(summary, options, lines), StatementOutput {
+        checked,
+        failed,
+        all_passed,
+        nodes,
+        identities: identity_results,
+        errors: report.errors.iter().map(|error| StatementError { path: error.path.clone(), error: error.message.clone() }).collect(),
+        settings: Settings::new(options),
+    })
+}
+
+/// 逐个节点核对子项之和
+pub fn verify(roots: &[StatementNode], options: &CalcOptions) -> StatementReport {
+    let mut report = StatementReport::default();
+    let mut labels: HashMap<String, Vec<String>> = HashMap::new();
+    for root in roots {
+        visit(root, &mut Vec::new(), options, &mut report, &mut labels);
+    }
+    for (label, paths) in labels {
+        if paths.len() > 1 {
+            report.ambiguous.insert(label, paths);
+        } else if let Some(&value) = report.values.get(&paths[0]) {
+            report.values.insert(label, value);
+        }
+    }
+    report
+}
+
+/// 核对一个节点及其子树，返回该节点的金额；金额不可用时返回 `None`
+///
+/// `labels` 收集每个名称出现的完整路径。
+fn visit(
+    node: &StatementNode,
+    path: &mut Vec<String>,
+    options: &CalcOptions,
+    report: &mut StatementReport,
+    labels: &mut HashMap<String, Vec<String>>,
+) -> Option<f64> {
+    path.push(node.label.trim().to_string());
+    let full_path = path.join(" → ");
+    labels.entry(node.label.trim().to_string()).or_default().push(full_path.clone());
+    let reported = match node.amount.as_deref().map(str::trim).filter(|amount| !amount.is_empty()) {
+        Some(amount) => match evaluate_expected(amount, options) {
+            Ok(value) => Some(options.round(value)),
+            Err(e) => {
+                report.errors.push(NodeError { path: path.clone(), message: format!("无效的金额 '{}': {}", amount, e.render(amount)) });
+                None
+            }
+        },
+        None if node.children().is_empty() => {
+            report.errors.push(NodeError { path: path.clone(), message: "缺少金额：没有子项的项目必须列示金额".to_string() });
+            None
+        }
+        None => None,
+    };
+
+    let mut children = Vec::new();
+    let mut complete = true;
+    for child in node.children() {
+        match visit(child, path, options, report, labels) {
+            Some(value) => children.push((child.label.trim().to_string(), child.sign() * value)),
+            None => complete = false,
+        }
+    }
+
+    // 子项的错误已经报告，缺少子项金额时不再核对本项
+    let value = if node.children().is_empty() || !complete {
+        reported
+    } else {
+        let sum = options.round(children.iter().map(|(_, value)| value).sum());
+        if let Some(reported) = reported {
+            let check = options.tolerance.check(sum, reported, options.decimals);
+            report.checks.push(NodeCheck { path: path.clone(), reported, children, sum, check });
+        }
+        Some(reported.unwrap_or(sum))
+    };
+
+    if let Some(value) = value {
+        report.values.insert(full_path, value);
+    }
+    path.pop();
+    value
+}
+
+/// 核对一个恒等式，例如 `{资产} = {负债} + {所有者权益}`；右边也可以是断言，例如 `{流动比率} >= 1`
+///
+/// 引用出现在多处的名称时报错，列出可用的完整路径。
+pub fn check_identity(identity: &str, options: &CalcOptions, report: &StatementReport) -> Result<IdentityCheck, String> {
+    let (lhs, rhs) = split_equation(identity).ok_or_else(|| format!("恒等式 '{}' 应为 `左边 = 右边` 或断言的形式", identity))?;
+    if let Some((name, paths)) = references(identity).into_iter().find_map(|name| report.ambiguous.get(&name).map(|paths| (name, paths))) {
+        let paths: Vec<String> = paths.iter().map(|path| format!("{{{}}}", path)).collect();
+        return Err(format!("引用有歧义: 项目 '{}' 出现在多处，请用完整路径引用：{}", name, paths.join("、")));
+    }
+    let values = &report.values;
+    let assertion = Assertion::parse_in(rhs, options, values).map_err(|e: CalcError| e.render(rhs))?;
+    let actual = calculate_in(lhs, options, values).map_err(|e| e.render(lhs))?;
+    Ok(IdentityCheck {
+        identity: identity.trim().to_string(),
+        passed: assertion.check(actual, options),
+        assertion,
+        actual,
+    })
+}
+
+/// 报表中有资产、负债和所有者权益时默认核对的会计恒等式
+pub fn default_identities(values: &Variables) -> Vec<String> {
+    let equity = EQUITY_LABELS.iter().find(|label| values.contains_key(**label));
+    match equity {
+        Some(equity) if values.contains_key("资产") && values.contains_key("负债") => {
+            vec![format!("{{资产}} = {{负债}} + {{{}}}", equity)]
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// 所有金额中最多的小数位数，用于 `decimals` 为 auto 时
+pub fn infer_statement_decimals(roots: &[StatementNode], number_format: NumberFormat) -> u32 {
+    roots
+        .iter()
+        .map(|node| {
+            let own = node.amount.as_deref().map_or(0, |amount| infer_decimals(amount, number_format));
+            own.max(infer_statement_decimals(node.children(), number_format))
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::calculator::PercentRounding;
+    use crate::tools::tolerance::Tolerance;
+
+    fn node(label: &str, amount: Option<&str>, children: Vec<StatementNode>) -> StatementNode {
+        StatementNode {
+            label: label.to_string(),
+            amount: amount.map(str::to_string),
+            subtract: None,
+            children: (!children.is_empty()).then_some(children),
+        }
+    }
+
+    fn leaf(label: &str, amount: &str) -> StatementNode {
+        node(label, Some(amount), Vec::new())
+    }
+
+    fn options() -> CalcOptions {
+        CalcOptions::new(2, PercentRounding::RoundThenDivideBy100).with_number_format(NumberFormat::EnUs)
+    }
+
+    fn balance_sheet() -> Vec<StatementNode> {
+        let mut depreciation = leaf("减：累计折旧", "200.00");
+        depreciation.subtract = Some(true);
+        vec![
+            node(
+                "资产",
+                Some("10,000.00"),
+                vec![
+                    node("流动资产", Some("3,000.00"), vec![leaf("货币资金", "1,000.004"), leaf("应收账款", "1,999.00")]),
+                    node("非流动资产", None, vec![leaf("固定资产原值", "7,200.00"), depreciation]),
+                ],
+            ),
+            node("负债", Some("4,000.00"), vec![leaf("短期借款", "4,000.00")]),
+            leaf("所有者权益", "6,000.00"),
+        ]
+    }
+
+    #[test]
+    fn test_verify() {
+        let report = verify(&balance_sheet(), &options());
+        assert!(report.errors.is_empty());
+        let paths: Vec<String> = report.checks.iter().map(|check| check.path.join(" → ")).collect();
+        assert_eq!(paths, vec!["资产 → 流动资产", "资产", "负债"]);
+
+        // 货币资金先舍入为 1,000.00，流动资产的子项之和为 2,999.00
+        let current = &report.checks[0];
+        assert_eq!((current.sum, current.reported, current.check.passed), (2999.0, 3000.0, false));
+        assert_eq!(current.children, vec![("货币资金".to_string(), 1000.0), ("应收账款".to_string(), 1999.0)]);
+        // 资产与列示的流动资产和非流动资产（子项之和 7,000.00）比较
+        assert_eq!((report.checks[1].sum, report.checks[1].check.passed), (10000.0, true));
+        assert_eq!(report.values["非流动资产"], 7000.0);
+    }
+
+    #[test]
+    fn test_tolerance() {
+        let report = verify(&balance_sheet(), &options().with_tolerance(Tolerance::Absolute(1.0)));
+        assert!(report.checks.iter().all(|check| check.check.passed));
+    }
+
+    #[test]
+    fn test_node_errors() {
+        let roots = vec![node("资产", Some("100"), vec![leaf("现金", "abc"), node("存货", None, Vec::new())])];
+        let report = verify(&roots, &options());
+        let errors: Vec<String> = report.errors.iter().map(|error| error.path.join(" → ")).collect();
+        assert_eq!(errors, vec!["资产 → 现金", "资产 → 存货"]);
+        assert!(report.errors[1].message.starts_with("缺少金额"));
+        // 子项金额不可用时不核对父项
+        assert!(report.checks.is_empty());
+        assert_eq!(report.values["资产"], 100.0);
+    }
+
+    #[test]
+    fn test_identities() {
+        let report = verify(&balance_sheet(), &options());
+        let identities = default_identities(&report.values);
+        assert_eq!(identities, vec!["{资产} = {负债} + {所有者权益}"]);
+        let check = check_identity(&identities[0], &options(), &report).unwrap();
+        assert_eq!((check.actual, check.passed), (10000.0, true));
+
+        let check = check_identity("{流动资产} >= {负债}", &options(), &report).unwrap();
+        assert!(!check.passed);
+        assert!(check_identity("{资产}", &options(), &report).is_err());
+        assert!(check_identity("{资产} = {权益}", &options(), &report).unwrap_err().contains("权益"));
+        assert!(default_identities(&Variables::new()).is_empty());
+    }
+
+    #[test]
+    fn test_duplicate_labels() {
+        let roots = vec![
+            node("资产", None, vec![
+                node("流动资产", None, vec![leaf("货币资金", "100"), leaf("其他", "10")]),
+                node("非流动资产", None, vec![leaf("其他", "20")]),
+            ]),
+        ];
+        let report = verify(&roots, &options());
+        assert!(!report.values.contains_key("其他"));
+        assert_eq!(report.values["资产 → 非流动资产 → 其他"], 20.0);
+        assert_eq!(
+            check_identity("{其他} = 10", &options(), &report).unwrap_err(),
+            "引用有歧义: 项目 '其他' 出现在多处，请用完整路径引用：{资产 → 流动资产 → 其他}、{资产 → 非流动资产 → 其他}"
+        );
+        let check = check_identity("{资产 → 流动资产 → 其他} + {货币资金} = 110", &options(), &report).unwrap();
+        assert!(check.passed);
+    }
+
+    #[test]
+    fn test_validate() {
+        let (text, output) = validate(&balance_sheet(), None, &options());
+        assert!(text.starts_with("报表验证发现不一致：3 个小计/合计中 1 个不符，1 个恒等式中 0 个不成立\n小数位数: 2，容差: 精确匹配\n"));
+        assert!(text.contains("资产 → 流动资产: 3000 ≠ 子项之和 2999 (差异 -1（允许 ±0，超出 1）)"));
+        assert!(text.contains("    子项: 货币资金 1000 + 应收账款 1999"));
+        assert_eq!((output.checked, output.failed, output.all_passed), (4, 1, false));
+
+        let identities = ["{流动资产} >= {负债}".to_string()];
+        let (_, output) = validate(&balance_sheet(), Some(&identities), &options().with_tolerance(Tolerance::Absolute(1.0)));
+        assert_eq!(output.identities.len(), 1);
+        assert_eq!((output.failed, output.identities[0].actual), (1, Some(3000.0)));
+    }
+
+    #[test]
+    fn test_infer_decimals() {
+        assert_eq!(infer_statement_decimals(&balance_sheet(), NumberFormat::EnUs), 3);
+        assert_eq!(infer_statement_decimals(&[leaf("现金", "100")], NumberFormat::EnUs), 0);
+    }
+
+    #[test]
+    fn test_schema() {
+        let schema = StatementNode::json_schema();
+        assert_eq!(schema["required"], serde_json::json!(["label"]));
+        assert_eq!(schema["properties"]["children"]["type"], "array");
+    }
+}
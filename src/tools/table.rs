@@ -0,0 +1,371 @@
+//! # 表格交叉核对
+//!
+//! 报表附注中的表格是二维的：分部 × 期间、资产类别 × 变动项目。表格中指定一行合计行和一列合计列，
+//! 交叉核对（cross-footing）要求：
+//!
+//! - 每一行的明细单元格之和等于该行的合计列（横向）；
+//! - 每一列的明细单元格之和等于该列的合计行（纵向）；
+//! - 合计行与合计列交叉处的总计两个方向都要核对相符。
+//!
+//! 单元格按数字字面量解析，不接受表达式，`(1,234)` 表示负数，空单元格和 `-`、`—` 表示零。求和沿用 `calculate` 的舍入规则：
+//! 单元格先按小数位数舍入再相加，和再舍入一次。
+
+use std::fmt;
+
+use rust_mcp_sdk::macros::JsonSchema;
+
+use crate::tools::calculator::{parse_amount_cell, CalcOptions};
+use crate::tools::number_format::NumberFormat;
+use crate::tools::output::{report_text, signed_terms, CrossfootOutput, FootingResult, Settings, StatementChild, TableCellError};
+use crate::tools::precision::infer_decimals;
+use crate::tools::tolerance::ToleranceCheck;
+
+/// 表格的一行
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, JsonSchema)]
+pub struct TableRow {
+    /// 行标签，例如 华东
+    pub label: String,
+    /// 按列的顺序排列的单元格，例如 1,234.56、(200.00)、-
+    pub cells: Vec<String>,
+}
+
+/// 核对的方向
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Axis {
+    /// 一行的明细之和等于该行的合计列
+    Row,
+    /// 一列的明细之和等于该列的合计行
+    Column,
+}
+
+/// 一个合计单元格的核对结果
+#[derive(Debug, Clone, PartialEq)]
+pub struct Footing {
+    pub axis: Axis,
+    /// 合计单元格所在的行和列
+    pub row: String,
+    pub column: String,
+    /// 参与求和的明细单元格：另一方向的标签和舍入后的值
+    pub details: Vec<(String, f64)>,
+    /// 表格中的合计（舍入后）
+    pub reported: f64,
+    /// 明细之和
+    pub sum: f64,
+    pub check: ToleranceCheck,
+}
+
+impl Footing {
+    /// 是否为总计（合计行与合计列交叉处）
+    pub fn is_grand_total(&self, table: &TableLayout) -> bool {
+        self.row == table.total_row_label() && self.column == table.total_column_label()
+    }
+}
+
+/// 无法解析的单元格
+#[derive(Debug, Clone, PartialEq)]
+pub struct CellError {
+    pub row: String,
+    pub column: String,
+    pub message: String,
+}
+
+/// 表格的结构：列标签、行和合计的位置
+#[derive(Debug, Clone)]
+pub struct TableLayout<'a> {
+    pub columns: &'a [String],
+    pub rows: &'a [TableRow],
+    pub total_row: usize,
+    pub total_column: usize,
+}
+
+impl<'a> TableLayout<'a> {
+    /// 检查表格的形状并确定合计的位置；未指定时合计为最后一行和最后一列
+    pub fn new(columns: &'a [String], rows: &'a [TableRow], total_row: Option<&str>, total_column: Option<&str>) -> Result<Self, String> {
+        if rows.len() < 2 || columns.len() < 2 {
+            return Err("表格至少需要两行两列（明细和合计）".to_string());
+        }
+        if let Some(row) = rows.iter().find(|row| row.cells.len() != columns.len()) {
+            return Err(format!("行 '{}' 有 {} 个单元格，与列数 {} 不一致", row.label, row.cells.len(), columns.len()));
+        }
+        let find = |labels: Vec<&str>, label: Option<&str>, kind: &str| match label.map(str::trim) {
+            Some(label) => labels.iter().position(|candidate| candidate.trim() == label).ok_or_else(|| format!("找不到合计{} '{}'", kind, label)),
+            None => Ok(labels.len() - 1),
+        };
+        let total_row = find(rows.iter().map(|row| row.label.as_str()).collect(), total_row, "行")?;
+        let total_column = find(columns.iter().map(String::as_str).collect(), total_column, "列")?;
+        Ok(TableLayout { columns, rows, total_row, total_column })
+    }
+
+    pub fn total_row_label(&self) -> &str {
+        self.rows[self.total_row].label.trim()
+    }
+
+    pub fn total_column_label(&self) -> &str {
+        self.columns[self.total_column].trim()
+    }
+}
+
+/// 交叉核对的结果
+#[derive(Debug, Clone, Default)]
+pub struct CrossfootReport {
+    pub footings: Vec<Footing>,
+    pub errors: Vec<CellError>,
+}
+
+/// 解析一个单元格，写法见 `parse_amount_cell`；空单元格表示零
+pub fn parse_cell(cell: &str, options: &CalcOptions) -> Result<f64, String> {
+    parse_amount_cell(cell, options)
+        .map(|value| value.unwrap_or(0.0))
+        .map_err(|message| format!("无效的单元格 '{}': {}", cell.trim(), message))
+}
+
+/// 交叉核对表格并汇总不符的合计单元格
+pub fn validate(table: &TableLayout, options: &CalcOptions) -> (String, CrossfootOutput) {
+    let report = crossfoot(table, options);
+    let mut lines = Vec::new();
+    let mut footings = Vec::new();
+    for footing in &report.footings {
+        let cell = if footing.is_grand_total(table) {
+            format!("总计 {} × {}（{}）", footing.row, footing.column, footing.axis)
+        } else {
+            match footing.axis {
+                Axis::Row => format!("行 {} × 合计列 {}", footing.row, footing.column),
+                Axis::Column => format!("列 {} × 合计行 {}", footing.column, footing.row),
+            }
+        };
+        if !footing.check.passed {
+            lines.push(format!("{}: {} ≠ {}之和 {} (差异 {})", cell, footing.reported, footing.axis, footing.sum, footing.check));
+            lines.push(format!("    明细: {}", signed_terms(&footing.details)));
+        } else if footing.is_grand_total(table) {
+            lines.push(format!("{}: {} = {}之和 {} (通过)", cell, footing.reported, footing.axis, footing.sum));
+        }
+        footings.push(FootingResult {
+            axis: footing.axis.name().to_string(),
+            row: footing.row.clone(),
+            column: footing.column.clone(),
+            reported: footing.reported,
+            detail_sum: footing.sum,
+            difference: footing.check.difference,
+            allowed: footing.check.allowed,
+            passed: footing.check.passed,
+            details: footing.details.iter().map(|(label, amount)| StatementChild { label: label.clone(), amount: *amount }).collect(),
+        });
+    }
+    for error in &report.errors {
+        lines.push(format!("行 {} × 列 {}: {}", error.row, error.column, error.message.replace('\n', "\n    ")));
+    }
+
+    let failed = footings.iter().filter(|footing| !footing.passed).count() as u32;
+    let all_passed = failed == 0 && report.errors.is_empty();
+    let (rows, columns) = (table.rows.len() - 1, table.columns.len() - 1);
+    let summary = if all_passed {
+        format!("交叉核对完成！{} 行明细、{} 列明细和总计全部相符", rows, columns)
+    } else {
+        let count = |axis: Axis| report.footings.iter().filter(|footing| footing.axis == axis && !footing.check.passed).count();
+        let mut parts = vec![format!("横向 {} 处不符", count(Axis::Row)), format!("纵向 {} 处不符", count(Axis::Column))];
+        if !report.errors.is_empty() {
+            parts.push(format!("{} 个单元格无法解析", report.errors.len()));
+        }
+        format!("交叉核对发现不一致：{}", parts.join("，"))
+    };
+    (report_text(summary, options, lines), CrossfootOutput {
+        checked: footings.len() as u32,
+        failed,
+        all_passed,
+        footings,
+        errors: report.errors.iter().map(|error| TableCellError { row: error.row.clone(), column: error.column.clone(), error: error.message.clone() }).collect(),
+        settings: Settings::new(options),
+    })
+}
+
+/// 核对每一行、每一列和总计
+pub fn crossfoot(table: &TableLayout, options: &CalcOptions) -> CrossfootReport {
+    let mut report = CrossfootReport::default();
+    let mut cells = Vec::with_capacity(table.rows.len());
+    for row in table.rows {
+        let mut values = Vec::with_capacity(row.cells.len());
+        for (column, cell) in table.columns.iter().zip(&row.cells) {
+            let value = parse_cell(cell, options);
+            if let Err(message) = &value {
+                report.errors.push(CellError { row: row.label.trim().to_string(), column: column.trim().to_string(), message: message.clone() });
+            }
+            values.push(value.ok());
+        }
+        cells.push(values);
+    }
+
+    let footing = |axis: Axis, row: usize, column: usize, details: Vec<(String, Option<f64>)>| {
+        // 有无法解析的单元格时不核对
+        let reported = cells[row][column]?;
+        let details: Vec<(String, f64)> = details.into_iter().map(|(label, value)| Some((label, value?))).collect::<Option<_>>()?;
+        let sum = options.round(details.iter().map(|(_, value)| value).sum());
+        Some(Footing {
+            axis,
+            row: table.rows[row].label.trim().to_string(),
+            column: table.columns[column].trim().to_string(),
+            details,
+            reported,
+            sum,
+            check: options.tolerance.check(sum, reported, options.decimals),
+        })
+    };
+
+    // 横向：每一行（包括合计行）的明细列之和等于合计列
+    for (row, values) in cells.iter().enumerate() {
+        let details = table.columns.iter().zip(values).enumerate()
+            .filter(|&(column, _)| column != table.total_column)
+            .map(|(_, (label, value))| (label.trim().to_string(), *value))
+            .collect();
+        report.footings.extend(footing(Axis::Row, row, table.total_column, details));
+    }
+    // 纵向：每一列（包括合计列）的明细行之和等于合计行
+    for column in 0..table.columns.len() {
+        let details = table.rows.iter().zip(&cells).enumerate()
+            .filter(|&(row, _)| row != table.total_row)
+            .map(|(_, (row, values))| (row.label.trim().to_string(), values[column]))
+            .collect();
+        report.footings.extend(footing(Axis::Column, table.total_row, column, details));
+    }
+    report
+}
+
+/// 所有单元格中最多的小数位数，用于 `decimals` 为 auto 时
+pub fn infer_table_decimals(rows: &[TableRow], number_format: NumberFormat) -> u32 {
+    rows.iter()
+        .flat_map(|row| &row.cells)
+        .map(|cell| infer_decimals(cell.trim().trim_start_matches('(').trim_end_matches(')'), number_format))
+        .max()
+        .unwrap_or(0)
+}
+
+impl Axis {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Axis::Row => "row",
+            Axis::Column => "column",
+        }
+    }
+}
+
+impl fmt::Display for Axis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Axis::Row => write!(f, "横向"),
+            Axis::Column => write!(f, "纵向"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::calculator::PercentRounding;
+    use crate::tools::tolerance::Tolerance;
+
+    fn options() -> CalcOptions {
+        CalcOptions::new(2, PercentRounding::RoundThenDivideBy100).with_number_format(NumberFormat::EnUs)
+    }
+
+    fn row(label: &str, cells: &[&str]) -> TableRow {
+        TableRow { label: label.to_string(), cells: cells.iter().map(|cell| cell.to_string()).collect() }
+    }
+
+    fn columns() -> Vec<String> {
+        ["2023", "2024", "合计"].iter().map(|column| column.to_string()).collect()
+    }
+
+    /// 华南的横向之和为 700，合计列的纵向之和为 3,199
+    fn regions() -> Vec<TableRow> {
+        vec![
+            row("华东", &["1,000.00", "1,500.00", "2,500.00"]),
+            row("华南", &["800.00", "(100.00)", "699.00"]),
+            row("合计", &["1,800.00", "1,400.00", "3,200.00"]),
+        ]
+    }
+
+    #[test]
+    fn test_parse_cell() {
+        assert_eq!(parse_cell("1,234.567", &options()), Ok(1234.57));
+        assert_eq!(parse_cell("(1,234.00)", &options()), Ok(-1234.0));
+        assert_eq!(parse_cell(" - ", &options()), Ok(0.0));
+        assert_eq!(parse_cell("", &options()), Ok(0.0));
+        assert!(parse_cell("abc", &options()).unwrap_err().starts_with("无效的单元格 'abc'"));
+        // 单元格只能是数字，括号中的表达式不表示负数
+        assert_eq!(parse_cell("1,000 + 5", &options()), Err("无效的单元格 '1,000 + 5': 只能是一个数字，不能是表达式".to_string()));
+        assert_eq!(parse_cell("(1+2)", &options()), Err("无效的单元格 '(1+2)': 只能是一个数字，不能是表达式".to_string()));
+        assert_eq!(parse_cell("-5", &options()), Ok(-5.0));
+    }
+
+    #[test]
+    fn test_crossfoot() {
+        let (columns, rows) = (columns(), regions());
+        let table = TableLayout::new(&columns, &rows, None, None).unwrap();
+        let report = crossfoot(&table, &options());
+        assert!(report.errors.is_empty());
+        assert_eq!(report.footings.len(), 6);
+
+        let failed: Vec<(Axis, &str, &str)> = report
+            .footings
+            .iter()
+            .filter(|footing| !footing.check.passed)
+            .map(|footing| (footing.axis, footing.row.as_str(), footing.column.as_str()))
+            .collect();
+        // 华南横向之和为 700；合计列纵向之和为 3,199；总计横向 3,200 相符
+        assert_eq!(failed, vec![(Axis::Row, "华南", "合计"), (Axis::Column, "合计", "合计")]);
+        let grand: Vec<&Footing> = report.footings.iter().filter(|footing| footing.is_grand_total(&table)).collect();
+        assert_eq!(grand.len(), 2);
+        assert_eq!(grand[1].sum, 3199.0);
+        assert_eq!(report.footings[1].details, vec![("2023".to_string(), 800.0), ("2024".to_string(), -100.0)]);
+
+        let report = crossfoot(&table, &options().with_tolerance(Tolerance::Absolute(1.0)));
+        assert!(report.footings.iter().all(|footing| footing.check.passed));
+    }
+
+    #[test]
+    fn test_validate() {
+        let (columns, rows) = (columns(), regions());
+        let table = TableLayout::new(&columns, &rows, None, None).unwrap();
+        let (text, output) = validate(&table, &options());
+        assert_eq!(
+            text,
+            "交叉核对发现不一致：横向 1 处不符，纵向 1 处不符\n小数位数: 2，容差: 精确匹配\n\n\
+             行 华南 × 合计列 合计: 699 ≠ 横向之和 700 (差异 1（允许 ±0，超出 1）)\n    明细: 2023 800 - 2024 100\n\
+             总计 合计 × 合计（横向）: 3200 = 横向之和 3200 (通过)\n\
+             总计 合计 × 合计（纵向）: 3200 ≠ 纵向之和 3199 (差异 -1（允许 ±0，超出 1）)\n    明细: 华东 2500 + 华南 699"
+        );
+        assert_eq!((output.checked, output.failed, output.all_passed), (6, 2, false));
+    }
+
+    #[test]
+    fn test_layout() {
+        let columns = columns();
+        let rows = vec![row("合计", &["3", "4", "7"]), row("A", &["1", "1", "2"]), row("B", &["2", "3", "5"])];
+        let table = TableLayout::new(&columns, &rows, Some("合计"), Some(" 合计 ")).unwrap();
+        assert_eq!((table.total_row, table.total_column), (0, 2));
+        assert!(crossfoot(&table, &options()).footings.iter().all(|footing| footing.check.passed));
+
+        assert!(TableLayout::new(&columns, &rows, Some("总计"), None).unwrap_err().contains("总计"));
+        assert!(TableLayout::new(&columns, &rows[..1], None, None).is_err());
+        let ragged = vec![row("A", &["1", "2"]), row("合计", &["1", "2", "3"])];
+        assert!(TableLayout::new(&columns, &ragged, None, None).unwrap_err().contains("'A'"));
+    }
+
+    #[test]
+    fn test_cell_errors() {
+        let columns = columns();
+        let rows = vec![row("A", &["1", "x", "3"]), row("B", &["1", "1", "2"]), row("合计", &["2", "3", "5"])];
+        let table = TableLayout::new(&columns, &rows, None, None).unwrap();
+        let report = crossfoot(&table, &options());
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!((report.errors[0].row.as_str(), report.errors[0].column.as_str()), ("A", "2024"));
+        // 行 A 和列 2024 不核对
+        assert_eq!(report.footings.len(), 4);
+        assert!(report.footings.iter().all(|footing| footing.row != "A" && footing.column != "2024"));
+    }
+
+    #[test]
+    fn test_infer_decimals() {
+        let rows = vec![row("A", &["(1.234)", "1.5", "-"])];
+        assert_eq!(infer_table_decimals(&rows, NumberFormat::EnUs), 3);
+    }
+}
@@ -0,0 +1,166 @@
+//! # 验证容差
+//!
+//! 默认情况下验证要求计算结果与预期值完全一致（只容忍浮点误差）。复核交叉合计时通常允许一定的差异，
+//! 容差可以写成三种形式：
+//!
+//! | 写法 | 含义 |
+//! |---|---|
+//! | `0.01` | 绝对容差：差异的绝对值不超过 0.01 |
+//! | `0.1%` | 相对容差：差异不超过预期值绝对值的 0.1% |
+//! | `2ulp` | 末位单位：差异不超过 2 个最后一位小数的单位，`decimals = 2` 时为 0.02 |
+
+use std::fmt;
+
+use crate::tools::calculator::round_value;
+
+/// 比较浮点数时始终容忍的误差
+const EPSILON: f64 = 1e-9;
+
+/// 差异在输出前保留的小数位数，用于去掉浮点运算的尾差
+const DIFFERENCE_DECIMALS: u32 = 10;
+
+/// 验证容差
+#[derive(Debug, PartialEq, Copy, Clone, Default)]
+pub enum Tolerance {
+    /// 精确匹配
+    #[default]
+    Exact,
+    /// 绝对容差
+    Absolute(f64),
+    /// 相对容差，以预期值的百分比表示
+    Relative(f64),
+    /// 最后一位小数的单位数
+    Ulp(u32),
+}
+
+/// 一次比较的结果
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct ToleranceCheck {
+    /// 比较的实际值，即计算结果本身
+    pub actual: f64,
+    /// 实际值减预期值
+    pub difference: f64,
+    /// 允许的最大差异（绝对值）
+    pub allowed: f64,
+    pub passed: bool,
+}
+
+impl Tolerance {
+    /// 支持的写法，用于错误提示
+    pub const SYNTAX: &'static str = "0.01（绝对容差）、0.1%（相对容差）、2ulp（末位单位）";
+
+    /// 从字符串解析容差，可以带 `±` 前缀；空字符串表示精确匹配
+    pub fn parse(input: &str) -> Option<Self> {
+        let input = input.trim();
+        let input = input.strip_prefix('±').unwrap_or(input).trim();
+        if input.is_empty() {
+            return Some(Tolerance::Exact);
+        }
+
+        let lower = input.to_ascii_lowercase();
+        if let Some(units) = lower.strip_suffix("ulp") {
+            return units.trim().parse::<u32>().ok().map(Tolerance::Ulp);
+        }
+        let (number, relative) = match input.strip_suffix('%') {
+            Some(number) => (number.trim(), true),
+            None => (input, false),
+        };
+        let value = number.parse::<f64>().ok().filter(|v| v.is_finite() && *v >= 0.0)?;
+        Some(if relative { Tolerance::Relative(value) } else { Tolerance::Absolute(value) })
+    }
+
+    /// 对给定的预期值允许的最大差异，`decimals` 用于计算末位单位
+    pub fn allowed(&self, expected: f64, decimals: u32) -> f64 {
+        match *self {
+            Tolerance::Exact => 0.0,
+            Tolerance::Absolute(value) => value,
+            Tolerance::Relative(percent) => expected.abs() * percent / 100.0,
+            Tolerance::Ulp(units) => units as f64 / 10f64.powi(decimals as i32),
+        }
+    }
+
+    /// 比较实际值与预期值
+    pub fn check(&self, actual: f64, expected: f64, decimals: u32) -> ToleranceCheck {
+        let difference = actual - expected;
+        let allowed = self.allowed(expected, decimals);
+        ToleranceCheck {
+            actual,
+            difference: round_value(difference, DIFFERENCE_DECIMALS),
+            allowed: round_value(allowed, DIFFERENCE_DECIMALS),
+            passed: difference.abs() <= allowed + EPSILON,
+        }
+    }
+}
+
+impl ToleranceCheck {
+    /// 通过时为距离容差上限的余量，失败时为超出容差的部分
+    pub fn margin(&self) -> f64 {
+        round_value((self.allowed - self.difference.abs()).abs(), DIFFERENCE_DECIMALS)
+    }
+}
+
+impl fmt::Display for Tolerance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Tolerance::Exact => write!(f, "精确匹配"),
+            Tolerance::Absolute(value) => write!(f, "±{}", value),
+            Tolerance::Relative(percent) => write!(f, "±{}%", percent),
+            Tolerance::Ulp(units) => write!(f, "±{} ulp", units),
+        }
+    }
+}
+
+impl fmt::Display for ToleranceCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.passed {
+            write!(f, "{}（允许 ±{}，余量 {}）", self.difference, self.allowed, self.margin())
+        } else {
+            write!(f, "{}（允许 ±{}，超出 {}）", self.difference, self.allowed, self.margin())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Tolerance::parse(""), Some(Tolerance::Exact));
+        assert_eq!(Tolerance::parse("0.01"), Some(Tolerance::Absolute(0.01)));
+        assert_eq!(Tolerance::parse("±0.01"), Some(Tolerance::Absolute(0.01)));
+        assert_eq!(Tolerance::parse("0.1%"), Some(Tolerance::Relative(0.1)));
+        assert_eq!(Tolerance::parse("2ulp"), Some(Tolerance::Ulp(2)));
+        assert_eq!(Tolerance::parse("1 ULP"), Some(Tolerance::Ulp(1)));
+        assert_eq!(Tolerance::parse("-0.01"), None);
+        assert_eq!(Tolerance::parse("1.5ulp"), None);
+        assert_eq!(Tolerance::parse("abc"), None);
+    }
+
+    #[test]
+    fn test_check() {
+        // 浮点误差不影响边界上的判断
+        let check = Tolerance::Absolute(0.01).check(13.57, 13.56, 2);
+        assert!(check.passed);
+        assert_eq!(check.difference, 0.01);
+        assert_eq!(check.margin(), 0.0);
+
+        let check = Tolerance::Absolute(0.01).check(100.0, 100.05, 2);
+        assert!(!check.passed);
+        assert_eq!(check.difference, -0.05);
+        assert_eq!(check.margin(), 0.04);
+        assert_eq!(check.to_string(), "-0.05（允许 ±0.01，超出 0.04）");
+
+        assert_eq!(Tolerance::Relative(0.1).allowed(-2000.0, 2), 2.0);
+        assert!(Tolerance::Relative(0.1).check(2001.5, 2000.0, 2).passed);
+        assert!(Tolerance::Ulp(2).check(1.02, 1.0, 2).passed);
+        assert!(!Tolerance::Ulp(2).check(1.03, 1.0, 2).passed);
+        assert!(Tolerance::Exact.check(0.1 + 0.2, 0.3, 2).passed);
+        assert!(!Tolerance::Exact.check(0.31, 0.3, 2).passed);
+
+        // 实际值按原样返回，不由舍入后的差异反推
+        let check = Tolerance::Exact.check(0.3, 0.1, 2);
+        assert_eq!(check.actual, 0.3);
+        assert_ne!(check.actual, 0.1 + check.difference);
+    }
+}
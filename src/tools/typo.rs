@@ -0,0 +1,196 @@
+//! # 输入错误定位
+//!
+//! 合计对不上时，差异往往来自某个数字的录入错误：相邻数字换位（差异可被 9 整除）、漏输一位数字、
+//! 正负号录反（差异为该数字的两倍）或小数点错位（相差 10 的整数次幂倍）。
+//!
+//! 本模块对失败算式中的每个操作数逐一尝试这些修正，重新计算，列出能使算式与预期值一致的修正。
+
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::tools::calculator::{evaluate_traced, CalcError, CalcOptions, Variables};
+use crate::tools::parser::parse;
+
+/// 录入错误的类型
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TypoKind {
+    /// 正负号录反
+    SignFlip,
+    /// 小数点错位
+    DecimalShift,
+    /// 相邻数字换位
+    Transposition,
+    /// 漏输一位数字
+    DroppedDigit,
+}
+
+/// 一条修正建议
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct TypoSuggestion {
+    /// 数字操作数在算式中的序号，从 1 开始；引用的变量不计
+    pub operand: usize,
+    /// 操作数的原始文本（含百分号）
+    pub text: String,
+    pub label: Option<String>,
+    pub kind: TypoKind,
+    /// 修正后的数字，与原始文本同一单位（百分数不除以 100）
+    pub corrected: f64,
+}
+
+/// 差异本身提示的录入错误，不依赖具体的操作数
+pub fn difference_hints(difference: f64, decimals: u32) -> Vec<String> {
+    let units = (difference * 10f64.powi(decimals as i32)).round();
+    let mut hints = Vec::new();
+    if units != 0.0 && units % 9.0 == 0.0 {
+        hints.push(format!("差异 {} 可被 9 整除（按末位单位计），可能存在数字换位", difference));
+    }
+    hints
+}
+
+/// 找出能使算式与预期值一致的单个操作数修正；算式可以引用 `variables` 中的变量，变量本身不作修正
+pub fn suggest_in(expr: &str, expected: f64, options: &CalcOptions, variables: &Variables) -> Result<Vec<TypoSuggestion>, CalcError> {
+    let ast = parse(expr, options)?;
+    let literals: Vec<_> = ast.literals().into_iter().cloned().collect();
+
+    let mut suggestions = Vec::new();
+    for (index, literal) in literals.iter().enumerate() {
+        let mut tried = Vec::new();
+        for (kind, corrected) in candidates(literal.value) {
+            if corrected == literal.value || tried.contains(&corrected) {
+                continue;
+            }
+            tried.push(corrected);
+
+            let mut candidate = ast.clone();
+            candidate.literals_mut()[index].value = corrected;
+            let matches = evaluate_traced(&candidate, options, variables, None)
+                .is_ok_and(|actual| options.tolerance.check(options.round(actual), expected, options.decimals).passed);
+            if matches {
+                suggestions.push(TypoSuggestion {
+                    operand: index + 1,
+                    text: if literal.percent { format!("{}%", literal.text) } else { literal.text.clone() },
+                    label: literal.label.clone(),
+                    kind,
+                    corrected,
+                });
+            }
+        }
+    }
+    Ok(suggestions)
+}
+
+/// 按类型依次生成一个数字的候选修正值
+fn candidates(value: f64) -> Vec<(TypoKind, f64)> {
+    let mut candidates = vec![(TypoKind::SignFlip, -value)];
+    for factor in [10.0, 100.0, 1000.0] {
+        candidates.push((TypoKind::DecimalShift, value * factor));
+        candidates.push((TypoKind::DecimalShift, value / factor));
+    }
+
+    let text = format!("{}", value.abs());
+    let sign = value.signum();
+    let chars: Vec<char> = text.chars().collect();
+    // 相邻的两个数字交换，跳过小数点
+    let digits: Vec<usize> = (0..chars.len()).filter(|&i| chars[i].is_ascii_digit()).collect();
+    for pair in digits.windows(2) {
+        let mut swapped = chars.clone();
+        swapped.swap(pair[0], pair[1]);
+        if let Some(value) = parse_digits(&swapped) {
+            candidates.push((TypoKind::Transposition, sign * value));
+        }
+    }
+    // 在任意位置补上一位数字
+    for position in 0..=chars.len() {
+        for digit in '0'..='9' {
+            let mut inserted = chars.clone();
+            inserted.insert(position, digit);
+            if let Some(value) = parse_digits(&inserted) {
+                candidates.push((TypoKind::DroppedDigit, sign * value));
+            }
+        }
+    }
+    candidates
+}
+
+fn parse_digits(chars: &[char]) -> Option<f64> {
+    chars.iter().collect::<String>().parse::<f64>().ok()
+}
+
+impl fmt::Display for TypoKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypoKind::SignFlip => write!(f, "正负号录反"),
+            TypoKind::DecimalShift => write!(f, "小数点错位"),
+            TypoKind::Transposition => write!(f, "数字换位"),
+            TypoKind::DroppedDigit => write!(f, "漏输数字"),
+        }
+    }
+}
+
+impl fmt::Display for TypoSuggestion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "第 {} 个操作数 {}", self.operand, self.text)?;
+        if let Some(label) = &self.label {
+            write!(f, "[{}]", label)?;
+        }
+        write!(f, " 可能{}，应为 {}", self.kind, self.corrected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::calculator::PercentRounding;
+    use crate::tools::number_format::NumberFormat;
+
+    fn options() -> CalcOptions {
+        CalcOptions::new(2, PercentRounding::DivideBy100ThenRound).with_number_format(NumberFormat::EnUs)
+    }
+
+    fn found(expr: &str, expected: f64) -> Vec<(usize, TypoKind, f64)> {
+        suggest_in(expr, expected, &options(), &Variables::new())
+            .unwrap()
+            .into_iter()
+            .map(|s| (s.operand, s.kind, s.corrected))
+            .collect()
+    }
+
+    #[test]
+    fn test_transposition() {
+        // 1,540 录成了 1,450，差异 90 可被 9 整除
+        assert!(found("1,000 + 1,450 + 500", 3040.0).contains(&(2, TypoKind::Transposition, 1540.0)));
+        assert_eq!(difference_hints(-90.0, 2).len(), 1);
+        assert!(difference_hints(-100.0, 2).is_empty());
+    }
+
+    #[test]
+    fn test_sign_flip_and_decimal_shift() {
+        assert!(found("1,000 - 200 + 50", 1250.0).contains(&(2, TypoKind::SignFlip, -200.0)));
+        assert!(found("1,000 + 25 + 50", 1300.0).contains(&(2, TypoKind::DecimalShift, 250.0)));
+    }
+
+    #[test]
+    fn test_dropped_digit() {
+        let suggestions = suggest_in("1,000[现金] + 205[存货]", 3005.0, &options(), &Variables::new()).unwrap();
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!(suggestions[0].kind, TypoKind::DroppedDigit);
+        assert_eq!(suggestions[0].corrected, 2005.0);
+        assert_eq!(suggestions[0].to_string(), "第 2 个操作数 205[存货] 可能漏输数字，应为 2005");
+    }
+
+    #[test]
+    fn test_references() {
+        // 引用的变量参与求值，只修正算式中的数字
+        let variables = Variables::from([("X".to_string(), 1000.0)]);
+        let suggestions = suggest_in("{X} + 1,450 + 500", 3040.0, &options(), &variables).unwrap();
+        assert!(suggestions.iter().any(|s| (s.operand, s.kind, s.corrected) == (1, TypoKind::Transposition, 1540.0)));
+        assert_eq!(suggest_in("{X} + 1", 2.0, &options(), &Variables::new()), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn test_no_suggestion_for_unrelated_difference() {
+        assert!(found("1 + 2", 1234.0).is_empty());
+    }
+}
@@ -0,0 +1,913 @@
+//! # Excel 工作簿
+//!
+//! `recalculate_xlsx` 读取 .xlsx 文件中各工作表的单元格和公式，按 `calculate` 的舍入规则重新计算，
+//! 并列出文件中缓存的值与重新计算的结果不符的单元格。Excel 按二进制浮点数计算公式，不做预先舍入，
+//! 缓存的值因此可能与按规则舍入的报表数字相差若干个末位单位。
+//!
+//! .xlsx 是 zip 压缩包，工作表、共享字符串和样式各是其中的一个 XML 文件。支持的公式与 `calculate_grid`
+//! 相同：`+ - * /`、括号、百分号、`SUM`、`ROUND` 和单元格区域；共享公式按各单元格的位置平移其中的相对引用。
+//! 引用其他工作表、使用其他函数或比较、文本运算的公式列为不支持，不参与比较。
+//!
+//! 单元格的取值与 Excel 的 SUM 一致：空单元格为零，区域中的文本和逻辑值被忽略；直接引用文本或逻辑值时报错。
+//! 常量单元格按数字字面量的规则预先舍入，公式的结果按小数位数舍入后供其他公式引用。
+//! 设置了百分比格式的单元格按百分数处理：显示为 12.35% 的 0.12345 按 `percent_rounding` 舍入，
+//! 公式的结果和比较都以百分点为单位。
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{Read, Seek};
+
+use quick_xml::escape::resolve_predefined_entity;
+use quick_xml::events::{BytesRef, BytesStart, Event};
+use quick_xml::{Reader, XmlVersion};
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+use crate::tools::ast::{CellReference, Expr, NumberLiteral};
+use crate::tools::calculator::{evaluate_traced, literal_value, CalcOptions, Variables};
+use crate::tools::cell::{column_name, CellRange, CellRef};
+use crate::tools::diagnostic::Span;
+use crate::tools::number_format::NumberFormat;
+use crate::tools::output::{CellDifference, FormulaError, RecalculateOutput, Settings};
+use crate::tools::parser::parse;
+use crate::tools::tolerance::ToleranceCheck;
+
+/// 内置的百分比数字格式（`0%` 和 `0.00%`）
+const PERCENT_FORMATS: [u32; 2] = [9, 10];
+
+/// 压缩包中单个文件解压后的最大字节数；很小的文件可能解压出数 GB 的内容（zip 炸弹），超过时不再读取
+const MAX_ENTRY_BYTES: u64 = 256 * 1024 * 1024;
+
+/// 单元格中的值
+#[derive(Debug, Clone, PartialEq)]
+pub enum CellValue {
+    Empty,
+    Number(f64),
+    Text(String),
+    Bool(bool),
+    /// 错误值，例如 `#DIV/0!`
+    Error(String),
+}
+
+/// 一个单元格
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cell {
+    /// 公式，不含开头的 `=`；共享公式已按单元格的位置平移
+    pub formula: Option<String>,
+    /// 文件中的值，公式单元格为缓存的计算结果
+    pub value: CellValue,
+    /// 是否设置了百分比格式
+    pub percent: bool,
+}
+
+/// 一个工作表
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sheet {
+    pub name: String,
+    /// 键为 (行, 列)，按行优先的顺序排列
+    cells: BTreeMap<(u32, u32), Cell>,
+}
+
+impl Sheet {
+    pub fn new(name: impl Into<String>) -> Self {
+        Sheet { name: name.into(), cells: BTreeMap::new() }
+    }
+
+    pub fn insert(&mut self, cell: CellRef, value: Cell) {
+        self.cells.insert((cell.row, cell.column), value);
+    }
+
+    pub fn cell(&self, cell: CellRef) -> Option<&Cell> {
+        self.cells.get(&(cell.row, cell.column))
+    }
+
+    /// 区域中存在的单元格，按行优先的顺序；只访问有内容的单元格，不逐个枚举区域内的地址
+    pub fn cells_in(&self, range: CellRange) -> impl Iterator<Item = (CellRef, &Cell)> {
+        let columns = range.start.column..=range.end.column;
+        self.cells
+            .range((range.start.row, range.start.column)..=(range.end.row, range.end.column))
+            .filter(move |((_, column), _)| columns.contains(column))
+            .map(|(&(row, column), cell)| (CellRef::new(column, row), cell))
+    }
+
+    /// 公式单元格，按行优先的顺序
+    pub fn formulas(&self) -> impl Iterator<Item = (CellRef, &str)> {
+        self.cells.iter().filter_map(|(&(row, column), cell)| Some((CellRef::new(column, row), cell.formula.as_deref()?)))
+    }
+}
+
+// --- 读取工作簿 ---
+
+/// 读取工作簿中的全部工作表，按工作簿中的顺序排列
+pub fn read_workbook<R: Read + Seek>(reader: R) -> Result<Vec<Sheet>, String> {
+    let mut archive = ZipArchive::new(reader).map_err(|e| format!("不是有效的 .xlsx 文件: {}", e))?;
+    let workbook = read_entry(&mut archive, "xl/workbook.xml")?.ok_or("不是有效的 .xlsx 文件: 缺少 xl/workbook.xml")?;
+    let relationships = match read_entry(&mut archive, "xl/_rels/workbook.xml.rels")? {
+        Some(xml) => parse_relationships(&xml)?,
+        None => HashMap::new(),
+    };
+    let strings = match read_entry(&mut archive, "xl/sharedStrings.xml")? {
+        Some(xml) => parse_shared_strings(&xml)?,
+        None => Vec::new(),
+    };
+    let percent_styles = match read_entry(&mut archive, "xl/styles.xml")? {
+        Some(xml) => parse_percent_styles(&xml)?,
+        None => Vec::new(),
+    };
+
+    let mut sheets = Vec::new();
+    for (name, id) in parse_sheet_list(&workbook)? {
+        let target = relationships.get(&id).ok_or_else(|| format!("找不到工作表 '{}' 的文件", name))?;
+        // 目标路径相对于 xl/ 目录，以 `/` 开头时相对于压缩包的根
+        let path = match target.strip_prefix('/') {
+            Some(path) => path.to_string(),
+            None => format!("xl/{}", target),
+        };
+        let xml = read_entry(&mut archive, &path)?.ok_or_else(|| format!("找不到工作表 '{}' 的文件 {}", name, path))?;
+        sheets.push(parse_sheet(name, &xml, &strings, &percent_styles)?);
+    }
+    Ok(sheets)
+}
+
+/// 读取压缩包中的一个文件，不存在时返回 `None`
+fn read_entry<R: Read + Seek>(archive: &mut ZipArchive<R>, name: &str) -> Result<Option<String>, String> {
+    read_entry_with_limit(archive, name, MAX_ENTRY_BYTES)
+}
+
+/// 读取压缩包中的一个文件，解压后超过 `limit` 字节时报错；声明的大小不可信，读取时同样限制
+fn read_entry_with_limit<R: Read + Seek>(archive: &mut ZipArchive<R>, name: &str, limit: u64) -> Result<Option<String>, String> {
+    let entry = match archive.by_name(name) {
+        Ok(entry) => entry,
+        Err(ZipError::FileNotFound) => return Ok(None),
+        Err(e) => return Err(format!("无法读取 {}: {}", name, e)),
+    };
+    let too_large = || format!("无法读取 {}: 解压后超过 {} 字节的上限", name, limit);
+    if entry.size() > limit {
+        return Err(too_large());
+    }
+    let mut xml = String::with_capacity(entry.size() as usize);
+    entry.take(limit + 1).read_to_string(&mut xml).map_err(|e| format!("无法读取 {}: {}", name, e))?;
+    if xml.len() as u64 > limit {
+        return Err(too_large());
+    }
+    Ok(Some(xml))
+}
+
+fn xml_error(part: &str, error: impl std::fmt::Display) -> String {
+    format!("无法解析 {}: {}", part, error)
+}
+
+/// 按不含前缀的名称查找属性，例如 `r:id` 按 `id` 查找
+fn attribute(element: &BytesStart, name: &str) -> Option<String> {
+    element
+        .attributes()
+        .flatten()
+        .find(|attribute| attribute.key.local_name().as_ref() == name)
+        .and_then(|attribute| attribute.normalized_value(XmlVersion::Implicit1_0).ok().map(Cow::into_owned))
+}
+
+/// 文本中的实体引用，例如 `&amp;`、`&#x30;`
+fn entity(reference: &BytesRef) -> String {
+    match reference.resolve_char_ref() {
+        Ok(Some(c)) => c.to_string(),
+        _ => resolve_predefined_entity(reference).map_or_else(|| format!("&{};", &**reference), str::to_string),
+    }
+}
+
+/// 工作簿中的工作表：名称和关系 ID
+fn parse_sheet_list(xml: &str) -> Result<Vec<(String, String)>, String> {
+    let mut reader = Reader::from_str(xml);
+    let mut sheets = Vec::new();
+    loop {
+        match reader.read_event().map_err(|e| xml_error("xl/workbook.xml", e))? {
+            Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == "sheet" => {
+                if let (Some(name), Some(id)) = (attribute(&e, "name"), attribute(&e, "id")) {
+                    sheets.push((name, id));
+                }
+            }
+            Event::Eof => return Ok(sheets),
+            _ => {}
+        }
+    }
+}
+
+/// 关系 ID 对应的文件
+fn parse_relationships(xml: &str) -> Result<HashMap<String, String>, String> {
+    let mut reader = Reader::from_str(xml);
+    let mut relationships = HashMap::new();
+    loop {
+        match reader.read_event().map_err(|e| xml_error("xl/_rels/workbook.xml.rels", e))? {
+            Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == "Relationship" => {
+                if let (Some(id), Some(target)) = (attribute(&e, "Id"), attribute(&e, "Target")) {
+                    relationships.insert(id, target);
+                }
+            }
+            Event::Eof => return Ok(relationships),
+            _ => {}
+        }
+    }
+}
+
+/// 共享字符串表；富文本的各段合并，注音（`rPh`）忽略
+fn parse_shared_strings(xml: &str) -> Result<Vec<String>, String> {
+    let mut reader = Reader::from_str(xml);
+    let mut strings = Vec::new();
+    let mut text = String::new();
+    let (mut in_text, mut phonetic) = (false, false);
+    loop {
+        match reader.read_event().map_err(|e| xml_error("xl/sharedStrings.xml", e))? {
+            Event::Start(e) => match e.local_name().as_ref() {
+                "si" => text.clear(),
+                "t" => in_text = !phonetic,
+                "rPh" => phonetic = true,
+                _ => {}
+            },
+            Event::Empty(e) if e.local_name().as_ref() == "si" => strings.push(String::new()),
+            Event::End(e) => match e.local_name().as_ref() {
+                "si" => strings.push(std::mem::take(&mut text)),
+                "t" => in_text = false,
+                "rPh" => phonetic = false,
+                _ => {}
+            },
+            Event::Text(e) if in_text => text.push_str(&e.xml10_content()),
+            Event::GeneralRef(reference) if in_text => text.push_str(&entity(&reference)),
+            Event::Eof => return Ok(strings),
+            _ => {}
+        }
+    }
+}
+
+/// 各单元格样式（`cellXfs` 中的 `xf`）是否为百分比格式
+fn parse_percent_styles(xml: &str) -> Result<Vec<bool>, String> {
+    let mut reader = Reader::from_str(xml);
+    let mut custom = HashMap::new();
+    let mut styles = Vec::new();
+    let mut in_cell_xfs = false;
+    loop {
+        let (element, start) = match reader.read_event().map_err(|e| xml_error("xl/styles.xml", e))? {
+            Event::Start(e) => (e, true),
+            Event::Empty(e) => (e, false),
+            Event::End(e) => {
+                if e.local_name().as_ref() == "cellXfs" {
+                    in_cell_xfs = false;
+                }
+                continue;
+            }
+            Event::Eof => return Ok(styles),
+            _ => continue,
+        };
+        let format = || attribute(&element, "numFmtId").and_then(|id| id.parse::<u32>().ok());
+        match element.local_name().as_ref() {
+            "numFmt" => {
+                if let (Some(id), Some(code)) = (format(), attribute(&element, "formatCode")) {
+                    custom.insert(id, code.contains('%'));
+                }
+            }
+            "cellXfs" => in_cell_xfs = start,
+            "xf" if in_cell_xfs => {
+                let id = format().unwrap_or(0);
+                styles.push(PERCENT_FORMATS.contains(&id) || custom.get(&id) == Some(&true));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// 正在读取的单元格
+#[derive(Default)]
+struct PendingCell {
+    cell: Option<CellRef>,
+    kind: String,
+    style: usize,
+    formula: Option<String>,
+    /// 共享公式的编号
+    shared: Option<String>,
+    value: String,
+}
+
+/// 当前读取的文本属于哪个元素
+#[derive(PartialEq)]
+enum Capture {
+    None,
+    Formula,
+    Value,
+    Inline,
+}
+
+fn parse_sheet(name: String, xml: &str, strings: &[String], percent_styles: &[bool]) -> Result<Sheet, String> {
+    let part = format!("工作表 '{}'", name);
+    let mut sheet = Sheet::new(name);
+    let mut reader = Reader::from_str(xml);
+    let (mut row, mut column) = (0, 0);
+    let mut pending = PendingCell::default();
+    let mut capture = Capture::None;
+    let mut text = String::new();
+    // 共享公式的首个单元格及其公式，其余单元格在读完后平移
+    let mut masters: HashMap<String, (CellRef, String)> = HashMap::new();
+    let mut followers = Vec::new();
+
+    loop {
+        let event = reader.read_event().map_err(|e| xml_error(&part, e))?;
+        let (element, empty) = match &event {
+            Event::Start(e) => (e, false),
+            Event::Empty(e) => (e, true),
+            Event::End(e) => {
+                match e.local_name().as_ref() {
+                    "f" => finish_formula(&mut pending, std::mem::take(&mut text), &mut masters),
+                    "v" => pending.value = std::mem::take(&mut text),
+                    "is" => pending.value = std::mem::take(&mut text),
+                    "c" => {
+                        let pending = std::mem::take(&mut pending);
+                        finish_cell(&mut sheet, pending, strings, percent_styles, &mut followers)?;
+                    }
+                    _ => {}
+                }
+                capture = Capture::None;
+                continue;
+            }
+            Event::Text(e) => {
+                if capture != Capture::None {
+                    text.push_str(&e.xml10_content());
+                }
+                continue;
+            }
+            Event::GeneralRef(reference) => {
+                if capture != Capture::None {
+                    text.push_str(&entity(reference));
+                }
+                continue;
+            }
+            Event::Eof => break,
+            _ => continue,
+        };
+        match element.local_name().as_ref() {
+            "row" => {
+                row = attribute(element, "r").and_then(|r| r.parse().ok()).unwrap_or(row + 1);
+                column = 0;
+            }
+            "c" => {
+                // 省略地址的单元格紧接在前一个单元格之后
+                let cell = attribute(element, "r").and_then(|r| CellRef::parse(&r)).unwrap_or(CellRef::new(column + 1, row));
+                (row, column) = (cell.row, cell.column);
+                pending = PendingCell {
+                    cell: Some(cell),
+                    kind: attribute(element, "t").unwrap_or_default(),
+                    style: attribute(element, "s").and_then(|s| s.parse().ok()).unwrap_or(0),
+                    ..PendingCell::default()
+                };
+                if empty {
+                    finish_cell(&mut sheet, std::mem::take(&mut pending), strings, percent_styles, &mut followers)?;
+                }
+            }
+            "f" => {
+                pending.shared = attribute(element, "si").filter(|_| attribute(element, "t").as_deref() == Some("shared"));
+                text.clear();
+                if empty {
+                    finish_formula(&mut pending, String::new(), &mut masters);
+                } else {
+                    capture = Capture::Formula;
+                }
+            }
+            "v" if !empty => {
+                text.clear();
+                capture = Capture::Value;
+            }
+            "is" => text.clear(),
+            "t" if !empty && pending.kind == "inlineStr" => capture = Capture::Inline,
+            _ => {}
+        }
+    }
+
+    for (cell, index) in followers {
+        let Some((anchor, formula)) = masters.get(&index) else { continue };
+        let shifted = shift_formula(formula, i64::from(cell.row) - i64::from(anchor.row), i64::from(cell.column) - i64::from(anchor.column));
+        if let Some(entry) = sheet.cells.get_mut(&(cell.row, cell.column)) {
+            entry.formula = Some(shifted);
+        }
+    }
+    Ok(sheet)
+}
+
+/// 读完 `<f>` 元素：共享公式的首个单元格记下公式，其余单元格留待平移
+fn finish_formula(pending: &mut PendingCell, formula: String, masters: &mut HashMap<String, (CellRef, String)>) {
+    if let (Some(index), Some(cell)) = (&pending.shared, pending.cell) {
+        if !formula.is_empty() {
+            masters.insert(index.clone(), (cell, formula.clone()));
+        }
+    }
+    if !formula.is_empty() {
+        pending.formula = Some(formula);
+    }
+}
+
+fn finish_cell(
+    sheet: &mut Sheet,
+    pending: PendingCell,
+    strings: &[String],
+    percent_styles: &[bool],
+    followers: &mut Vec<(CellRef, String)>,
+) -> Result<(), String> {
+    let Some(cell) = pending.cell else { return Ok(()) };
+    let value = match pending.kind.as_str() {
+        "s" => {
+            let text = pending.value.trim().parse::<usize>().ok().and_then(|index| strings.get(index));
+            CellValue::Text(text.ok_or_else(|| format!("单元格 {} 引用了不存在的共享字符串 {}", cell, pending.value))?.clone())
+        }
+        "str" | "inlineStr" | "d" => CellValue::Text(pending.value),
+        "b" => CellValue::Bool(pending.value.trim() == "1"),
+        "e" => CellValue::Error(pending.value),
+        _ if pending.value.trim().is_empty() => CellValue::Empty,
+        _ => CellValue::Number(pending.value.trim().parse().map_err(|_| format!("单元格 {} 的值无法解析: '{}'", cell, pending.value))?),
+    };
+    if pending.formula.is_none() {
+        if let Some(index) = pending.shared {
+            followers.push((cell, index));
+        }
+    }
+    let percent = percent_styles.get(pending.style).copied().unwrap_or(false);
+    sheet.insert(cell, Cell { formula: pending.formula, value, percent });
+    Ok(())
+}
+
+// --- 公式 ---
+
+/// 按位置平移共享公式中的相对引用，`$` 标记的行或列不变；字符串和函数名原样保留
+pub fn shift_formula(formula: &str, rows: i64, columns: i64) -> String {
+    let chars: Vec<char> = formula.chars().collect();
+    let is_word = |c: char| c.is_alphanumeric() || c == '$' || c == '_' || c == '.';
+    let mut shifted = String::with_capacity(formula.len());
+    let mut pos = 0;
+    while pos < chars.len() {
+        let c = chars[pos];
+        let end = if c == '"' {
+            chars[pos + 1..].iter().position(|&c| c == '"').map_or(chars.len(), |offset| pos + offset + 2)
+        } else if is_word(c) {
+            chars[pos..].iter().position(|&c| !is_word(c)).map_or(chars.len(), |offset| pos + offset)
+        } else {
+            pos + 1
+        };
+        let token: String = chars[pos..end].iter().collect();
+        match shift_reference(&token, rows, columns).filter(|_| chars.get(end) != Some(&'(')) {
+            Some(reference) => shifted.push_str(&reference),
+            None => shifted.push_str(&token),
+        }
+        pos = end;
+    }
+    shifted
+}
+
+/// 平移一个单元格地址，不是地址或平移到表格之外时返回 `None`
+fn shift_reference(token: &str, rows: i64, columns: i64) -> Option<String> {
+    let cell = CellRef::parse(token)?;
+    let (column_part, row_part) = token.split_at(token.rfind(|c: char| c.is_ascii_alphabetic())? + 1);
+    let (absolute_column, absolute_row) = (column_part.starts_with('$'), row_part.starts_with('$'));
+    let column = i64::from(cell.column) + if absolute_column { 0 } else { columns };
+    let row = i64::from(cell.row) + if absolute_row { 0 } else { rows };
+    let shifted = CellRef::new(u32::try_from(column).ok().filter(|&c| c > 0)?, u32::try_from(row).ok().filter(|&r| r > 0)?);
+    let dollar = |absolute: bool| if absolute { "$" } else { "" };
+    Some(format!("{}{}{}{}", dollar(absolute_column), column_name(shifted.column), dollar(absolute_row), shifted.row))
+}
+
+/// 把 Excel 公式转换为计算器的表达式：去掉开头的 `=`，函数参数之间的 `,` 后加空格，以免被当作千分位
+pub fn translate(formula: &str) -> String {
+    let formula = formula.trim();
+    let formula = formula.strip_prefix('=').unwrap_or(formula);
+    let mut translated = String::with_capacity(formula.len());
+    let mut quoted = false;
+    for c in formula.chars() {
+        quoted ^= c == '"';
+        translated.push(c);
+        if c == ',' && !quoted {
+            translated.push(' ');
+        }
+    }
+    translated
+}
+
+// --- 重新计算 ---
+
+/// 一个公式单元格的重新计算结果
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recalculated {
+    pub cell: CellRef,
+    /// 转换后的公式
+    pub formula: String,
+    /// 文件中缓存的值，不是数字时为 `None`
+    pub cached: Option<f64>,
+    pub percent: bool,
+    /// 重新计算的结果；公式不受支持或无法计算时为错误
+    pub result: Result<f64, String>,
+}
+
+impl Recalculated {
+    /// 把缓存的值按相同的规则舍入后与重新计算的结果比较；百分比格式的单元格以百分点为单位。
+    /// 没有缓存的数值或无法计算时为 `None`
+    pub fn check(&self, options: &CalcOptions) -> Option<ToleranceCheck> {
+        let (cached, result) = (self.cached?, *self.result.as_ref().ok()?);
+        let scale = if self.percent { 100.0 } else { 1.0 };
+        Some(options.tolerance.check(result * scale, options.round(cached * scale), options.decimals))
+    }
+}
+
+/// 重新计算工作表中的全部公式，按行优先的顺序返回；公式中的数字按 Excel 的写法（`1234.5`）解析
+pub fn recalculate(sheet: &Sheet, options: &CalcOptions) -> Vec<Recalculated> {
+    let options = options.with_number_format(NumberFormat::EnUs);
+    let mut recalculation = Recalculation { sheet, options, parsed: HashMap::new(), results: HashMap::new() };
+    let cells: Vec<CellRef> = sheet.formulas().map(|(cell, _)| cell).collect();
+    cells
+        .into_iter()
+        .map(|cell| {
+            recalculation.evaluate(cell);
+            let entry = &sheet.cells[&(cell.row, cell.column)];
+            Recalculated {
+                cell,
+                formula: translate(entry.formula.as_deref().unwrap_or_default()),
+                cached: match entry.value {
+                    CellValue::Number(value) => Some(value),
+                    _ => None,
+                },
+                percent: entry.percent,
+                result: recalculation.results[&cell].clone(),
+            }
+        })
+        .collect()
+}
+
+/// 重新计算工作簿中的公式并汇总不符的单元格
+pub fn recalculate_workbook<R: Read + Seek>(reader: R, sheet: Option<&str>, options: &CalcOptions) -> Result<(String, RecalculateOutput), String> {
+    let mut sheets = read_workbook(reader)?;
+    if let Some(name) = sheet {
+        let names: Vec<&str> = sheets.iter().map(|sheet| sheet.name.as_str()).collect();
+        if !names.contains(&name) {
+            return Err(format!("工作簿中没有名为 '{}' 的工作表，现有的工作表：{}", name, names.join("、")));
+        }
+        sheets.retain(|sheet| sheet.name == name);
+    }
+
+    let mut output = RecalculateOutput {
+        sheets: sheets.iter().map(|sheet| sheet.name.clone()).collect(),
+        formulas: 0,
+        matched: 0,
+        uncached: 0,
+        differences: Vec::new(),
+        unsupported: Vec::new(),
+        all_matched: false,
+        settings: Settings::new(options),
+    };
+    let (mut differences, mut unsupported) = (Vec::new(), Vec::new());
+    for sheet in &sheets {
+        for recalculated in recalculate(sheet, options) {
+            output.formulas += 1;
+            let cell = recalculated.cell.to_string();
+            let recomputed = match &recalculated.result {
+                Ok(value) => *value,
+                Err(error) => {
+                    unsupported.push(format!("  {}!{}: {}", sheet.name, cell, error.replace('\n', "\n    ")));
+                    output.unsupported.push(FormulaError { sheet: sheet.name.clone(), cell, formula: recalculated.formula, error: error.clone() });
+                    continue;
+                }
+            };
+            match (recalculated.check(options), recalculated.cached) {
+                (Some(check), _) if check.passed => output.matched += 1,
+                (Some(check), Some(cached)) => {
+                    differences.push(format!("  {}!{}: {} 缓存 {}，重新计算 {} (差异 {})", sheet.name, cell, recalculated.formula, cached, recomputed, check));
+                    output.differences.push(CellDifference {
+                        sheet: sheet.name.clone(),
+                        cell,
+                        formula: recalculated.formula,
+                        cached,
+                        recomputed,
+                        difference: check.difference,
+                        allowed: check.allowed,
+                    });
+                }
+                _ => output.uncached += 1,
+            }
+        }
+    }
+    output.all_matched = output.differences.is_empty() && output.unsupported.is_empty();
+
+    let summary = if output.all_matched {
+        format!("重新计算完成！{} 个公式全部与缓存的值相符", output.formulas)
+    } else {
+        format!(
+            "重新计算完成：{} 个公式中 {} 个相符，{} 个不符，{} 个不受支持或无法计算",
+            output.formulas, output.matched, output.differences.len(), output.unsupported.len()
+        )
+    };
+    let mut text = vec![
+        summary,
+        format!("工作表: {}", output.sheets.join("、")),
+        format!("小数位数: {}，百分数: {}，容差: {}", options.decimals, options.percent_rounding.description(), options.tolerance),
+    ];
+    if output.uncached > 0 {
+        text.push(format!("另有 {} 个公式没有缓存的数值，只重新计算、不比较", output.uncached));
+    }
+    if !differences.is_empty() {
+        text.push("".to_string());
+        text.push("不符的单元格:".to_string());
+        text.extend(differences);
+    }
+    if !unsupported.is_empty() {
+        text.push("".to_string());
+        text.push("不受支持或无法计算的公式:".to_string());
+        text.extend(unsupported);
+    }
+    Ok((text.join("\n"), output))
+}
+
+struct Recalculation<'a> {
+    sheet: &'a Sheet,
+    options: CalcOptions,
+    parsed: HashMap<CellRef, Result<Expr, String>>,
+    results: HashMap<CellRef, Result<f64, String>>,
+}
+
+impl Recalculation<'_> {
+    fn is_formula(&self, cell: CellRef) -> bool {
+        self.sheet.cell(cell).is_some_and(|entry| entry.formula.is_some())
+    }
+
+    fn parsed(&mut self, cell: CellRef) -> &Result<Expr, String> {
+        let (sheet, options) = (self.sheet, &self.options);
+        self.parsed.entry(cell).or_insert_with(|| {
+            let formula = translate(sheet.cell(cell).and_then(|entry| entry.formula.as_deref()).unwrap_or_default());
+            parse(&formula, options).map_err(|e| format!("不支持的公式: {}", e.render(&formula)))
+        })
+    }
+
+    /// 公式直接或通过区域引用的其他公式单元格
+    fn dependencies(&mut self, cell: CellRef) -> Vec<CellRef> {
+        let sheet = self.sheet;
+        let is_formula = |cell: CellRef| sheet.cell(cell).is_some_and(|entry| entry.formula.is_some());
+        let Ok(expr) = self.parsed(cell) else { return Vec::new() };
+        let mut dependencies = Vec::new();
+        for reference in expr.cell_references() {
+            match reference {
+                CellReference::Cell { cell, .. } => dependencies.extend(Some(cell).filter(|&cell| is_formula(cell))),
+                CellReference::Range(range) => {
+                    dependencies.extend(sheet.cells_in(range).filter(|(_, entry)| entry.formula.is_some()).map(|(cell, _)| cell))
+                }
+            }
+        }
+        dependencies
+    }
+
+    /// 按依赖顺序计算公式单元格；用显式的栈代替递归，长的引用链不会耗尽调用栈
+    fn evaluate(&mut self, target: CellRef) {
+        let mut stack = vec![(target, false)];
+        // 栈中已展开的单元格，即当前的引用路径
+        let mut visiting = HashSet::new();
+        while let Some(&(cell, expanded)) = stack.last() {
+            if self.results.contains_key(&cell) {
+                stack.pop();
+                visiting.remove(&cell);
+                continue;
+            }
+            if !expanded {
+                visiting.insert(cell);
+                stack.last_mut().expect("栈不为空").1 = true;
+                for dependency in self.dependencies(cell) {
+                    if visiting.contains(&dependency) {
+                        self.results.insert(cell, Err(format!("循环引用: {} 引用了 {}", cell, dependency)));
+                        break;
+                    }
+                    if !self.results.contains_key(&dependency) {
+                        stack.push((dependency, false));
+                    }
+                }
+                continue;
+            }
+            stack.pop();
+            visiting.remove(&cell);
+            let result = self.compute(cell);
+            self.results.insert(cell, result);
+        }
+    }
+
+    /// 计算一个公式单元格，引用的公式单元格都已计算
+    fn compute(&mut self, cell: CellRef) -> Result<f64, String> {
+        let expr = self.parsed(cell).clone()?;
+        let mut variables = Variables::new();
+        for reference in expr.cell_references() {
+            match reference {
+                CellReference::Cell { name, cell } => {
+                    variables.insert(name.to_string(), self.value(cell, false)?.unwrap_or(0.0));
+                }
+                CellReference::Range(range) => {
+                    // 区域按合计传给求值，空单元格不参与
+                    let mut sum = 0.0;
+                    for (cell, _) in self.sheet.cells_in(range) {
+                        sum += self.value(cell, true)?.unwrap_or(0.0);
+                    }
+                    variables.insert(range.to_string(), sum);
+                }
+            }
+        }
+        let formula = translate(self.sheet.cell(cell).and_then(|entry| entry.formula.as_deref()).unwrap_or_default());
+        let value = evaluate_traced(&expr, &self.options, &variables, None).map_err(|e| e.render(&formula))?;
+        let percent = self.sheet.cell(cell).is_some_and(|entry| entry.percent);
+        Ok(if percent { self.options.round(value * 100.0) / 100.0 } else { self.options.round(value) })
+    }
+
+    /// 被引用的单元格的值，空单元格为 `None`；区域中的文本和逻辑值也为 `None`
+    fn value(&self, cell: CellRef, in_range: bool) -> Result<Option<f64>, String> {
+        let Some(entry) = self.sheet.cell(cell) else { return Ok(None) };
+        if self.is_formula(cell) {
+            return match self.results.get(&cell) {
+                Some(Ok(value)) => Ok(Some(*value)),
+                _ => Err(format!("引用的单元格 {} 无法重新计算", cell)),
+            };
+        }
+        match &entry.value {
+            CellValue::Empty => Ok(None),
+            CellValue::Number(value) => Ok(Some(self.constant(*value, entry.percent))),
+            CellValue::Text(_) | CellValue::Bool(_) if in_range => Ok(None),
+            CellValue::Text(text) => Err(format!("单元格 {} 不是数字: '{}'", cell, text)),
+            CellValue::Bool(value) => Err(format!("单元格 {} 不是数字: {}", cell, if *value { "TRUE" } else { "FALSE" })),
+            CellValue::Error(error) => Err(format!("单元格 {} 的值是错误 {}", cell, error)),
+        }
+    }
+
+    /// 常量单元格按数字字面量的规则预先舍入，百分比格式的单元格按百分数处理
+    fn constant(&self, value: f64, percent: bool) -> f64 {
+        let literal = NumberLiteral {
+            text: String::new(),
+            value: if percent { value * 100.0 } else { value },
+            percent,
+            label: None,
+            format: NumberFormat::EnUs,
+            span: Span::at(0),
+        };
+        literal_value(&literal, &self.options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use zip::write::SimpleFileOptions;
+    use crate::tools::calculator::PercentRounding;
+
+    const WORKBOOK: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships">
+<sheets><sheet name="利润表" sheetId="1" r:id="rId1"/></sheets></workbook>"#;
+
+    const RELATIONSHIPS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
+<Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet1.xml"/>
+</Relationships>"#;
+
+    const STRINGS: &str = r#"<sst><si><t>收入</t></si><si><r><t>成本</t></r><r><t xml:space="preserve"> &amp; 费用</t></r><rPh><t>ひ</t></rPh></si></sst>"#;
+
+    const STYLES: &str = r#"<styleSheet><numFmts count="1"><numFmt numFmtId="164" formatCode="0.0%"/></numFmts>
+<cellStyleXfs count="1"><xf numFmtId="9"/></cellStyleXfs>
+<cellXfs count="3"><xf numFmtId="0"/><xf numFmtId="10"/><xf numFmtId="164"/></cellXfs></styleSheet>"#;
+
+    /// 收入 1000.005 与 2000.004 之和 3000.009，按两位小数预先舍入后为 3000.01；
+    /// 成本行用共享公式，毛利率设置了百分比格式
+    const SHEET: &str = r#"<worksheet><sheetData>
+<row r="1"><c r="A1" t="s"><v>0</v></c><c r="B1"><v>1000.005</v></c><c r="C1"><v>2000.004</v></c><c r="D1"><f>SUM(B1:C1)</f><v>3000.009</v></c></row>
+<row r="2"><c r="A2" t="s"><v>1</v></c><c r="B2"><f t="shared" ref="B2:C2" si="0">B1*0.6</f><v>600.003</v></c><c r="C2"><f t="shared" si="0"/><v>1200.0024</v></c><c r="D2"><f>ROUND(B2+C2,1)</f><v>1800</v></c></row>
+<row r="3"><c r="D3" s="1"><f>(D1-D2)/D1</f><v>0.400001</v></c><c r="E3"><f>VLOOKUP(A1,A1:D2,2)</f><v>1000.005</v></c><c r="F3" t="str"><f>A1&amp;A2</f><v>收入成本</v></c></row>
+<row r="4"><c r="A4"><f>A1+1</f><v>0</v></c><c r="B4"><f>C4+1</f><v>0</v></c><c r="C4"><f>B4+1</f><v>0</v></c><c r="D4" t="inlineStr"><is><t>备注</t></is></c></row>
+</sheetData></worksheet>"#;
+
+    fn workbook(sheet: &str) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let files = [
+            ("xl/workbook.xml", WORKBOOK),
+            ("xl/_rels/workbook.xml.rels", RELATIONSHIPS),
+            ("xl/sharedStrings.xml", STRINGS),
+            ("xl/styles.xml", STYLES),
+            ("xl/worksheets/sheet1.xml", sheet),
+        ];
+        for (name, content) in files {
+            writer.start_file(name, SimpleFileOptions::default()).unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn cell(name: &str) -> CellRef {
+        CellRef::parse(name).unwrap()
+    }
+
+    #[test]
+    fn test_read_workbook() {
+        let sheets = read_workbook(Cursor::new(workbook(SHEET))).unwrap();
+        assert_eq!(sheets.len(), 1);
+        let sheet = &sheets[0];
+        assert_eq!(sheet.name, "利润表");
+        assert_eq!(sheet.cell(cell("A2")).unwrap().value, CellValue::Text("成本 & 费用".to_string()));
+        assert_eq!(sheet.cell(cell("C2")).unwrap().formula.as_deref(), Some("C1*0.6"));
+        assert_eq!(sheet.cell(cell("F3")).unwrap().formula.as_deref(), Some("A1&A2"));
+        assert_eq!(sheet.cell(cell("D4")).unwrap().value, CellValue::Text("备注".to_string()));
+        assert!(sheet.cell(cell("D3")).unwrap().percent);
+        assert!(!sheet.cell(cell("D2")).unwrap().percent);
+        assert_eq!(sheet.formulas().count(), 10);
+
+        assert!(read_workbook(Cursor::new(b"not a zip".to_vec())).unwrap_err().starts_with("不是有效的 .xlsx 文件"));
+    }
+
+    #[test]
+    fn test_entry_limit() {
+        let mut archive = ZipArchive::new(Cursor::new(workbook(SHEET))).unwrap();
+        let limit = SHEET.len() as u64 - 1;
+        assert_eq!(
+            read_entry_with_limit(&mut archive, "xl/worksheets/sheet1.xml", limit),
+            Err(format!("无法读取 xl/worksheets/sheet1.xml: 解压后超过 {} 字节的上限", limit))
+        );
+        assert!(read_entry_with_limit(&mut archive, "xl/worksheets/sheet1.xml", limit + 1).unwrap().is_some());
+        assert_eq!(read_entry_with_limit(&mut archive, "xl/missing.xml", limit), Ok(None));
+    }
+
+    #[test]
+    fn test_shift_formula() {
+        assert_eq!(shift_formula("B1*0.6", 1, 1), "C2*0.6");
+        assert_eq!(shift_formula("SUM($B1:B$1)+$C$3", 2, 1), "SUM($B3:C$1)+$C$3");
+        assert_eq!(shift_formula("LOG10(A1)&\"A1\"", 1, 0), "LOG10(A2)&\"A1\"");
+        assert_eq!(shift_formula("A1+1E5", 0, -1), "A1+1E5");
+        assert_eq!(translate("=ROUND(A1,2)&\"x,y\""), "ROUND(A1, 2)&\"x,y\"");
+    }
+
+    #[test]
+    fn test_recalculate() {
+        let sheets = read_workbook(Cursor::new(workbook(SHEET))).unwrap();
+        let options = CalcOptions::new(2, PercentRounding::RoundThenDivideBy100);
+        let results = recalculate(&sheets[0], &options);
+        let result = |name: &str| results.iter().find(|r| r.cell == cell(name)).unwrap();
+
+        // 预先舍入：1000.01 + 2000 = 3000.01，缓存的 3000.009 舍入后相同
+        assert_eq!(result("D1").result, Ok(3000.01));
+        assert!(result("D1").check(&options).unwrap().passed);
+        // 1000.01 * 0.6 = 600.006 → 600.01，缓存的 600.003 舍入后为 600
+        assert_eq!(result("B2").result, Ok(600.01));
+        let check = result("B2").check(&options).unwrap();
+        assert!(!check.passed);
+        assert_eq!(check.difference, 0.01);
+        assert_eq!(result("D2").result, Ok(1800.0));
+        // 百分比格式：(3000.01 - 1800) / 3000.01 = 40.0001% → 40%
+        assert_eq!(result("D3").result, Ok(0.4));
+        assert!(result("D3").check(&options).unwrap().passed);
+
+        assert!(result("E3").result.as_ref().unwrap_err().starts_with("不支持的公式: 未知的函数"));
+        assert!(result("F3").result.as_ref().unwrap_err().starts_with("不支持的公式: 无效字符"));
+        assert_eq!(result("F3").check(&options), None);
+        assert_eq!(result("A4").result, Err("单元格 A1 不是数字: '收入'".to_string()));
+        assert_eq!(result("B4").result, Err("引用的单元格 C4 无法重新计算".to_string()));
+        assert_eq!(result("C4").result, Err("循环引用: C4 引用了 B4".to_string()));
+    }
+
+    #[test]
+    fn test_recalculate_workbook() {
+        let options = CalcOptions::new(2, PercentRounding::RoundThenDivideBy100);
+        let (text, output) = recalculate_workbook(Cursor::new(workbook(SHEET)), Some("利润表"), &options).unwrap();
+        assert!(text.starts_with(&format!("重新计算完成：{} 个公式中 {} 个相符", output.formulas, output.matched)));
+        assert_eq!(output.differences.len(), 1);
+        assert_eq!(output.differences[0].cell, "B2");
+        assert!(!output.all_matched);
+        assert_eq!(
+            recalculate_workbook(Cursor::new(workbook(SHEET)), Some("附注"), &options).unwrap_err(),
+            "工作簿中没有名为 '附注' 的工作表，现有的工作表：利润表"
+        );
+    }
+
+    #[test]
+    fn test_range_values() {
+        let mut sheet = Sheet::new("Sheet1");
+        let constant = |value: CellValue| Cell { formula: None, value, percent: false };
+        sheet.insert(cell("A1"), constant(CellValue::Number(1.004)));
+        sheet.insert(cell("A2"), constant(CellValue::Text("n/a".to_string())));
+        sheet.insert(cell("A3"), constant(CellValue::Bool(true)));
+        sheet.insert(cell("B1"), Cell { formula: Some("SUM(A1:A4)*2".to_string()), value: CellValue::Empty, percent: false });
+        sheet.insert(cell("B2"), Cell { formula: Some("A5*2+A1".to_string()), value: CellValue::Number(1.0), percent: false });
+        sheet.insert(cell("B3"), Cell { formula: Some("B1/A5".to_string()), value: CellValue::Number(1.0), percent: false });
+        let options = CalcOptions::new(2, PercentRounding::RoundThenDivideBy100);
+        let results = recalculate(&sheet, &options);
+        assert_eq!(results[0].result, Ok(2.0));
+        assert_eq!(results[0].cached, None);
+        assert_eq!(results[1].result, Ok(1.0));
+        assert!(results[2].result.as_ref().unwrap_err().starts_with("除零错误"));
+        sheet.insert(cell("B1"), Cell { formula: Some("A3+1".to_string()), value: CellValue::Empty, percent: false });
+        assert_eq!(recalculate(&sheet, &options)[0].result, Err("单元格 A3 不是数字: TRUE".to_string()));
+    }
+
+    #[test]
+    fn test_whole_column_range() {
+        let mut sheet = Sheet::new("Sheet1");
+        let constant = |value: f64| Cell { formula: None, value: CellValue::Number(value), percent: false };
+        sheet.insert(cell("A1"), constant(1.0));
+        sheet.insert(cell("A1048576"), constant(2.0));
+        sheet.insert(cell("B2"), constant(100.0));
+        sheet.insert(cell("C1"), Cell { formula: Some("SUM(A1:A1048576)+C2".to_string()), value: CellValue::Empty, percent: false });
+        sheet.insert(cell("C2"), Cell { formula: Some("SUM(A2:B1048576)".to_string()), value: CellValue::Empty, percent: false });
+        let cells: Vec<_> = sheet.cells_in(CellRange::new(cell("A1"), cell("A1048576"))).map(|(cell, _)| cell.to_string()).collect();
+        assert_eq!(cells, ["A1", "A1048576"]);
+        let options = CalcOptions::new(2, PercentRounding::RoundThenDivideBy100);
+        let results = recalculate(&sheet, &options);
+        assert_eq!(results[0].result, Ok(105.0));
+        assert_eq!(results[1].result, Ok(102.0));
+    }
+}
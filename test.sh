@@ -163,22 +163,55 @@ echo "7. 测试批量验证功能..."
 
 # 测试基本批量验证
 echo "7.1 测试基本批量验证："
-npx @modelcontextprotocol/inspector --cli "$SERVER_CMD" --method tools/call --tool-name batch_validate --tool-arg expressions='["1 + 2|3", "2 * 3|6", "10 / 2|5"]' --tool-arg default_decimals=0
+npx @modelcontextprotocol/inspector --cli "$SERVER_CMD" --method tools/call --tool-name batch_validate --tool-arg expressions='["1 + 2|3", "2 * 3|6", "10 / 2|5"]' --tool-arg decimals=0
 echo
 
 # 测试带小数的批量验证
 echo "7.2 测试带小数的批量验证："
-npx @modelcontextprotocol/inspector --cli "$SERVER_CMD" --method tools/call --tool-name batch_validate --tool-arg expressions='["1.234 + 2.567|3.80|2", "50.126%|0.50|2"]' --tool-arg default_decimals=2
+npx @modelcontextprotocol/inspector --cli "$SERVER_CMD" --method tools/call --tool-name batch_validate --tool-arg expressions='["1.234 + 2.567|3.80|合计", "50.126%|0.50|比率"]' --tool-arg decimals=2
 echo
 
 # 测试混合格式的批量验证
 echo "7.3 测试混合格式的批量验证："
-npx @modelcontextprotocol/inspector --cli "$SERVER_CMD" --method tools/call --tool-name batch_validate --tool-arg expressions='["1,234.56 + 1.000,44|2235.00|2", "1,000,000.00 + 500,000.00|1500000|0"]' --tool-arg default_decimals=2
+npx @modelcontextprotocol/inspector --cli "$SERVER_CMD" --method tools/call --tool-name batch_validate --tool-arg expressions='["1,234.56 + 1.000,44|2235.00", "1,000,000.00 + 500,000.00|1500000|dp=0"]' --tool-arg decimals=2
+echo
+
+# 测试单行设置
+echo "7.3.1 测试单行设置（金额 2 位小数，利率 4 位小数）："
+npx @modelcontextprotocol/inspector --cli "$SERVER_CMD" --method tools/call --tool-name batch_validate --tool-arg expressions='["1,234.56 + 1,000.44|2,235.00|金额", "1,234.5678 * 12.3456%|152.4691|利息|decimals=4;percent=divide_by_100_then_round;tol=0.01"]'
+echo
+
+# 测试结构化的项
+echo "7.3.2 测试结构化的项："
+npx @modelcontextprotocol/inspector --cli "$SERVER_CMD" --method tools/call --tool-name batch_validate --tool-arg items='[{"id": "BS-12", "expression": "1,000.00 + 2,000.00", "expected": "3,000.00", "label": "流动资产|合计"}, {"expression": "0.12345", "expected": "0.1235", "decimals": 4}]'
+echo
+
+# 测试引用其他行
+echo "7.3.3 测试用 {标记} 引用前面的行："
+npx @modelcontextprotocol/inspector --cli "$SERVER_CMD" --method tools/call --tool-name batch_validate --tool-arg expressions='["1,000.00 + 2,000.00|3,000.00|流动资产合计", "5,000.00 + 2,000.00|7,000.00|非流动资产合计", "{流动资产合计} + {非流动资产合计}|10,000.00|资产总计"]'
+echo
+
+# 测试控制输出规模
+echo "7.3.4 测试只输出失败的行并在失败两行后停止："
+npx @modelcontextprotocol/inspector --cli "$SERVER_CMD" --method tools/call --tool-name batch_validate --tool-arg expressions='["1 + 1|2", "1 + 1|3", "2 + 2|4", "2 + 2|5", "3 + 3|7"]' --tool-arg max_failures=2 --tool-arg failures_only=true
+echo
+
+# 测试按重要性水平分类差异
+echo "7.3.5 测试按资产总计的 0.5% 对差异分类："
+npx @modelcontextprotocol/inspector --cli "$SERVER_CMD" --method tools/call --tool-name batch_validate --tool-arg expressions='["1000 + 2000|3000|流动资产", "5000 + 2000.02|7000|非流动资产", "33.333% * 300|100.00|比率", "{流动资产} + {非流动资产}|10200|资产总计"]' --tool-arg materiality="0.5% of {资产总计}"
+echo
+
+# 测试从文件读取
+echo "7.3.6 测试从允许的目录中读取文件："
+LEDGER_DIR=$(mktemp -d)
+printf '%s\n' "1,000.00 + 2,000.00|3,000.00|流动资产合计" "" '{"expression": "{流动资产合计} * 2", "expected": "6,000.00", "id": "r3"}' > "$LEDGER_DIR/ledger.txt"
+npx @modelcontextprotocol/inspector --cli "$SERVER_CMD" --allow-dir "$LEDGER_DIR" --method tools/call --tool-name batch_validate --tool-arg file="ledger.txt"
+rm -r "$LEDGER_DIR"
 echo
 
 # 测试包含错误的批量验证
 echo "7.4 测试包含错误的批量验证："
-npx @modelcontextprotocol/inspector --cli "$SERVER_CMD" --method tools/call --tool-name batch_validate --tool-arg expressions='["1 + 2|3", "2 * 3|7", "10 / 2|5"]' --tool-arg default_decimals=0
+npx @modelcontextprotocol/inspector --cli "$SERVER_CMD" --method tools/call --tool-name batch_validate --tool-arg expressions='["1 + 2|3", "2 * 3|7", "10 / 2|5"]' --tool-arg decimals=0
 echo
 
 # 测试预期值包含百分数
@@ -191,6 +224,111 @@ echo "7.6 测试预期值包含千分位："
 npx @modelcontextprotocol/inspector --cli "$SERVER_CMD" --method tools/call --tool-name validate --tool-arg expression="500 + 500" --tool-arg expected="1,000" --tool-arg decimals=0
 echo
 
+# 测试容差
+echo "7.7 测试绝对容差 (100.00 + 200.01 ≈ 300, ±0.01)："
+npx @modelcontextprotocol/inspector --cli "$SERVER_CMD" --method tools/call --tool-name validate --tool-arg expression="100.00 + 200.01" --tool-arg expected="300" --tool-arg decimals=2 --tool-arg tolerance="0.01"
+echo
+
+# 测试等式形式
+echo "7.8 测试等式 1,000.00 + 2,000.00 = 500.00 + 2,500.00："
+npx @modelcontextprotocol/inspector --cli "$SERVER_CMD" --method tools/call --tool-name validate --tool-arg expression="1,000.00 + 2,000.00 = 500.00 + 2,500.00" --tool-arg decimals=2
+echo
+
+# 测试断言
+echo "7.9 测试断言 (1200 - 900) / 1200 between 20% and 40%："
+npx @modelcontextprotocol/inspector --cli "$SERVER_CMD" --method tools/call --tool-name validate --tool-arg expression="(1200 - 900) / 1200 between 20% and 40%" --tool-arg decimals=2
+echo
+
+echo "8. 测试解析功能..."
+
+# 测试语法树输出
+echo "8.1 测试解析表达式 ((1,234.56 + 1.000,44) * 10%)："
+npx @modelcontextprotocol/inspector --cli "$SERVER_CMD" --method tools/call --tool-name parse --tool-arg expression="(1,234.56 + 1.000,44) * 10%"
+echo
+
+# 测试操作数标记与计算步骤
+echo "8.2 测试带标记的逐步计算："
+npx @modelcontextprotocol/inspector --cli "$SERVER_CMD" --method tools/call --tool-name calculate --tool-arg expression="1,000.00[货币资金] + 2,000.00[应收账款] + 500" --tool-arg decimals=2 --tool-arg show_steps=true
+echo
+
+echo "9. 测试脚本功能..."
+
+# 测试多步脚本
+echo "9.1 测试脚本 gross = 12,000.00 * 1.13; tax = gross * 13% / 1.13; gross - tax："
+npx @modelcontextprotocol/inspector --cli "$SERVER_CMD" --method tools/call --tool-name calculate_script --tool-arg script="gross = 12,000.00 * 1.13; tax = gross * 13% / 1.13; gross - tax" --tool-arg decimals=2
+echo
+
+echo "10. 测试舍入口径搜索..."
+
+echo "10.1 测试搜索 2 / 3 = 0.66 的口径："
+npx @modelcontextprotocol/inspector --cli "$SERVER_CMD" --method tools/call --tool-name find_rounding_convention --tool-arg expression="2 / 3" --tool-arg reported="0.66"
+echo
+
+echo "11. 测试报表层级验证..."
+
+echo "11.1 测试资产负债表的小计和会计恒等式："
+npx @modelcontextprotocol/inspector --cli "$SERVER_CMD" --method tools/call --tool-name validate_statement --tool-arg statement='[{"label": "资产", "amount": "10,000.00", "children": [{"label": "流动资产", "amount": "3,000.00", "children": [{"label": "货币资金", "amount": "1,000.00"}, {"label": "应收账款", "amount": "1,999.00"}]}, {"label": "非流动资产", "children": [{"label": "固定资产原值", "amount": "7,200.00"}, {"label": "减：累计折旧", "amount": "200.00", "subtract": true}]}]}, {"label": "负债", "amount": "4,000.00"}, {"label": "所有者权益", "amount": "6,000.00"}]'
+echo
+
+echo "12. 测试表格交叉核对..."
+
+echo "12.1 测试分部 × 期间表格的横向和纵向合计："
+npx @modelcontextprotocol/inspector --cli "$SERVER_CMD" --method tools/call --tool-name crossfoot_table --tool-arg columns='["2023", "2024", "合计"]' --tool-arg rows='[{"label": "华东", "cells": ["1,000.00", "1,500.00", "2,500.00"]}, {"label": "华南", "cells": ["800.00", "(100.00)", "699.00"]}, {"label": "合计", "cells": ["1,800.00", "1,400.00", "3,200.00"]}]'
+echo
+
+echo "13. 测试 CSV 报表验证..."
+
+echo "13.1 测试从允许的目录中读取 CSV 报表："
+CSV_DIR=$(mktemp -d)
+printf '%s\n' "项目,金额,公式" '货币资金,"1,000.00",' '应收账款,"2,000.00",' '流动资产合计,"3,001.00",{货币资金} + {应收账款}' > "$CSV_DIR/balance_sheet.csv"
+npx @modelcontextprotocol/inspector --cli "$SERVER_CMD" --allow-dir "$CSV_DIR" --method tools/call --tool-name validate_csv --tool-arg path="balance_sheet.csv"
+echo
+
+echo "13.2 测试命令行模式："
+# 存在失败的行时退出码为 1，不中止测试
+"$SERVER_CMD" --allow-dir "$CSV_DIR" --validate-csv "$CSV_DIR/balance_sheet.csv" --number-format en-US --decimals 2 || echo "退出码: $?"
+rm -r "$CSV_DIR"
+echo
+
+echo "14. 测试单元格引用..."
+
+echo "14.1 测试表格上的区域求和与单元格引用："
+npx @modelcontextprotocol/inspector --cli "$SERVER_CMD" --method tools/call --tool-name calculate_grid --tool-arg expressions='["SUM(B2:B4) - B5", "B2 / B5", "SUM(B2:B4) = B5", "B6 * 2"]' --tool-arg grid='[["项目", "2024"], ["货币资金", "1,000.00"], ["应收账款", "(200.00)"], ["存货", "300.005"], ["流动资产合计", "1,100.01"], ["备注", ""]]'
+echo
+
+echo "14.2 测试 CSV 文本形式的表格："
+npx @modelcontextprotocol/inspector --cli "$SERVER_CMD" --method tools/call --tool-name calculate_grid --tool-arg expressions='["$B$2 / B3 >= 10%"]' --tool-arg csv=$'项目;金额\n毛利;1.250,00\n收入;10.000,00'
+echo
+
+echo "15. 测试 Excel 工作簿重新计算..."
+
+echo "15.1 测试重新计算工作簿中的公式："
+if command -v python3 > /dev/null; then
+    XLSX_DIR=$(mktemp -d)
+    python3 - "$XLSX_DIR/report.xlsx" <<'PY'
+import sys, zipfile
+parts = {
+    "[Content_Types].xml": '<?xml version="1.0"?><Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"/>',
+    "xl/workbook.xml": '<workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><sheets><sheet name="利润表" sheetId="1" r:id="rId1"/></sheets></workbook>',
+    "xl/_rels/workbook.xml.rels": '<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet1.xml"/></Relationships>',
+    "xl/worksheets/sheet1.xml": '<worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><sheetData>'
+        '<row r="1"><c r="A1" t="inlineStr"><is><t>收入</t></is></c><c r="B1"><v>1000.005</v></c></row>'
+        '<row r="2"><c r="A2" t="inlineStr"><is><t>成本</t></is></c><c r="B2"><f>B1*0.6</f><v>600.003</v></c></row>'
+        '<row r="3"><c r="A3" t="inlineStr"><is><t>毛利</t></is></c><c r="B3"><f>ROUND(B1-B2,2)</f><v>400</v></c></row>'
+        '<row r="4"><c r="A4" t="inlineStr"><is><t>合计</t></is></c><c r="B4"><f>SUM(B1:B3)</f><v>2000.008</v></c></row>'
+        '</sheetData></worksheet>',
+}
+with zipfile.ZipFile(sys.argv[1], "w") as archive:
+    for name, data in parts.items():
+        archive.writestr(name, data)
+PY
+    npx @modelcontextprotocol/inspector --cli "$SERVER_CMD" --allow-dir "$XLSX_DIR" --method tools/call --tool-name recalculate_xlsx --tool-arg path="report.xlsx"
+    rm -r "$XLSX_DIR"
+else
+    echo "未找到 python3，跳过"
+fi
+echo
+
 echo "✓ 所有测试完成！"
 echo
 echo "测试总结："
@@ -203,5 +341,13 @@ echo "- 错误处理: ✓"
 echo "- 高级功能: ✓"
 echo "- 千分位分隔符: ✓"
 echo "- 批量验证: ✓"
+echo "- 表达式解析: ✓"
+echo "- 多步脚本: ✓"
+echo "- 舍入口径搜索: ✓"
+echo "- 报表层级验证: ✓"
+echo "- 表格交叉核对: ✓"
+echo "- CSV 报表验证: ✓"
+echo "- 单元格引用: ✓"
+echo "- Excel 工作簿重新计算: ✓"
 echo
 echo "算术表达式计算器 MCP 服务器已准备就绪！"
\ No newline at end of file